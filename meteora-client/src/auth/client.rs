@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use grpcio::{CallOption, EnvBuilder, MetadataBuilder};
+use log::*;
+use opentelemetry::Context;
+
+use meteora_proto::proto::auth::{
+    AuthenticateReq, ChangePasswordReq, Permission, PermissionType, RoleAddReq, RoleDeleteReq,
+    RoleGrantPermissionReq, RoleRevokePermissionReq, UserAddReq, UserDeleteReq, UserGrantRoleReq,
+    UserRevokeRoleReq,
+};
+use meteora_proto::proto::auth_grpc::AuthServiceClient;
+use meteora_proto::proto::common::{NodeAddress, State};
+pub use meteora_server::auth::server::prefix_range_end;
+use meteora_server::tls::{self, TlsConfig};
+use meteora_server::trace;
+
+use crate::config::ClientConfig;
+use crate::error::Error;
+use crate::raft::client::discover;
+
+pub fn create_auth_client(
+    address: String,
+    tls: Option<&TlsConfig>,
+) -> Result<AuthServiceClient, io::Error> {
+    let env = Arc::new(EnvBuilder::new().build());
+    let ch = tls::connect(env, &address, tls)?;
+    Ok(AuthServiceClient::new(ch))
+}
+
+// Attaches the token to the `authorization` metadata of the request, along with the
+// context of the current trace.
+pub fn call_option(token: Option<&str>) -> CallOption {
+    let mut builder = MetadataBuilder::with_capacity(2);
+    if let Some(token) = token {
+        builder
+            .add_str("authorization", &format!("Bearer {}", token))
+            .unwrap();
+    }
+    trace::inject(&Context::current(), &mut builder);
+    CallOption::default().headers(builder.build())
+}
+
+pub struct AuthClient {
+    leader_id: u64, // leader's node id
+    clients: HashMap<u64, Arc<AuthServiceClient>>,
+    addresses: HashMap<u64, String>,
+    tls: Option<TlsConfig>,
+    token: Option<String>,
+}
+
+impl AuthClient {
+    // Connects to the cluster through the first of the seeds that answers.
+    pub fn connect(seeds: &[&str], tls: Option<TlsConfig>) -> Result<AuthClient, Error> {
+        let (_, reply) = discover(seeds, tls.as_ref(), &ClientConfig::default())?;
+        let leader_id = reply.leader_id;
+        let addresses: HashMap<u64, String> = reply
+            .address_map
+            .iter()
+            .map(|(node_id, node_address)| (node_id.clone(), node_address.kv_address.clone()))
+            .collect();
+
+        let mut clients = HashMap::new();
+        for (i, a) in &addresses {
+            let c = create_auth_client(a.to_string(), tls.as_ref())?;
+            clients.insert(*i, Arc::new(c));
+        }
+
+        Ok(AuthClient {
+            leader_id,
+            clients,
+            addresses,
+            tls,
+            token: None,
+        })
+    }
+
+    pub fn set_token(&mut self, token: String) {
+        self.token = Some(token);
+    }
+
+    pub fn authenticate(&mut self, name: &str, password: &str) -> Result<String, Error> {
+        let mut req = AuthenticateReq::new();
+        req.set_name(name.to_string());
+        req.set_password(password.to_string());
+
+        let reply = self.call(
+            |client, opt| client.authenticate_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(reply.get_token().to_string())
+    }
+
+    pub fn user_add(&mut self, name: &str, password: &str) -> Result<(), Error> {
+        let mut req = UserAddReq::new();
+        req.set_name(name.to_string());
+        req.set_password(password.to_string());
+
+        self.call(
+            |client, opt| client.user_add_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn user_delete(&mut self, name: &str) -> Result<(), Error> {
+        let mut req = UserDeleteReq::new();
+        req.set_name(name.to_string());
+
+        self.call(
+            |client, opt| client.user_delete_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn change_password(&mut self, name: &str, password: &str) -> Result<(), Error> {
+        let mut req = ChangePasswordReq::new();
+        req.set_name(name.to_string());
+        req.set_password(password.to_string());
+
+        self.call(
+            |client, opt| client.change_password_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn user_grant_role(&mut self, name: &str, role: &str) -> Result<(), Error> {
+        let mut req = UserGrantRoleReq::new();
+        req.set_name(name.to_string());
+        req.set_role(role.to_string());
+
+        self.call(
+            |client, opt| client.user_grant_role_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn user_revoke_role(&mut self, name: &str, role: &str) -> Result<(), Error> {
+        let mut req = UserRevokeRoleReq::new();
+        req.set_name(name.to_string());
+        req.set_role(role.to_string());
+
+        self.call(
+            |client, opt| client.user_revoke_role_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn role_add(&mut self, name: &str) -> Result<(), Error> {
+        let mut req = RoleAddReq::new();
+        req.set_name(name.to_string());
+
+        self.call(
+            |client, opt| client.role_add_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn role_delete(&mut self, name: &str) -> Result<(), Error> {
+        let mut req = RoleDeleteReq::new();
+        req.set_name(name.to_string());
+
+        self.call(
+            |client, opt| client.role_delete_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn role_grant_permission(
+        &mut self,
+        name: &str,
+        perm_type: PermissionType,
+        key: Vec<u8>,
+        range_end: Vec<u8>,
+    ) -> Result<(), Error> {
+        let mut permission = Permission::new();
+        permission.set_perm_type(perm_type);
+        permission.set_key(key);
+        permission.set_range_end(range_end);
+
+        let mut req = RoleGrantPermissionReq::new();
+        req.set_name(name.to_string());
+        req.set_permission(permission);
+
+        self.call(
+            |client, opt| client.role_grant_permission_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn role_revoke_permission(
+        &mut self,
+        name: &str,
+        key: Vec<u8>,
+        range_end: Vec<u8>,
+    ) -> Result<(), Error> {
+        let mut req = RoleRevokePermissionReq::new();
+        req.set_name(name.to_string());
+        req.set_key(key);
+        req.set_range_end(range_end);
+
+        self.call(
+            |client, opt| client.role_revoke_permission_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    // Sends the request to the leader, and retries with a new leader on WRONG_LEADER.
+    fn call<R, F, P>(&mut self, f: F, parts: P) -> Result<R, Error>
+    where
+        F: Fn(&AuthServiceClient, CallOption) -> grpcio::Result<R>,
+        P: Fn(&R) -> (State, u64, HashMap<u64, NodeAddress>),
+    {
+        let max_retry = 10;
+        let mut cnt_retry = 0;
+
+        loop {
+            let client = match self.clients.get(&self.leader_id) {
+                Some(c) => c,
+                None => return Err(Error::UnknownNode(self.leader_id)),
+            };
+
+            let reply = f(client, call_option(self.token.as_deref()))?;
+
+            let (state, leader_id, address_map) = parts(&reply);
+            if !address_map.is_empty() {
+                self.update_addresses(&address_map);
+            }
+
+            match state {
+                State::OK => return Ok(reply),
+                State::WRONG_LEADER => {
+                    warn!(
+                        "upddate leader id: current={}, new={}",
+                        self.leader_id, leader_id
+                    );
+                    self.leader_id = leader_id;
+                    cnt_retry += 1;
+                    if max_retry < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
+                    warn!("retry with a new leader: id={}", self.leader_id);
+                }
+                // The leader changed before the write was applied. It may be applied anyway.
+                State::PROPOSAL_DROPPED => {
+                    cnt_retry += 1;
+                    if max_retry < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
+                    warn!("proposal was dropped, retry");
+                }
+                _ => return Err(Error::State(state)),
+            }
+        }
+    }
+
+    fn update_addresses(&mut self, address_map: &HashMap<u64, NodeAddress>) {
+        // update address list and clients
+        // add new ids
+        for (id, address) in address_map {
+            if let Some(grpc_address) = self.addresses.get(&id) {
+                if grpc_address == address.kv_address.as_str() {
+                    debug!(
+                        "node has not been changed: id={}, address={}",
+                        id, grpc_address
+                    );
+                    continue;
+                }
+            }
+            debug!("update node: id={}, address={}", id, address.kv_address);
+            // The node is left out until a later reply, if the client can't be set up.
+            match create_auth_client(address.kv_address.clone(), self.tls.as_ref()) {
+                Ok(client) => {
+                    self.addresses.insert(*id, address.kv_address.clone());
+                    self.clients.insert(*id, Arc::new(client));
+                }
+                Err(e) => warn!(
+                    "failed to connect to node: id={}, address={}, error={}",
+                    id, address.kv_address, e
+                ),
+            }
+        }
+
+        // remove unused ids
+        for (id, address) in &self.addresses.clone() {
+            if !address_map.contains_key(&id) {
+                debug!("node is not in use: id={}, address={}", id, address);
+                self.addresses.remove(id);
+                self.clients.remove(id);
+            }
+        }
+
+        debug!("addresses={:?}", self.addresses);
+    }
+}
@@ -0,0 +1,120 @@
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use grpcio::CallOption;
+use rand::Rng;
+
+use crate::auth::client::call_option;
+
+// How a client retries and how long it waits for the nodes.
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    // The number of times a request is sent again after the first attempt.
+    pub max_retries: u32,
+    // The delay before the first retry. It doubles with every retry up to max_backoff.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    // The deadline of each request sent to a node. None means no deadline.
+    pub rpc_timeout: Option<Duration>,
+    // How long to wait for the first node to be reachable when the client is created. None
+    // means the client fails at once if the node is down.
+    pub connect_timeout: Option<Duration>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            rpc_timeout: None,
+            connect_timeout: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    // The delay before the retry of the given number, starting from 1. A random delay between
+    // half and all of the exponential one is taken, so that clients don't retry in lockstep.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exp = self
+            .initial_backoff
+            .checked_mul(1 << retry.saturating_sub(1).min(31))
+            .unwrap_or(self.max_backoff);
+        let millis = exp.min(self.max_backoff).as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2..=millis))
+    }
+
+    pub(crate) fn call_option(&self, token: Option<&str>) -> CallOption {
+        let opt = call_option(token);
+        match self.rpc_timeout {
+            Some(timeout) => opt.timeout(timeout),
+            None => opt,
+        }
+    }
+
+    // The options of the first request, which waits for the node to be up.
+    pub(crate) fn connect_option(&self) -> CallOption {
+        match self.connect_timeout {
+            Some(timeout) => call_option(None).wait_for_ready(true).timeout(timeout),
+            None => self.call_option(None),
+        }
+    }
+}
+
+// Waits for the backoff of an asynchronous client without blocking the executor. The clients
+// run on any executor, so there is no timer to use, and the wait is done on a thread instead.
+pub(crate) async fn sleep(duration: Duration) {
+    if duration == Duration::from_millis(0) {
+        return;
+    }
+    let (sender, receiver) = oneshot::channel();
+    thread::spawn(move || {
+        thread::sleep(duration);
+        let _ = sender.send(());
+    });
+    let _ = receiver.await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ClientConfig {
+        ClientConfig {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            ..ClientConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let config = config();
+        for (retry, millis) in &[(1, 100), (2, 200), (3, 400), (4, 800)] {
+            let backoff = config.backoff(*retry);
+            assert!(backoff >= Duration::from_millis(millis / 2));
+            assert!(backoff <= Duration::from_millis(*millis));
+        }
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let config = config();
+        for retry in &[5, 32, 64, u32::MAX] {
+            let backoff = config.backoff(*retry);
+            assert!(backoff >= Duration::from_millis(500));
+            assert!(backoff <= config.max_backoff);
+        }
+    }
+
+    #[test]
+    fn test_no_backoff() {
+        let config = ClientConfig {
+            initial_backoff: Duration::from_millis(0),
+            ..ClientConfig::default()
+        };
+        assert_eq!(config.backoff(3), Duration::from_millis(0));
+    }
+}
@@ -0,0 +1,146 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use grpcio::RpcStatusCode;
+
+use meteora_proto::proto::common::State;
+
+// An error of a request sent by a client.
+#[derive(Debug)]
+pub enum Error {
+    // The server answered with a state other than OK.
+    State(State),
+    // The request failed in gRPC, for example because it timed out or the node is down.
+    Grpc(grpcio::Error),
+    // The client has no connection to the node, for example because no leader is known.
+    UnknownNode(u64),
+    // Every attempt was answered with a state to retry on. The state of the last one is kept.
+    RetriesExhausted { attempts: u32, state: State },
+    // The client was given no address to connect to.
+    NoSeeds,
+    // The client could not be set up, for example because a TLS file could not be read.
+    Io(io::Error),
+}
+
+impl Error {
+    // The state that the server answered with, if any.
+    pub fn state(&self) -> Option<State> {
+        match self {
+            Error::State(state) | Error::RetriesExhausted { state, .. } => Some(*state),
+            _ => None,
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::State(State::NOT_FOUND))
+    }
+
+    pub fn is_permission_denied(&self) -> bool {
+        matches!(
+            self,
+            Error::State(State::UNAUTHENTICATED) | Error::State(State::PERMISSION_DENIED)
+        )
+    }
+
+    pub fn is_invalid_argument(&self) -> bool {
+        matches!(self, Error::State(State::INVALID_ARGUMENT))
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Error::Grpc(grpcio::Error::RpcFailure(status)) => {
+                status.status == RpcStatusCode::DeadlineExceeded
+            }
+            _ => false,
+        }
+    }
+
+    // Whether the request may succeed if it is sent again later. A write that failed this way
+    // may have been applied anyway, for example if it timed out after being proposed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::State(state) | Error::RetriesExhausted { state, .. } => matches!(
+                state,
+                State::WRONG_LEADER | State::PROPOSAL_DROPPED | State::IO_ERROR | State::UNKNOWN
+            ),
+            Error::Grpc(grpcio::Error::RpcFailure(status)) => matches!(
+                status.status,
+                RpcStatusCode::Unavailable
+                    | RpcStatusCode::DeadlineExceeded
+                    | RpcStatusCode::ResourceExhausted
+                    | RpcStatusCode::Aborted
+            ),
+            Error::Grpc(_) | Error::NoSeeds | Error::Io(_) => false,
+            Error::UnknownNode(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::State(State::NOT_FOUND) => write!(f, "not found"),
+            Error::State(State::UNAUTHENTICATED) => write!(f, "authentication failed"),
+            Error::State(State::PERMISSION_DENIED) => write!(f, "permission denied"),
+            Error::State(State::INVALID_ARGUMENT) => write!(f, "invalid argument"),
+            Error::State(State::ALREADY_EXISTS) => write!(f, "already exists"),
+            Error::State(State::PROPOSAL_DROPPED) => write!(f, "proposal was dropped"),
+            Error::State(state) => write!(f, "request failed: state={:?}", state),
+            Error::Grpc(e) => write!(f, "failed to send request: {}", e),
+            Error::UnknownNode(id) => write!(f, "failed to get client for node: id={}", id),
+            Error::RetriesExhausted { attempts, state } => write!(
+                f,
+                "max retry count has been exceeded: attempts={}, state={:?}",
+                attempts, state
+            ),
+            Error::NoSeeds => write!(f, "no address to connect to"),
+            Error::Io(e) => write!(f, "failed to set up client: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Grpc(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<grpcio::Error> for Error {
+    fn from(e: grpcio::Error) -> Error {
+        Error::Grpc(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+// For callers that report errors as `std::io::Error`. The error is kept as the inner error.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        if let Error::Io(e) = e {
+            return e;
+        }
+        let kind = if e.is_not_found() {
+            io::ErrorKind::NotFound
+        } else if e.is_permission_denied() {
+            io::ErrorKind::PermissionDenied
+        } else if e.is_invalid_argument() {
+            io::ErrorKind::InvalidInput
+        } else if e.is_timeout() {
+            io::ErrorKind::TimedOut
+        } else if e.state() == Some(State::ALREADY_EXISTS) {
+            io::ErrorKind::AlreadyExists
+        } else {
+            io::ErrorKind::Other
+        };
+        io::Error::new(kind, e)
+    }
+}
@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use futures::compat::Future01CompatExt;
+use futures::lock::Mutex;
+use grpcio::{CallOption, ClientUnaryReceiver, EnvBuilder, Environment};
+use log::*;
+use rand::Rng;
+
+use meteora_proto::proto::common::{NodeAddress, State};
+use meteora_proto::proto::kv::{
+    BatchOp, BatchReq, DeleteReq, GetReq, IncrReq, PutCondition, PutReq, ScanReq,
+};
+use meteora_proto::proto::kv_grpc::KvServiceClient;
+use meteora_server::tls::{self, TlsConfig};
+
+use crate::config::{self, ClientConfig};
+use crate::error::Error;
+use crate::kv::client::{Item, PutOptions};
+use crate::raft::client::{discover, pick_node};
+
+// The nodes of the cluster, shared by the clones of a client.
+struct Cluster {
+    leader_id: u64,
+    clients: HashMap<u64, Arc<KvServiceClient>>,
+    addresses: HashMap<u64, String>,
+}
+
+// An asynchronous counterpart of `KVClient`. It can be cloned and shared across tasks, and
+// each call is a future that doesn't block the thread, so requests can be sent concurrently.
+// The futures run on any executor, since gRPC completes them on its own threads.
+pub struct AsyncKvClient {
+    env: Arc<Environment>,
+    cluster: Arc<RwLock<Cluster>>,
+    // Reads are spread over the nodes in turn.
+    next_index: Arc<AtomicUsize>,
+    tls: Option<TlsConfig>,
+    token: Option<String>,
+    config: ClientConfig,
+    // A random id, and the number of the last write. The nodes apply a write only once even if
+    // it is retried, but they require the writes of a client to be sent one at a time, so the
+    // writes wait for each other.
+    client_id: u64,
+    request_seq: AtomicU64,
+    write_lock: Mutex<()>,
+}
+
+// Each clone is a client of its own for the nodes, so that clones write concurrently.
+impl Clone for AsyncKvClient {
+    fn clone(&self) -> AsyncKvClient {
+        AsyncKvClient {
+            env: self.env.clone(),
+            cluster: self.cluster.clone(),
+            next_index: self.next_index.clone(),
+            tls: self.tls.clone(),
+            token: self.token.clone(),
+            config: self.config.clone(),
+            client_id: new_client_id(),
+            request_seq: AtomicU64::new(0),
+            write_lock: Mutex::new(()),
+        }
+    }
+}
+
+impl AsyncKvClient {
+    // Connects to the cluster through the first of the seeds, the Raft addresses of some of
+    // the nodes, that answers. The seeds are asked in the same way as by `KVClient`, which
+    // blocks the thread until one of them answers.
+    pub async fn connect(seeds: &[&str], tls: Option<TlsConfig>) -> Result<AsyncKvClient, Error> {
+        AsyncKvClient::connect_with_config(seeds, tls, ClientConfig::default()).await
+    }
+
+    pub async fn connect_with_config(
+        seeds: &[&str],
+        tls: Option<TlsConfig>,
+        config: ClientConfig,
+    ) -> Result<AsyncKvClient, Error> {
+        let (_, reply) = discover(seeds, tls.as_ref(), &config)?;
+        let env = Arc::new(EnvBuilder::new().build());
+
+        let client = AsyncKvClient {
+            env,
+            cluster: Arc::new(RwLock::new(Cluster {
+                leader_id: reply.get_leader_id(),
+                clients: HashMap::new(),
+                addresses: HashMap::new(),
+            })),
+            next_index: Arc::new(AtomicUsize::new(0)),
+            tls,
+            token: None,
+            config,
+            client_id: new_client_id(),
+            request_seq: AtomicU64::new(0),
+            write_lock: Mutex::new(()),
+        };
+        client.update_addresses(reply.get_address_map());
+        Ok(client)
+    }
+
+    fn next_request_seq(&self) -> u64 {
+        self.request_seq.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    // The token applies to this client only, not to its clones made before.
+    pub fn set_token(&mut self, token: String) {
+        self.token = Some(token);
+    }
+
+    pub fn clear_token(&mut self) {
+        self.token = None;
+    }
+
+    pub async fn get(&self, key: Vec<u8>) -> Result<Vec<u8>, Error> {
+        self.get_item(key).await.map(|item| item.value)
+    }
+
+    pub async fn get_item(&self, key: Vec<u8>) -> Result<Item, Error> {
+        let mut req = GetReq::new();
+        req.set_key(key);
+
+        let reply = self
+            .read(
+                |client, opt| client.get_async_opt(&req, opt),
+                |reply| (reply.get_state(), reply.get_address_map().clone()),
+            )
+            .await?;
+        Ok(Item {
+            value: reply.get_value().to_vec(),
+            flags: reply.get_flags(),
+            version: reply.get_version(),
+        })
+    }
+
+    pub async fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Error> {
+        self.put_with(key, value, &PutOptions::default())
+            .await
+            .map(|_| ())
+    }
+
+    // Puts the value if the condition holds, and returns whether it was put. If ttl is not 0,
+    // the value expires after ttl milliseconds.
+    pub async fn put_if(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: u64,
+        condition: PutCondition,
+    ) -> Result<bool, Error> {
+        let options = PutOptions {
+            ttl,
+            condition,
+            ..Default::default()
+        };
+        self.put_with(key, value, &options).await
+    }
+
+    // Puts the value if the condition of the options holds, and returns whether it was put.
+    pub async fn put_with(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        options: &PutOptions,
+    ) -> Result<bool, Error> {
+        let mut req = PutReq::new();
+        req.set_key(key);
+        req.set_value(value);
+        req.set_flags(options.flags);
+        req.set_ttl(options.ttl);
+        req.set_keep_ttl(options.keep_ttl);
+        req.set_condition(options.condition);
+        req.set_version(options.version);
+        let _session = self.write_lock.lock().await;
+        req.set_client_id(self.client_id);
+        req.set_request_seq(self.next_request_seq());
+
+        let reply = self
+            .write(
+                |client, opt| client.put_async_opt(&req, opt),
+                |reply| {
+                    (
+                        reply.get_state(),
+                        reply.get_leader_id(),
+                        reply.get_address_map().clone(),
+                    )
+                },
+            )
+            .await?;
+        Ok(reply.get_applied())
+    }
+
+    // Deletes the key, and returns whether it existed.
+    pub async fn delete(&self, key: Vec<u8>) -> Result<bool, Error> {
+        let mut req = DeleteReq::new();
+        req.set_key(key);
+        let _session = self.write_lock.lock().await;
+        req.set_client_id(self.client_id);
+        req.set_request_seq(self.next_request_seq());
+
+        let reply = self
+            .write(
+                |client, opt| client.delete_async_opt(&req, opt),
+                |reply| {
+                    (
+                        reply.get_state(),
+                        reply.get_leader_id(),
+                        reply.get_address_map().clone(),
+                    )
+                },
+            )
+            .await?;
+        Ok(reply.get_deleted())
+    }
+
+    // Returns the key-value pairs in [key, range_end) in key order. If range_end is empty or
+    // "\0", all keys greater than or equal to the key are returned. A limit of 0 means no limit.
+    pub async fn scan(
+        &self,
+        key: Vec<u8>,
+        range_end: Vec<u8>,
+        limit: u64,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
+        let mut req = ScanReq::new();
+        req.set_key(key);
+        req.set_range_end(range_end);
+        req.set_limit(limit);
+
+        let reply = self
+            .read(
+                |client, opt| client.scan_async_opt(&req, opt),
+                |reply| (reply.get_state(), reply.get_address_map().clone()),
+            )
+            .await?;
+        Ok(reply
+            .get_kvs()
+            .iter()
+            .map(|kv| (kv.get_key().to_vec(), kv.get_value().to_vec()))
+            .collect())
+    }
+
+    // Adds delta to the integer value of the key, and returns the new value.
+    pub async fn incr(&self, key: Vec<u8>, delta: i64) -> Result<i64, Error> {
+        let mut req = IncrReq::new();
+        req.set_key(key);
+        req.set_delta(delta);
+        let _session = self.write_lock.lock().await;
+        req.set_client_id(self.client_id);
+        req.set_request_seq(self.next_request_seq());
+
+        let reply = self
+            .write(
+                |client, opt| client.incr_async_opt(&req, opt),
+                |reply| {
+                    (
+                        reply.get_state(),
+                        reply.get_leader_id(),
+                        reply.get_address_map().clone(),
+                    )
+                },
+            )
+            .await?;
+        Ok(reply.get_value())
+    }
+
+    // Applies the operations together in order, and returns whether each of them was applied.
+    pub async fn batch(&self, ops: Vec<BatchOp>) -> Result<Vec<bool>, Error> {
+        let mut req = BatchReq::new();
+        req.set_ops(ops.into());
+        let _session = self.write_lock.lock().await;
+        req.set_client_id(self.client_id);
+        req.set_request_seq(self.next_request_seq());
+
+        let reply = self
+            .write(
+                |client, opt| client.batch_async_opt(&req, opt),
+                |reply| {
+                    (
+                        reply.get_state(),
+                        reply.get_leader_id(),
+                        reply.get_address_map().clone(),
+                    )
+                },
+            )
+            .await?;
+        Ok(reply.get_applied().to_vec())
+    }
+
+    // Sends a read request to the next node, and retries on the next one if it fails. Reads
+    // are also retried when the node can't be reached, since they change nothing.
+    async fn read<R, F, P>(&self, f: F, parts: P) -> Result<R, Error>
+    where
+        F: Fn(&KvServiceClient, CallOption) -> grpcio::Result<ClientUnaryReceiver<R>>,
+        P: Fn(&R) -> (State, HashMap<u64, NodeAddress>),
+    {
+        let mut cnt_retry = 0;
+
+        loop {
+            let client = {
+                let cluster = self.cluster.read().unwrap();
+                match self.next_node(&cluster) {
+                    Some(id) => cluster.clients[&id].clone(),
+                    None => return Err(Error::UnknownNode(cluster.leader_id)),
+                }
+            };
+
+            let opt = self.config.call_option(self.token.as_deref());
+            let reply = match send(f(&client, opt)).await {
+                Ok(reply) => reply,
+                Err(e) => {
+                    let e = Error::from(e);
+                    cnt_retry += 1;
+                    if !e.is_retryable() || self.config.max_retries < cnt_retry {
+                        return Err(e);
+                    }
+                    warn!("failed to read: error={}", e);
+                    config::sleep(self.config.backoff(cnt_retry)).await;
+                    continue;
+                }
+            };
+
+            let (state, address_map) = parts(&reply);
+            if !address_map.is_empty() {
+                self.update_addresses(&address_map);
+            }
+
+            match state {
+                State::OK => return Ok(reply),
+                State::NOT_FOUND | State::UNAUTHENTICATED | State::PERMISSION_DENIED => {
+                    return Err(Error::State(state))
+                }
+                _ => {
+                    cnt_retry += 1;
+                    warn!("failed to read: state={:?}", state);
+                    if self.config.max_retries < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
+                    config::sleep(self.config.backoff(cnt_retry)).await;
+                }
+            }
+        }
+    }
+
+    // Sends a write request to the leader, following it if it has changed.
+    async fn write<R, F, P>(&self, f: F, parts: P) -> Result<R, Error>
+    where
+        F: Fn(&KvServiceClient, CallOption) -> grpcio::Result<ClientUnaryReceiver<R>>,
+        P: Fn(&R) -> (State, u64, HashMap<u64, NodeAddress>),
+    {
+        let mut cnt_retry = 0;
+
+        loop {
+            let (current_leader_id, client) = {
+                let cluster = self.cluster.read().unwrap();
+                match pick_node(&cluster.clients, cluster.leader_id) {
+                    Some(id) => (id, cluster.clients[&id].clone()),
+                    None => return Err(Error::UnknownNode(cluster.leader_id)),
+                }
+            };
+
+            // The next node is tried in case the leader is down, and it tells the leader.
+            let opt = self.config.call_option(self.token.as_deref());
+            let reply = match send(f(&client, opt)).await {
+                Ok(reply) => reply,
+                Err(e) => {
+                    let e = Error::from(e);
+                    {
+                        let mut cluster = self.cluster.write().unwrap();
+                        if cluster.leader_id == current_leader_id {
+                            if let Some(id) = self.next_node(&cluster) {
+                                cluster.leader_id = id;
+                            }
+                        }
+                    }
+                    cnt_retry += 1;
+                    if !e.is_retryable() || self.config.max_retries < cnt_retry {
+                        return Err(e);
+                    }
+                    warn!("failed to write: error={}", e);
+                    config::sleep(self.config.backoff(cnt_retry)).await;
+                    continue;
+                }
+            };
+
+            let (state, leader_id, address_map) = parts(&reply);
+            if !address_map.is_empty() {
+                self.update_addresses(&address_map);
+            }
+
+            match state {
+                State::OK => return Ok(reply),
+                State::WRONG_LEADER => {
+                    warn!(
+                        "upddate leader id: current={}, new={}",
+                        current_leader_id, leader_id
+                    );
+                    self.cluster.write().unwrap().leader_id = leader_id;
+                    cnt_retry += 1;
+                    if self.config.max_retries < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
+                    warn!("retry with a new leader: id={}", leader_id);
+                    config::sleep(self.config.backoff(cnt_retry)).await;
+                }
+                // The leader changed before the write was applied. It may be applied anyway.
+                State::PROPOSAL_DROPPED => {
+                    cnt_retry += 1;
+                    if self.config.max_retries < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
+                    warn!("proposal was dropped, retry");
+                    config::sleep(self.config.backoff(cnt_retry)).await;
+                }
+                _ => return Err(Error::State(state)),
+            }
+        }
+    }
+
+    // The next node to send a request to. The nodes are taken in turn.
+    fn next_node(&self, cluster: &Cluster) -> Option<u64> {
+        let mut ids: Vec<u64> = cluster.clients.keys().copied().collect();
+        if ids.is_empty() {
+            return None;
+        }
+        ids.sort_unstable();
+        let index = self.next_index.fetch_add(1, Ordering::Relaxed) % ids.len();
+        Some(ids[index])
+    }
+
+    fn update_addresses(&self, address_map: &HashMap<u64, NodeAddress>) {
+        let mut cluster = self.cluster.write().unwrap();
+        for (id, address) in address_map {
+            if cluster.addresses.get(id) == Some(&address.kv_address) {
+                continue;
+            }
+            debug!("update node: id={}, address={}", id, address.kv_address);
+            let ch = match tls::connect(self.env.clone(), &address.kv_address, self.tls.as_ref()) {
+                Ok(ch) => ch,
+                Err(e) => {
+                    warn!(
+                        "failed to connect to node: id={}, address={}, error={}",
+                        id, address.kv_address, e
+                    );
+                    continue;
+                }
+            };
+            cluster
+                .clients
+                .insert(*id, Arc::new(KvServiceClient::new(ch)));
+            cluster.addresses.insert(*id, address.kv_address.clone());
+        }
+        cluster
+            .addresses
+            .retain(|id, _| address_map.contains_key(id));
+        cluster.clients.retain(|id, _| address_map.contains_key(id));
+        debug!("addresses={:?}", cluster.addresses);
+    }
+}
+
+fn new_client_id() -> u64 {
+    rand::thread_rng().gen_range(1..u64::MAX)
+}
+
+// Waits for the reply of a request that has been sent, if it could be.
+async fn send<R>(receiver: grpcio::Result<ClientUnaryReceiver<R>>) -> grpcio::Result<R> {
+    receiver?.compat().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::join;
+
+    use crate::testing::{client_config, start_node};
+
+    #[test]
+    fn test_connect_without_seeds() {
+        match block_on(AsyncKvClient::connect(&[], None)) {
+            Err(Error::NoSeeds) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_clone_has_own_session() {
+        let node = start_node();
+        block_on(async {
+            let client =
+                AsyncKvClient::connect_with_config(&[&node.address], None, client_config())
+                    .await
+                    .unwrap();
+            client.next_request_seq();
+            let clone = client.clone();
+            assert_ne!(client.client_id, clone.client_id);
+            assert_eq!(clone.next_request_seq(), 1);
+            assert_eq!(client.next_request_seq(), 2);
+        });
+    }
+
+    #[test]
+    fn test_put_get_delete() {
+        let node = start_node();
+        block_on(async {
+            let client =
+                AsyncKvClient::connect_with_config(&[&node.address], None, client_config())
+                    .await
+                    .unwrap();
+            client.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+            assert_eq!(client.get(b"a".to_vec()).await.unwrap(), b"1".to_vec());
+            assert!(client.delete(b"a".to_vec()).await.unwrap());
+            assert!(!client.delete(b"a".to_vec()).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_concurrent_writes() {
+        let node = start_node();
+        block_on(async {
+            let client =
+                AsyncKvClient::connect_with_config(&[&node.address], None, client_config())
+                    .await
+                    .unwrap();
+            let clone = client.clone();
+            // The writes of the same client are sent one at a time, and none of them is
+            // refused for being older than the one before.
+            let (a, b, c) = join!(
+                client.incr(b"n".to_vec(), 1),
+                client.incr(b"n".to_vec(), 1),
+                clone.incr(b"n".to_vec(), 1)
+            );
+            a.unwrap();
+            b.unwrap();
+            c.unwrap();
+            assert_eq!(client.incr(b"n".to_vec(), 0).await.unwrap(), 3);
+        });
+    }
+
+    #[test]
+    fn test_batch_and_scan() {
+        let node = start_node();
+        block_on(async {
+            let client =
+                AsyncKvClient::connect_with_config(&[&node.address], None, client_config())
+                    .await
+                    .unwrap();
+            let mut ops = Vec::new();
+            for key in &["k1", "k2", "k3"] {
+                let mut put = PutReq::new();
+                put.set_key(key.as_bytes().to_vec());
+                put.set_value(b"v".to_vec());
+                let mut op = BatchOp::new();
+                op.set_put(put);
+                ops.push(op);
+            }
+            assert_eq!(client.batch(ops).await.unwrap(), vec![true, true, true]);
+
+            let kvs = client
+                .scan(b"k1".to_vec(), b"k3".to_vec(), 0)
+                .await
+                .unwrap();
+            assert_eq!(
+                kvs,
+                vec![
+                    (b"k1".to_vec(), b"v".to_vec()),
+                    (b"k2".to_vec(), b"v".to_vec())
+                ]
+            );
+            let kvs = client.scan(b"k".to_vec(), Vec::new(), 1).await.unwrap();
+            assert_eq!(kvs, vec![(b"k1".to_vec(), b"v".to_vec())]);
+        });
+    }
+}
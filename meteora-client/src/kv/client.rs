@@ -1,21 +1,53 @@
 use std::collections::HashMap;
-use std::io::{Error, ErrorKind};
+use std::io;
 use std::sync::Arc;
+use std::thread;
 
-use grpcio::{ChannelBuilder, EnvBuilder};
+use grpcio::{CallOption, EnvBuilder};
 use log::*;
-
-use meteora_proto::proto::common::{Null, State};
-use meteora_proto::proto::kv::{DeleteReq, GetReq, PutReq};
+use opentelemetry::trace::{TraceContextExt, Tracer};
+use opentelemetry::Context;
+use rand::Rng;
+
+use meteora_proto::proto::common::{NodeAddress, State};
+use meteora_proto::proto::kv::{
+    BatchOp, BatchReq, DeleteReq, GetReq, IncrReq, PutCondition, PutReq, ScanReq,
+};
 use meteora_proto::proto::kv_grpc::KvServiceClient;
+use meteora_server::tls::{self, TlsConfig};
+use meteora_server::trace;
 
-use crate::raft::client::create_raft_client;
+use crate::config::ClientConfig;
+use crate::error::Error;
+use crate::raft::client::{discover, pick_node};
 
-pub fn create_kv_client(address: String) -> KvServiceClient {
+pub fn create_kv_client(
+    address: String,
+    tls: Option<&TlsConfig>,
+) -> Result<KvServiceClient, io::Error> {
     let env = Arc::new(EnvBuilder::new().build());
-    let ch = ChannelBuilder::new(env).connect(&address);
-    let client = KvServiceClient::new(ch);
-    client
+    let ch = tls::connect(env, &address, tls)?;
+    Ok(KvServiceClient::new(ch))
+}
+
+// A value along with its metadata.
+pub struct Item {
+    pub value: Vec<u8>,
+    pub flags: u32,
+    // The revision at which the key was last modified.
+    pub version: u64,
+}
+
+// The options of a put. The version is the expected one for the IF_VERSION condition.
+#[derive(Default)]
+pub struct PutOptions {
+    pub flags: u32,
+    // Milliseconds. 0 means the value never expires.
+    pub ttl: u64,
+    // Keep the expiration time of the current value instead of using ttl.
+    pub keep_ttl: bool,
+    pub condition: PutCondition,
+    pub version: u64,
 }
 
 pub struct KVClient {
@@ -24,355 +56,449 @@ pub struct KVClient {
     addresses: HashMap<u64, String>,
     next_index: usize,
     node_id: u64, // node id
+    tls: Option<TlsConfig>,
+    token: Option<String>,
+    config: ClientConfig,
+    // A random id, and the number of the last write. The nodes apply a write only once even if
+    // it is retried.
+    client_id: u64,
+    request_seq: u64,
 }
 
 impl KVClient {
-    pub fn new(raft_address: &str) -> KVClient {
-        let raft_client = create_raft_client(raft_address.to_string());
+    pub fn with_config(
+        raft_address: &str,
+        tls: Option<TlsConfig>,
+        config: ClientConfig,
+    ) -> Result<KVClient, Error> {
+        KVClient::connect_with_config(&[raft_address], tls, config)
+    }
+
+    // Connects to the cluster through the first of the seeds, the Raft addresses of some of
+    // the nodes, that answers. It fails only if none of them does.
+    pub fn connect(seeds: &[&str], tls: Option<TlsConfig>) -> Result<KVClient, Error> {
+        KVClient::connect_with_config(seeds, tls, ClientConfig::default())
+    }
 
-        let req = Null::new();
-        let reply = raft_client.status(&req).unwrap();
+    pub fn connect_with_config(
+        seeds: &[&str],
+        tls: Option<TlsConfig>,
+        config: ClientConfig,
+    ) -> Result<KVClient, Error> {
+        let (seed, reply) = discover(seeds, tls.as_ref(), &config)?;
         let leader_id = reply.leader_id;
         let addresses: HashMap<u64, String> = reply
             .address_map
             .iter()
             .map(|(node_id, node_address)| (node_id.clone(), node_address.kv_address.clone()))
             .collect();
+        // Reads start from the node that answered. It may not be in the address map if the seed
+        // is, for example, behind a load balancer.
         let node_id = reply
             .address_map
             .iter()
             .find_map(|(node_id, node_address)| {
-                if &node_address.raft_address == raft_address {
+                if node_address.raft_address == seed {
                     Some(node_id.clone())
                 } else {
                     None
                 }
             })
-            .unwrap();
-
-        let kv_address = addresses.get(&node_id).unwrap();
-        let kv_client = create_kv_client(kv_address.clone());
+            .unwrap_or_else(|| *addresses.keys().min().unwrap());
 
         let mut clients = HashMap::new();
-        clients.insert(node_id, Arc::new(kv_client));
         for (i, a) in &addresses {
-            if node_id != *i {
-                let c = create_kv_client(a.to_string());
-                clients.insert(*i, Arc::new(c));
-            }
+            let c = create_kv_client(a.to_string(), tls.as_ref())?;
+            clients.insert(*i, Arc::new(c));
         }
 
-        KVClient {
+        Ok(KVClient {
             leader_id,
             clients,
             addresses,
             next_index: 0,
             node_id,
-        }
+            tls,
+            token: None,
+            config,
+            client_id: rand::thread_rng().gen_range(1..u64::MAX),
+            request_seq: 0,
+        })
     }
 
-    pub fn get(&mut self, key: Vec<u8>) -> Result<Vec<u8>, std::io::Error> {
-        let mut req = GetReq::new();
-        req.set_key(key);
+    fn next_request_seq(&mut self) -> u64 {
+        self.request_seq += 1;
+        self.request_seq
+    }
 
-        let max_retry = 10;
-        let mut cnt_retry = 0;
+    pub fn set_token(&mut self, token: String) {
+        self.token = Some(token);
+    }
 
-        loop {
-            if max_retry < cnt_retry {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("max retry count has been exceeded: max_retry={}", max_retry),
-                ));
-            }
+    pub fn clear_token(&mut self) {
+        self.token = None;
+    }
 
-            let client = match self.clients.get(&self.node_id) {
-                Some(c) => c,
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to get client for node: id={}", self.node_id),
-                    ));
-                }
-            };
+    pub fn get(&mut self, key: Vec<u8>) -> Result<Vec<u8>, Error> {
+        self.get_item(key).map(|item| item.value)
+    }
 
-            let reply = match client.get(&req) {
-                Ok(r) => r,
-                Err(e) => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to get value: {:?}", e),
-                    ));
-                }
-            };
+    pub fn get_item(&mut self, key: Vec<u8>) -> Result<Item, Error> {
+        // The span stays current until this returns, so its context is sent with every attempt.
+        let _guard = Context::current_with_span(trace::tracer().start("KVClient::get")).attach();
 
-            // update address list and clients
-            // add new ids
-            for (id, address) in reply.get_address_map() {
-                if let Some(grpc_address) = self.addresses.get(&id) {
-                    if grpc_address == address.kv_address.as_str() {
-                        debug!(
-                            "node has not been changed: id={}, address={}",
-                            id, grpc_address
-                        );
-                    } else {
-                        debug!("update node: id={}, address={}", id, address.kv_address);
-                        self.addresses
-                            .insert(id.clone(), address.kv_address.clone());
-                        self.clients.insert(
-                            id.clone(),
-                            Arc::new(create_kv_client(address.kv_address.clone())),
-                        );
-                    }
-                } else {
-                    debug!("add node: id={}, address={}", id, address.kv_address);
-                    self.addresses
-                        .insert(id.clone(), address.kv_address.clone());
-                    self.clients.insert(
-                        id.clone(),
-                        Arc::new(create_kv_client(address.kv_address.clone())),
-                    );
-                }
-            }
+        let mut req = GetReq::new();
+        req.set_key(key);
 
-            // remove unused ids
-            for (id, address) in &self.addresses.clone() {
-                if reply.get_address_map().contains_key(&id) {
-                    debug!("node is in use: id={}, address={}", id, address);
-                } else {
-                    debug!("node is not in use: id={}, address={}", id, address);
-                    self.addresses.remove(id);
-                    self.clients.remove(id);
-                }
-            }
+        let reply = self.read(
+            |client, opt| client.get_opt(&req, opt),
+            |reply| (reply.get_state(), reply.get_address_map().clone()),
+        )?;
+        Ok(Item {
+            value: reply.get_value().to_vec(),
+            flags: reply.get_flags(),
+            version: reply.get_version(),
+        })
+    }
 
-            debug!("addresses={:?}", self.addresses);
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Error> {
+        let _guard = Context::current_with_span(trace::tracer().start("KVClient::put")).attach();
 
-            // change node id
-            let keys: Vec<u64> = self.addresses.keys().map(|i| i.clone()).collect();
-            self.next_index = (self.next_index + 1) % self.addresses.len();
-            self.node_id = keys.get(self.next_index).unwrap().clone();
+        let mut req = PutReq::new();
+        req.set_key(key);
+        req.set_value(value);
+        req.set_client_id(self.client_id);
+        req.set_request_seq(self.next_request_seq());
+
+        self.write(
+            |client, opt| client.put_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(())
+    }
 
-            match reply.get_state() {
-                State::OK => {
-                    return Ok(reply.get_value().to_vec());
-                }
-                State::NOT_FOUND => {
-                    return Err(Error::new(
-                        ErrorKind::NotFound,
-                        format!("not found: key={:?}", req.get_key()),
-                    ));
-                }
-                _ => {
-                    cnt_retry += 1;
-                    warn!("failed to get value: key={:?}", req.get_key());
-                }
-            }
-        }
+    // Deletes the key, and returns whether it existed.
+    pub fn delete(&mut self, key: Vec<u8>) -> Result<bool, Error> {
+        let _guard = Context::current_with_span(trace::tracer().start("KVClient::delete")).attach();
+
+        let mut req = DeleteReq::new();
+        req.set_key(key);
+        req.set_client_id(self.client_id);
+        req.set_request_seq(self.next_request_seq());
+
+        let reply = self.write(
+            |client, opt| client.delete_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(reply.get_deleted())
+    }
+
+    // Returns the key-value pairs in [key, range_end) in key order. If range_end is empty or
+    // "\0", all keys greater than or equal to the key are returned. A limit of 0 means no limit.
+    pub fn scan(
+        &mut self,
+        key: Vec<u8>,
+        range_end: Vec<u8>,
+        limit: u64,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
+        let _guard = Context::current_with_span(trace::tracer().start("KVClient::scan")).attach();
+
+        let mut req = ScanReq::new();
+        req.set_key(key);
+        req.set_range_end(range_end);
+        req.set_limit(limit);
+
+        let reply = self.read(
+            |client, opt| client.scan_opt(&req, opt),
+            |reply| (reply.get_state(), reply.get_address_map().clone()),
+        )?;
+        Ok(reply
+            .get_kvs()
+            .iter()
+            .map(|kv| (kv.get_key().to_vec(), kv.get_value().to_vec()))
+            .collect())
+    }
+
+    // Puts the value if the condition holds, and returns whether it was put. If ttl is not 0,
+    // the value expires after ttl milliseconds.
+    pub fn put_if(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: u64,
+        condition: PutCondition,
+    ) -> Result<bool, Error> {
+        let options = PutOptions {
+            ttl,
+            condition,
+            ..Default::default()
+        };
+        self.put_with(key, value, &options)
     }
 
-    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), std::io::Error> {
+    // Puts the value if the condition of the options holds, and returns whether it was put.
+    pub fn put_with(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        options: &PutOptions,
+    ) -> Result<bool, Error> {
+        let _guard = Context::current_with_span(trace::tracer().start("KVClient::put_if")).attach();
+
         let mut req = PutReq::new();
         req.set_key(key);
         req.set_value(value);
+        req.set_flags(options.flags);
+        req.set_ttl(options.ttl);
+        req.set_keep_ttl(options.keep_ttl);
+        req.set_condition(options.condition);
+        req.set_version(options.version);
+        req.set_client_id(self.client_id);
+        req.set_request_seq(self.next_request_seq());
+
+        let reply = self.write(
+            |client, opt| client.put_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(reply.get_applied())
+    }
+
+    // Adds delta to the integer value of the key, and returns the new value.
+    pub fn incr(&mut self, key: Vec<u8>, delta: i64) -> Result<i64, Error> {
+        let _guard = Context::current_with_span(trace::tracer().start("KVClient::incr")).attach();
+
+        let mut req = IncrReq::new();
+        req.set_key(key);
+        req.set_delta(delta);
+        req.set_client_id(self.client_id);
+        req.set_request_seq(self.next_request_seq());
+
+        let reply = self.write(
+            |client, opt| client.incr_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(reply.get_value())
+    }
+
+    // Applies the operations together in order, and returns whether each of them was applied.
+    pub fn batch(&mut self, ops: Vec<BatchOp>) -> Result<Vec<bool>, Error> {
+        let _guard = Context::current_with_span(trace::tracer().start("KVClient::batch")).attach();
+
+        let mut req = BatchReq::new();
+        req.set_ops(ops.into());
+        req.set_client_id(self.client_id);
+        req.set_request_seq(self.next_request_seq());
+
+        let reply = self.write(
+            |client, opt| client.batch_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )?;
+        Ok(reply.get_applied().to_vec())
+    }
 
-        let max_retry = 10;
+    // Sends a read request to the next node, and retries on the next one if it fails. Reads
+    // are also retried when the node can't be reached, since they change nothing.
+    fn read<R, F, P>(&mut self, f: F, parts: P) -> Result<R, Error>
+    where
+        F: Fn(&KvServiceClient, CallOption) -> grpcio::Result<R>,
+        P: Fn(&R) -> (State, HashMap<u64, NodeAddress>),
+    {
         let mut cnt_retry = 0;
 
         loop {
-            if max_retry < cnt_retry {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("max retry count has been exceeded: max_retry={}", max_retry),
-                ));
-            }
-
-            let client = match self.clients.get(&self.leader_id) {
-                Some(c) => c,
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to get client for node: id={}", self.leader_id),
-                    ));
-                }
+            let client = match pick_node(&self.clients, self.node_id) {
+                Some(id) => &self.clients[&id],
+                None => return Err(Error::UnknownNode(self.node_id)),
             };
 
-            let reply = match client.put(&req) {
-                Ok(r) => r,
+            let reply = match f(client, self.config.call_option(self.token.as_deref())) {
+                Ok(reply) => reply,
                 Err(e) => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to set value: {:?}", e),
-                    ));
-                }
-            };
-
-            // update address list and clients
-            // add new ids
-            for (id, address) in reply.get_address_map() {
-                if let Some(grpc_address) = self.addresses.get(&id) {
-                    if grpc_address == address.kv_address.as_str() {
-                        debug!(
-                            "node has not been changed: id={}, address={}",
-                            id, grpc_address
-                        );
-                    } else {
-                        debug!("update node: id={}, address={}", id, address.kv_address);
-                        self.addresses
-                            .insert(id.clone(), address.kv_address.clone());
-                        self.clients.insert(
-                            id.clone(),
-                            Arc::new(create_kv_client(address.kv_address.clone())),
-                        );
+                    let e = Error::from(e);
+                    self.next_node();
+                    cnt_retry += 1;
+                    if !e.is_retryable() || self.config.max_retries < cnt_retry {
+                        return Err(e);
                     }
-                } else {
-                    debug!("add node: id={}, address={}", id, address.kv_address);
-                    self.addresses
-                        .insert(id.clone(), address.kv_address.clone());
-                    self.clients.insert(
-                        id.clone(),
-                        Arc::new(create_kv_client(address.kv_address.clone())),
-                    );
+                    warn!("failed to read: error={}", e);
+                    thread::sleep(self.config.backoff(cnt_retry));
+                    continue;
                 }
-            }
+            };
 
-            // remove unused ids
-            for (id, address) in &self.addresses.clone() {
-                if reply.get_address_map().contains_key(&id) {
-                    debug!("node is in use: id={}, address={}", id, address);
-                } else {
-                    debug!("node is not in use: id={}, address={}", id, address);
-                    self.addresses.remove(id);
-                    self.clients.remove(id);
-                }
+            let (state, address_map) = parts(&reply);
+            if !address_map.is_empty() {
+                self.update_addresses(&address_map);
             }
+            self.next_node();
 
-            debug!("addresses={:?}", self.addresses);
-
-            match reply.get_state() {
-                State::OK => {
-                    return Ok(());
-                }
-                State::WRONG_LEADER => {
-                    warn!(
-                        "upddate leader id: current={}, new={}",
-                        self.leader_id,
-                        reply.get_leader_id()
-                    );
-                    self.leader_id = reply.get_leader_id();
-                    cnt_retry += 1;
-                    warn!("retry with a new leader: id={}", self.leader_id);
-                    continue;
+            match state {
+                State::OK => return Ok(reply),
+                State::NOT_FOUND | State::UNAUTHENTICATED | State::PERMISSION_DENIED => {
+                    return Err(Error::State(state))
                 }
                 _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to set value: key={:?}", req.get_key()),
-                    ));
+                    cnt_retry += 1;
+                    warn!("failed to read: state={:?}", state);
+                    if self.config.max_retries < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
+                    thread::sleep(self.config.backoff(cnt_retry));
                 }
-            };
+            }
         }
     }
 
-    pub fn delete(&mut self, key: Vec<u8>) -> Result<(), std::io::Error> {
-        let mut req = DeleteReq::new();
-        req.set_key(key.to_vec());
-
-        let max_retry = 10;
+    // Sends a write request to the leader, following it if it has changed.
+    fn write<R, F, P>(&mut self, f: F, parts: P) -> Result<R, Error>
+    where
+        F: Fn(&KvServiceClient, CallOption) -> grpcio::Result<R>,
+        P: Fn(&R) -> (State, u64, HashMap<u64, NodeAddress>),
+    {
         let mut cnt_retry = 0;
 
         loop {
-            if max_retry < cnt_retry {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("max retry count has been exceeded: max_retry={}", max_retry),
-                ));
-            }
-
-            let client = match self.clients.get(&self.leader_id) {
-                Some(c) => c,
-                None => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to get client for node: id={}", self.leader_id),
-                    ));
-                }
+            let client = match pick_node(&self.clients, self.leader_id) {
+                Some(id) => &self.clients[&id],
+                None => return Err(Error::UnknownNode(self.leader_id)),
             };
 
-            let reply = match client.delete(&req) {
-                Ok(r) => r,
+            // The nodes apply a write only once, so one that may have failed in transit is sent
+            // again. The next node is tried in case the leader is down, and it tells the leader.
+            let reply = match f(client, self.config.call_option(self.token.as_deref())) {
+                Ok(reply) => reply,
                 Err(e) => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to delete value: {:?}", e),
-                    ));
-                }
-            };
-
-            // update address list and clients
-            // add new ids
-            for (id, address) in reply.get_address_map() {
-                if let Some(grpc_address) = self.addresses.get(&id) {
-                    if grpc_address == address.kv_address.as_str() {
-                        debug!(
-                            "node has not been changed: id={}, address={}",
-                            id, grpc_address
-                        );
-                    } else {
-                        debug!("update node: id={}, address={}", id, address.kv_address);
-                        self.addresses
-                            .insert(id.clone(), address.kv_address.clone());
-                        self.clients.insert(
-                            id.clone(),
-                            Arc::new(create_kv_client(address.kv_address.clone())),
-                        );
+                    let e = Error::from(e);
+                    self.next_node();
+                    self.leader_id = self.node_id;
+                    cnt_retry += 1;
+                    if !e.is_retryable() || self.config.max_retries < cnt_retry {
+                        return Err(e);
                     }
-                } else {
-                    debug!("add node: id={}, address={}", id, address.kv_address);
-                    self.addresses
-                        .insert(id.clone(), address.kv_address.clone());
-                    self.clients.insert(
-                        id.clone(),
-                        Arc::new(create_kv_client(address.kv_address.clone())),
-                    );
+                    warn!("failed to write: error={}", e);
+                    thread::sleep(self.config.backoff(cnt_retry));
+                    continue;
                 }
-            }
+            };
 
-            // remove unused ids
-            for (id, address) in &self.addresses.clone() {
-                if reply.get_address_map().contains_key(&id) {
-                    debug!("node is in use: id={}, address={}", id, address);
-                } else {
-                    debug!("node is not in use: id={}, address={}", id, address);
-                    self.addresses.remove(id);
-                    self.clients.remove(id);
-                }
+            let (state, leader_id, address_map) = parts(&reply);
+            if !address_map.is_empty() {
+                self.update_addresses(&address_map);
             }
 
-            debug!("addresses={:?}", self.addresses);
-
-            match reply.get_state() {
-                State::OK => {
-                    return Ok(());
-                }
+            match state {
+                State::OK => return Ok(reply),
                 State::WRONG_LEADER => {
                     warn!(
                         "upddate leader id: current={}, new={}",
-                        self.leader_id,
-                        reply.get_leader_id()
+                        self.leader_id, leader_id
                     );
-                    self.leader_id = reply.get_leader_id();
+                    self.leader_id = leader_id;
                     cnt_retry += 1;
+                    if self.config.max_retries < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
                     warn!("retry with a new leader: id={}", self.leader_id);
+                    thread::sleep(self.config.backoff(cnt_retry));
+                }
+                // The leader changed before the write was applied. It may be applied anyway.
+                State::PROPOSAL_DROPPED => {
+                    cnt_retry += 1;
+                    if self.config.max_retries < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
+                    warn!("proposal was dropped, retry");
+                    thread::sleep(self.config.backoff(cnt_retry));
+                }
+                _ => return Err(Error::State(state)),
+            }
+        }
+    }
+
+    // Moves reads on to the next node.
+    fn next_node(&mut self) {
+        let keys: Vec<u64> = self.addresses.keys().copied().collect();
+        if !keys.is_empty() {
+            self.next_index = (self.next_index + 1) % keys.len();
+            self.node_id = keys[self.next_index];
+        }
+    }
+
+    fn update_addresses(&mut self, address_map: &HashMap<u64, NodeAddress>) {
+        // update address list and clients
+        // add new ids
+        for (id, address) in address_map {
+            if let Some(grpc_address) = self.addresses.get(&id) {
+                if grpc_address == address.kv_address.as_str() {
+                    debug!(
+                        "node has not been changed: id={}, address={}",
+                        id, grpc_address
+                    );
                     continue;
                 }
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to delete value: key={:?}", req.get_key()),
-                    ));
+            }
+            debug!("update node: id={}, address={}", id, address.kv_address);
+            // The node is left out until a later reply, if the client can't be set up.
+            match create_kv_client(address.kv_address.clone(), self.tls.as_ref()) {
+                Ok(client) => {
+                    self.addresses.insert(*id, address.kv_address.clone());
+                    self.clients.insert(*id, Arc::new(client));
                 }
-            };
+                Err(e) => warn!(
+                    "failed to connect to node: id={}, address={}, error={}",
+                    id, address.kv_address, e
+                ),
+            }
         }
+
+        // remove unused ids
+        for (id, address) in &self.addresses.clone() {
+            if !address_map.contains_key(&id) {
+                debug!("node is not in use: id={}, address={}", id, address);
+                self.addresses.remove(id);
+                self.clients.remove(id);
+            }
+        }
+
+        debug!("addresses={:?}", self.addresses);
     }
 }
@@ -1,2 +1,11 @@
+pub mod auth;
+pub mod config;
+pub mod error;
 pub mod kv;
 pub mod raft;
+
+pub use crate::config::ClientConfig;
+pub use crate::error::Error;
+
+#[cfg(test)]
+mod testing;
@@ -1 +1,2 @@
+pub mod async_client;
 pub mod client;
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use bincode::serialize;
+use futures::compat::Future01CompatExt;
+use grpcio::{CallOption, ClientUnaryReceiver, EnvBuilder, Environment};
+use log::*;
+use raft::eraftpb::{ConfChange, ConfChangeType};
+
+use meteora_proto::proto::common::{NodeAddress, Null, State};
+use meteora_proto::proto::raft_grpc::RaftServiceClient;
+use meteora_server::tls::{self, TlsConfig};
+
+use crate::config::{self, ClientConfig};
+use crate::error::Error;
+use crate::raft::client::{discover, pick_node};
+
+// The nodes of the cluster, shared by the clones of a client.
+struct Cluster {
+    leader_id: u64,
+    clients: HashMap<u64, Arc<RaftServiceClient>>,
+    addresses: HashMap<u64, String>,
+}
+
+// An asynchronous counterpart of `RaftClient`, which can be cloned and shared across tasks.
+#[derive(Clone)]
+pub struct AsyncRaftClient {
+    env: Arc<Environment>,
+    cluster: Arc<RwLock<Cluster>>,
+    tls: Option<TlsConfig>,
+    config: ClientConfig,
+}
+
+impl AsyncRaftClient {
+    // Connects to the cluster through the first of the seeds that answers, which blocks the
+    // thread in the same way as `RaftClient`.
+    pub async fn connect(seeds: &[&str], tls: Option<TlsConfig>) -> Result<AsyncRaftClient, Error> {
+        AsyncRaftClient::connect_with_config(seeds, tls, ClientConfig::default()).await
+    }
+
+    pub async fn connect_with_config(
+        seeds: &[&str],
+        tls: Option<TlsConfig>,
+        config: ClientConfig,
+    ) -> Result<AsyncRaftClient, Error> {
+        let (_, reply) = discover(seeds, tls.as_ref(), &config)?;
+        let env = Arc::new(EnvBuilder::new().build());
+
+        let client = AsyncRaftClient {
+            env,
+            cluster: Arc::new(RwLock::new(Cluster {
+                leader_id: reply.get_leader_id(),
+                clients: HashMap::new(),
+                addresses: HashMap::new(),
+            })),
+            tls,
+            config,
+        };
+        client.update_addresses(reply.get_address_map());
+        Ok(client)
+    }
+
+    pub async fn join(
+        &self,
+        node_id: u64,
+        node_address: NodeAddress,
+    ) -> Result<HashMap<u64, NodeAddress>, Error> {
+        let mut req = ConfChange::new();
+        req.set_node_id(node_id);
+        req.set_change_type(ConfChangeType::AddNode);
+        req.set_context(serialize(&node_address).unwrap());
+
+        let address_map = self
+            .call(
+                |client, opt| client.change_config_async_opt(&req, opt),
+                |reply| {
+                    (
+                        reply.get_state(),
+                        reply.get_leader_id(),
+                        reply.get_address_map().clone(),
+                    )
+                },
+            )
+            .await?;
+        Ok(address_map)
+    }
+
+    pub async fn leave(&self, id: u64) -> Result<HashMap<u64, NodeAddress>, Error> {
+        let mut req = ConfChange::new();
+        req.set_node_id(id);
+        req.set_change_type(ConfChangeType::RemoveNode);
+        req.set_context(vec![]);
+
+        let address_map = self
+            .call(
+                |client, opt| client.change_config_async_opt(&req, opt),
+                |reply| {
+                    (
+                        reply.get_state(),
+                        reply.get_leader_id(),
+                        reply.get_address_map().clone(),
+                    )
+                },
+            )
+            .await?;
+        Ok(address_map)
+    }
+
+    pub async fn status(&self) -> Result<HashMap<u64, NodeAddress>, Error> {
+        let req = Null::new();
+
+        let address_map = self
+            .call(
+                |client, opt| client.status_async_opt(&req, opt),
+                |reply| {
+                    (
+                        reply.get_state(),
+                        reply.get_leader_id(),
+                        reply.get_address_map().clone(),
+                    )
+                },
+            )
+            .await?;
+        Ok(address_map)
+    }
+
+    // Sends a request to the leader, following it if it has changed. Returns the addresses of
+    // the nodes in the reply.
+    async fn call<R, F, P>(&self, f: F, parts: P) -> Result<HashMap<u64, NodeAddress>, Error>
+    where
+        F: Fn(&RaftServiceClient, CallOption) -> grpcio::Result<ClientUnaryReceiver<R>>,
+        P: Fn(&R) -> (State, u64, HashMap<u64, NodeAddress>),
+    {
+        let mut cnt_retry = 0;
+
+        loop {
+            let (current_leader_id, client) = {
+                let cluster = self.cluster.read().unwrap();
+                match pick_node(&cluster.clients, cluster.leader_id) {
+                    Some(id) => (id, cluster.clients[&id].clone()),
+                    None => return Err(Error::UnknownNode(cluster.leader_id)),
+                }
+            };
+
+            // The leader is forgotten in case it is down, so that a random node is tried next,
+            // and it tells the leader. No node has the ID 0.
+            let reply = match send(f(&client, self.config.call_option(None))).await {
+                Ok(reply) => reply,
+                Err(e) => {
+                    let e = Error::from(e);
+                    {
+                        let mut cluster = self.cluster.write().unwrap();
+                        if cluster.leader_id == current_leader_id {
+                            cluster.leader_id = 0;
+                        }
+                    }
+                    cnt_retry += 1;
+                    if !e.is_retryable() || self.config.max_retries < cnt_retry {
+                        return Err(e);
+                    }
+                    warn!("failed to send request: error={}", e);
+                    config::sleep(self.config.backoff(cnt_retry)).await;
+                    continue;
+                }
+            };
+
+            let (state, leader_id, address_map) = parts(&reply);
+            if !address_map.is_empty() {
+                self.update_addresses(&address_map);
+            }
+
+            match state {
+                State::OK => return Ok(address_map),
+                State::WRONG_LEADER => {
+                    warn!(
+                        "upddate leader id: current={}, new={}",
+                        current_leader_id, leader_id
+                    );
+                    self.cluster.write().unwrap().leader_id = leader_id;
+                    cnt_retry += 1;
+                    if self.config.max_retries < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
+                    warn!("retry with a new leader: id={}", leader_id);
+                    config::sleep(self.config.backoff(cnt_retry)).await;
+                }
+                // The leader changed before the write was applied. It may be applied anyway.
+                State::PROPOSAL_DROPPED => {
+                    cnt_retry += 1;
+                    if self.config.max_retries < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
+                    warn!("proposal was dropped, retry");
+                    config::sleep(self.config.backoff(cnt_retry)).await;
+                }
+                _ => return Err(Error::State(state)),
+            }
+        }
+    }
+
+    fn update_addresses(&self, address_map: &HashMap<u64, NodeAddress>) {
+        let mut cluster = self.cluster.write().unwrap();
+        for (id, address) in address_map {
+            if cluster.addresses.get(id) == Some(&address.raft_address) {
+                continue;
+            }
+            debug!("update node: id={}, address={}", id, address.raft_address);
+            let ch = match tls::connect(self.env.clone(), &address.raft_address, self.tls.as_ref())
+            {
+                Ok(ch) => ch,
+                Err(e) => {
+                    warn!(
+                        "failed to connect to node: id={}, address={}, error={}",
+                        id, address.raft_address, e
+                    );
+                    continue;
+                }
+            };
+            cluster
+                .clients
+                .insert(*id, Arc::new(RaftServiceClient::new(ch)));
+            cluster.addresses.insert(*id, address.raft_address.clone());
+        }
+        cluster
+            .addresses
+            .retain(|id, _| address_map.contains_key(id));
+        cluster.clients.retain(|id, _| address_map.contains_key(id));
+        debug!("addresses={:?}", cluster.addresses);
+    }
+}
+
+// Waits for the reply of a request that has been sent, if it could be.
+async fn send<R>(receiver: grpcio::Result<ClientUnaryReceiver<R>>) -> grpcio::Result<R> {
+    receiver?.compat().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    use crate::testing::{client_config, start_node};
+
+    #[test]
+    fn test_connect_without_seeds() {
+        match block_on(AsyncRaftClient::connect(&[], None)) {
+            Err(Error::NoSeeds) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_status() {
+        let node = start_node();
+        block_on(async {
+            let client =
+                AsyncRaftClient::connect_with_config(&[&node.address], None, client_config())
+                    .await
+                    .unwrap();
+            let addresses = client.status().await.unwrap();
+            assert_eq!(addresses.len(), 1);
+            assert_eq!(addresses[&1].get_kv_address(), node.address);
+            assert_eq!(addresses[&1].get_raft_address(), node.address);
+        });
+    }
+}
@@ -1,387 +1,315 @@
 use std::collections::HashMap;
-use std::io::{Error, ErrorKind};
+use std::io;
 use std::sync::Arc;
+use std::thread;
 
 use bincode::serialize;
-use grpcio::{ChannelBuilder, EnvBuilder};
+use grpcio::{CallOption, EnvBuilder};
 use log::*;
 use raft::eraftpb::{ConfChange, ConfChangeType};
+use rand::seq::IteratorRandom;
 
 use meteora_proto::proto::common::{NodeAddress, Null, State};
+use meteora_proto::proto::raft::StatusReply;
 use meteora_proto::proto::raft_grpc::RaftServiceClient;
+use meteora_server::tls::{self, TlsConfig};
 
-pub fn create_raft_client(address: String) -> RaftServiceClient {
+use crate::config::ClientConfig;
+use crate::error::Error;
+
+pub fn create_raft_client(
+    address: String,
+    tls: Option<&TlsConfig>,
+) -> Result<RaftServiceClient, io::Error> {
     let env = Arc::new(EnvBuilder::new().build());
-    let ch = ChannelBuilder::new(env).connect(&address);
-    let client = RaftServiceClient::new(ch);
-    client
+    let ch = tls::connect(env, &address, tls)?;
+    Ok(RaftServiceClient::new(ch))
+}
+
+// Asks the seeds in turn for the status of the cluster, and returns the address of the first
+// one that answers along with its reply.
+pub(crate) fn discover<'a>(
+    seeds: &[&'a str],
+    tls: Option<&TlsConfig>,
+    config: &ClientConfig,
+) -> Result<(&'a str, StatusReply), Error> {
+    let mut last_error = Error::NoSeeds;
+    for seed in seeds {
+        let client = create_raft_client(seed.to_string(), tls)?;
+        match client.status_opt(&Null::new(), config.connect_option()) {
+            Ok(reply) if reply.get_address_map().is_empty() => {
+                warn!("seed knows no nodes: address={}", seed);
+                last_error = Error::UnknownNode(reply.get_leader_id());
+            }
+            Ok(reply) => return Ok((seed, reply)),
+            Err(e) => {
+                warn!("failed to connect to seed: address={}, error={}", seed, e);
+                last_error = e.into();
+            }
+        }
+    }
+    Err(last_error)
+}
+
+// Returns the node if the client has a connection to it, or else a random node that it has one
+// to, as when the leader is not known during an election. The node tells the leader once it
+// knows it.
+pub(crate) fn pick_node<C>(clients: &HashMap<u64, C>, node_id: u64) -> Option<u64> {
+    if clients.contains_key(&node_id) {
+        Some(node_id)
+    } else {
+        clients.keys().copied().choose(&mut rand::thread_rng())
+    }
 }
 
 pub struct RaftClient {
     leader_id: u64, // leader's index in server_ids
     clients: HashMap<u64, Arc<RaftServiceClient>>,
     addresses: HashMap<u64, String>,
+    tls: Option<TlsConfig>,
+    config: ClientConfig,
 }
 
 impl RaftClient {
-    pub fn new(address: &str) -> RaftClient {
-        let raft_client = create_raft_client(address.to_string());
+    pub fn with_config(
+        address: &str,
+        tls: Option<TlsConfig>,
+        config: ClientConfig,
+    ) -> Result<RaftClient, Error> {
+        RaftClient::connect_with_config(&[address], tls, config)
+    }
 
-        let req = Null::new();
-        let reply = raft_client.status(&req).unwrap();
+    // Connects to the cluster through the first of the seeds that answers.
+    pub fn connect(seeds: &[&str], tls: Option<TlsConfig>) -> Result<RaftClient, Error> {
+        RaftClient::connect_with_config(seeds, tls, ClientConfig::default())
+    }
+
+    pub fn connect_with_config(
+        seeds: &[&str],
+        tls: Option<TlsConfig>,
+        config: ClientConfig,
+    ) -> Result<RaftClient, Error> {
+        let (_, reply) = discover(seeds, tls.as_ref(), &config)?;
         let leader_id = reply.leader_id;
         let addresses: HashMap<u64, String> = reply
             .address_map
             .iter()
             .map(|(node_id, node_address)| (node_id.clone(), node_address.raft_address.clone()))
             .collect();
-        let node_id = reply
-            .address_map
-            .iter()
-            .find_map(|(node_id, node_address)| {
-                if &node_address.raft_address == address {
-                    Some(node_id.clone())
-                } else {
-                    None
-                }
-            })
-            .unwrap();
 
         let mut clients = HashMap::new();
-        clients.insert(node_id, Arc::new(raft_client));
         for (i, a) in &addresses {
-            if node_id != *i {
-                let c = create_raft_client(a.to_string());
-                clients.insert(*i, Arc::new(c));
-            }
+            let c = create_raft_client(a.to_string(), tls.as_ref())?;
+            clients.insert(*i, Arc::new(c));
         }
 
-        RaftClient {
+        Ok(RaftClient {
             leader_id,
             clients,
             addresses,
-        }
+            tls,
+            config,
+        })
     }
 
     pub fn join(
         &mut self,
         node_id: u64,
         node_address: NodeAddress,
-    ) -> Result<HashMap<u64, NodeAddress>, std::io::Error> {
+    ) -> Result<HashMap<u64, NodeAddress>, Error> {
         let mut req = ConfChange::new();
         req.set_node_id(node_id);
         req.set_change_type(ConfChangeType::AddNode);
         req.set_context(serialize(&node_address).unwrap());
 
-        let max_retry = 10;
-        let mut cnt_retry = 0;
-
-        loop {
-            if max_retry < cnt_retry {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("max retry count has been exceeded: max_retry={}", max_retry),
-                ));
-            }
-
-            let client = match self.clients.get(&self.leader_id) {
-                Some(c) => c,
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to get client for node: id={}", self.leader_id),
-                    ));
-                }
-            };
+        self.call(
+            |client, opt| client.change_config_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )
+    }
 
-            let reply = match client.change_config(&req) {
-                Ok(r) => r,
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!(
-                            "failed to join node to the cluster: id={}",
-                            req.get_node_id()
-                        ),
-                    ));
-                }
-            };
+    pub fn leave(&mut self, id: u64) -> Result<HashMap<u64, NodeAddress>, Error> {
+        let mut req = ConfChange::new();
+        req.set_node_id(id);
+        req.set_change_type(ConfChangeType::RemoveNode);
+        req.set_context(vec![]);
 
-            // update address list and clients
-            // add new ids
-            for (id, address) in reply.get_address_map() {
-                if let Some(grpc_address) = self.addresses.get(&id) {
-                    if grpc_address == address.raft_address.as_str() {
-                        debug!(
-                            "node has not been changed: id={}, address={}",
-                            id, grpc_address
-                        );
-                    } else {
-                        debug!("update node: id={}, address={}", id, address.raft_address);
-                        self.addresses
-                            .insert(id.clone(), address.raft_address.clone());
-                        self.clients.insert(
-                            id.clone(),
-                            Arc::new(create_raft_client(address.raft_address.clone())),
-                        );
-                    }
-                } else {
-                    debug!("add node: id={}, address={}", id, address.raft_address);
-                    self.addresses
-                        .insert(id.clone(), address.raft_address.clone());
-                    self.clients.insert(
-                        id.clone(),
-                        Arc::new(create_raft_client(address.raft_address.clone())),
-                    );
-                }
-            }
+        self.call(
+            |client, opt| client.change_config_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )
+    }
 
-            // remove unused ids
-            for (id, address) in &self.addresses.clone() {
-                if reply.get_address_map().contains_key(&id) {
-                    debug!("node is in use: id={}, address={}", id, address);
-                } else {
-                    debug!("node is not in use: id={}, address={}", id, address);
-                    self.addresses.remove(id);
-                    self.clients.remove(id);
-                }
-            }
+    pub fn status(&mut self) -> Result<HashMap<u64, NodeAddress>, Error> {
+        self.status_reply()
+            .map(|reply| reply.get_address_map().clone())
+    }
 
-            debug!("addresses={:?}", self.addresses);
+    // Returns the whole status, including the storage settings of the node that answered.
+    pub fn status_reply(&mut self) -> Result<StatusReply, Error> {
+        let req = Null::new();
 
-            match reply.get_state() {
-                State::OK => {
-                    return Ok(reply.get_address_map().clone());
-                }
-                State::WRONG_LEADER => {
-                    warn!(
-                        "upddate leader id: current={}, new={}",
-                        self.leader_id,
-                        reply.get_leader_id()
-                    );
-                    self.leader_id = reply.get_leader_id();
-                    cnt_retry += 1;
-                    warn!("retry with a new leader: id={}", self.leader_id);
-                    continue;
-                }
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!(
-                            "failed to join node to the cluster: id={}",
-                            req.get_node_id()
-                        ),
-                    ));
-                }
-            };
-        }
+        self.request(
+            |client, opt| client.status_opt(&req, opt),
+            |reply| {
+                (
+                    reply.get_state(),
+                    reply.get_leader_id(),
+                    reply.get_address_map().clone(),
+                )
+            },
+        )
     }
 
-    pub fn leave(&mut self, id: u64) -> Result<HashMap<u64, NodeAddress>, std::io::Error> {
-        let mut req = ConfChange::new();
-        req.set_node_id(id);
-        req.set_change_type(ConfChangeType::RemoveNode);
-        req.set_context(vec![]);
+    // Sends the request to the leader, and retries with a new leader on WRONG_LEADER. Returns
+    // the addresses of the nodes in the reply.
+    fn call<R, F, P>(&mut self, f: F, parts: P) -> Result<HashMap<u64, NodeAddress>, Error>
+    where
+        F: Fn(&RaftServiceClient, CallOption) -> grpcio::Result<R>,
+        P: Fn(&R) -> (State, u64, HashMap<u64, NodeAddress>),
+    {
+        let reply = self.request(f, &parts)?;
+        Ok(parts(&reply).2)
+    }
 
-        let max_retry = 10;
+    fn request<R, F, P>(&mut self, f: F, parts: P) -> Result<R, Error>
+    where
+        F: Fn(&RaftServiceClient, CallOption) -> grpcio::Result<R>,
+        P: Fn(&R) -> (State, u64, HashMap<u64, NodeAddress>),
+    {
         let mut cnt_retry = 0;
 
         loop {
-            if max_retry < cnt_retry {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("max retry count has been exceeded: max_retry={}", max_retry),
-                ));
-            }
-
-            let client = match self.clients.get(&self.leader_id) {
-                Some(c) => c,
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to get client for node: id={}", self.leader_id),
-                    ));
-                }
+            let client = match pick_node(&self.clients, self.leader_id) {
+                Some(id) => &self.clients[&id],
+                None => return Err(Error::UnknownNode(self.leader_id)),
             };
 
-            let reply = match client.change_config(&req) {
-                Ok(r) => r,
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!(
-                            "failed to leave node from the cluster: id={}",
-                            req.get_node_id()
-                        ),
-                    ));
-                }
-            };
-
-            // update address list and clients
-            // add new ids
-            for (id, address) in reply.get_address_map() {
-                if let Some(grpc_address) = self.addresses.get(&id) {
-                    if grpc_address == address.raft_address.as_str() {
-                        debug!(
-                            "node has not been changed: id={}, address={}",
-                            id, grpc_address
-                        );
-                    } else {
-                        debug!("update node: id={}, address={}", id, address.raft_address);
-                        self.addresses
-                            .insert(id.clone(), address.raft_address.clone());
-                        self.clients.insert(
-                            id.clone(),
-                            Arc::new(create_raft_client(address.raft_address.clone())),
-                        );
+            // The leader is forgotten in case it is down, so that a random node is tried next,
+            // and it tells the leader. No node has the ID 0.
+            let reply = match f(client, self.config.call_option(None)) {
+                Ok(reply) => reply,
+                Err(e) => {
+                    let e = Error::from(e);
+                    self.leader_id = 0;
+                    cnt_retry += 1;
+                    if !e.is_retryable() || self.config.max_retries < cnt_retry {
+                        return Err(e);
                     }
-                } else {
-                    debug!("add node: id={}, address={}", id, address.raft_address);
-                    self.addresses
-                        .insert(id.clone(), address.raft_address.clone());
-                    self.clients.insert(
-                        id.clone(),
-                        Arc::new(create_raft_client(address.raft_address.clone())),
-                    );
-                }
-            }
-
-            // remove unused ids
-            for (id, address) in &self.addresses.clone() {
-                if reply.get_address_map().contains_key(&id) {
-                    debug!("node is in use: id={}, address={}", id, address);
-                } else {
-                    debug!("node is not in use: id={}, address={}", id, address);
-                    self.addresses.remove(id);
-                    self.clients.remove(id);
+                    warn!("failed to send request: error={}", e);
+                    thread::sleep(self.config.backoff(cnt_retry));
+                    continue;
                 }
-            }
+            };
 
-            debug!("addresses={:?}", self.addresses);
+            let (state, leader_id, address_map) = parts(&reply);
+            self.update_addresses(&address_map);
 
-            match reply.get_state() {
-                State::OK => {
-                    return Ok(reply.get_address_map().clone());
-                }
+            match state {
+                State::OK => return Ok(reply),
                 State::WRONG_LEADER => {
                     warn!(
                         "upddate leader id: current={}, new={}",
-                        self.leader_id,
-                        reply.get_leader_id()
+                        self.leader_id, leader_id
                     );
-                    self.leader_id = reply.get_leader_id();
+                    self.leader_id = leader_id;
                     cnt_retry += 1;
+                    if self.config.max_retries < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
                     warn!("retry with a new leader: id={}", self.leader_id);
-                    continue;
+                    thread::sleep(self.config.backoff(cnt_retry));
                 }
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!(
-                            "failed to leave node from the cluster: id={}",
-                            req.get_node_id()
-                        ),
-                    ));
+                // The leader changed before the write was applied. It may be applied anyway.
+                State::PROPOSAL_DROPPED => {
+                    cnt_retry += 1;
+                    if self.config.max_retries < cnt_retry {
+                        return Err(Error::RetriesExhausted {
+                            attempts: cnt_retry,
+                            state,
+                        });
+                    }
+                    warn!("proposal was dropped, retry");
+                    thread::sleep(self.config.backoff(cnt_retry));
                 }
-            };
+                _ => return Err(Error::State(state)),
+            }
         }
     }
 
-    pub fn status(&mut self) -> Result<HashMap<u64, NodeAddress>, std::io::Error> {
-        let req = Null::new();
-
-        let max_retry = 10;
-        let mut cnt_retry = 0;
-
-        loop {
-            if max_retry < cnt_retry {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("max retry count has been exceeded: max_retry={}", max_retry),
-                ));
-            }
-
-            let client = match self.clients.get(&self.leader_id) {
-                Some(c) => c,
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed to get client for node: id={}", self.leader_id),
-                    ));
-                }
-            };
-
-            let reply = match client.status(&req) {
-                Ok(r) => r,
-                _ => {
-                    return Err(Error::new(ErrorKind::Other, "failed to get status"));
-                }
-            };
-
-            // update address list and clients
-            // add new ids
-            for (id, address) in reply.get_address_map() {
-                if let Some(grpc_address) = self.addresses.get(&id) {
-                    if grpc_address == address.raft_address.as_str() {
-                        debug!(
-                            "node has not been changed: id={}, address={}",
-                            id, grpc_address
-                        );
-                    } else {
-                        debug!("update node: id={}, address={}", id, address.raft_address);
-                        self.addresses
-                            .insert(id.clone(), address.raft_address.clone());
-                        self.clients.insert(
-                            id.clone(),
-                            Arc::new(create_raft_client(address.raft_address.clone())),
-                        );
-                    }
-                } else {
-                    debug!("add node: id={}, address={}", id, address.raft_address);
-                    self.addresses
-                        .insert(id.clone(), address.raft_address.clone());
-                    self.clients.insert(
-                        id.clone(),
-                        Arc::new(create_raft_client(address.raft_address.clone())),
+    fn update_addresses(&mut self, address_map: &HashMap<u64, NodeAddress>) {
+        // update address list and clients
+        // add new ids
+        for (id, address) in address_map {
+            if let Some(grpc_address) = self.addresses.get(&id) {
+                if grpc_address == address.raft_address.as_str() {
+                    debug!(
+                        "node has not been changed: id={}, address={}",
+                        id, grpc_address
                     );
+                    continue;
                 }
             }
-
-            // remove unused ids
-            for (id, address) in &self.addresses.clone() {
-                if reply.get_address_map().contains_key(&id) {
-                    debug!("node is in use: id={}, address={}", id, address);
-                } else {
-                    debug!("node is not in use: id={}, address={}", id, address);
-                    self.addresses.remove(id);
-                    self.clients.remove(id);
+            debug!("update node: id={}, address={}", id, address.raft_address);
+            // The node is left out until a later reply, if the client can't be set up.
+            match create_raft_client(address.raft_address.clone(), self.tls.as_ref()) {
+                Ok(client) => {
+                    self.addresses.insert(*id, address.raft_address.clone());
+                    self.clients.insert(*id, Arc::new(client));
                 }
+                Err(e) => warn!(
+                    "failed to connect to node: id={}, address={}, error={}",
+                    id, address.raft_address, e
+                ),
             }
+        }
 
-            debug!("addresses={:?}", self.addresses);
+        // remove unused ids
+        for (id, address) in &self.addresses.clone() {
+            if !address_map.contains_key(&id) {
+                debug!("node is not in use: id={}, address={}", id, address);
+                self.addresses.remove(id);
+                self.clients.remove(id);
+            }
+        }
 
-            match reply.get_state() {
-                State::OK => {
-                    return Ok(reply.get_address_map().clone());
-                }
-                State::WRONG_LEADER => {
-                    warn!(
-                        "upddate leader id: current={}, new={}",
-                        self.leader_id,
-                        reply.get_leader_id()
-                    );
-                    self.leader_id = reply.get_leader_id();
-                    cnt_retry += 1;
-                    warn!("retry with a new leader: id={}", self.leader_id);
-                    continue;
-                }
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        "failed to leave node from the cluster: id={}",
-                    ));
-                }
-            };
+        debug!("addresses={:?}", self.addresses);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_known_node() {
+        let clients: HashMap<u64, ()> = vec![(1, ()), (2, ()), (3, ())].into_iter().collect();
+        assert_eq!(pick_node(&clients, 2), Some(2));
+    }
+
+    #[test]
+    fn test_pick_node_without_leader() {
+        let clients: HashMap<u64, ()> = vec![(1, ()), (2, ())].into_iter().collect();
+        // No leader during an election, or one that the client has no connection to.
+        for id in &[0, 3] {
+            let picked = pick_node(&clients, *id).unwrap();
+            assert!(clients.contains_key(&picked));
         }
+        assert_eq!(pick_node(&HashMap::<u64, ()>::new(), 0), None);
     }
 }
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+use grpcio::{Environment, Server, ServerBuilder};
+use raft::storage::MemStorage;
+
+use meteora_proto::proto::common::NodeAddress;
+use meteora_proto::proto::kv_grpc::create_kv_service;
+use meteora_proto::proto::raft_grpc::create_raft_service;
+use meteora_server::kv::server::KVServer;
+use meteora_server::raft::config::RaftConfig;
+use meteora_server::storage::{Engine, StorageConfig};
+
+use crate::config::ClientConfig;
+
+// A node of its own cluster, serving the key-value and Raft services on the same address.
+pub(crate) struct Node {
+    _server: Server,
+    pub(crate) address: String,
+}
+
+pub(crate) fn start_node() -> Node {
+    // The node has to know its address before the server is built, so a free port is taken
+    // from the OS first.
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let address = format!("127.0.0.1:{}", port);
+    let mut node_address = NodeAddress::new();
+    node_address.set_kv_address(address.clone());
+    node_address.set_raft_address(address.clone());
+
+    let raft_config = RaftConfig {
+        tick_interval: Duration::from_millis(10),
+        ..RaftConfig::default()
+    };
+    let storage_config = StorageConfig {
+        engine: Engine::Memory,
+        ..StorageConfig::default()
+    };
+    let (kv, raft, _, _) = KVServer::new(
+        String::new(),
+        MemStorage::new(),
+        1,
+        node_address,
+        HashMap::new(),
+        None,
+        false,
+        None,
+        raft_config,
+        storage_config,
+    )
+    .unwrap();
+
+    let mut server = ServerBuilder::new(Arc::new(Environment::new(2)))
+        .register_service(create_kv_service(kv))
+        .register_service(create_raft_service(raft))
+        .bind("127.0.0.1", port)
+        .build()
+        .unwrap();
+    server.start();
+    Node {
+        _server: server,
+        address,
+    }
+}
+
+// Retries long enough for the node to elect itself.
+pub(crate) fn client_config() -> ClientConfig {
+    ClientConfig {
+        max_retries: 100,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+        rpc_timeout: Some(Duration::from_secs(5)),
+        connect_timeout: Some(Duration::from_secs(5)),
+    }
+}
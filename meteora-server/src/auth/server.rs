@@ -0,0 +1,896 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bincode::{deserialize, serialize};
+use futures::Future;
+use grpcio::{RpcContext, UnarySink};
+use hmac::Hmac;
+use log::*;
+use opentelemetry::Context;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use meteora_proto::proto::auth::{
+    AuthenticateReply, AuthenticateReq, ChangePasswordReply, ChangePasswordReq, PermissionType,
+    RoleAddReply, RoleAddReq, RoleDeleteReply, RoleDeleteReq, RoleGrantPermissionReply,
+    RoleGrantPermissionReq, RoleRevokePermissionReply, RoleRevokePermissionReq, UserAddReply,
+    UserAddReq, UserDeleteReply, UserDeleteReq, UserGrantRoleReply, UserGrantRoleReq,
+    UserRevokeRoleReply, UserRevokeRoleReq,
+};
+use meteora_proto::proto::auth_grpc::AuthService;
+use meteora_proto::proto::common::{NodeAddress, State};
+
+use crate::auth::token::{generate_token, get_token};
+use crate::encryption::KeyRing;
+use crate::engine::KvEngine;
+use crate::kv::record::{get_value, now_millis};
+use crate::kv::server::{ApplyResult, Op};
+use crate::raft::config;
+
+// Keys under this prefix are used by Meteora itself and cannot be accessed
+// through the Key-Value service.
+pub const RESERVED_KEY_PREFIX: &[u8] = b"\x00meteora/";
+const USER_KEY_PREFIX: &[u8] = b"\x00meteora/auth/users/";
+const ROLE_KEY_PREFIX: &[u8] = b"\x00meteora/auth/roles/";
+
+pub const ROOT_USER: &str = "root";
+const PBKDF2_ROUNDS: u32 = 10_000;
+
+#[derive(Serialize, Deserialize)]
+struct User {
+    name: String,
+    salt: Vec<u8>,
+    password_hash: Vec<u8>,
+    roles: Vec<String>,
+}
+
+impl User {
+    fn new(name: &str, password: &str) -> User {
+        let mut user = User {
+            name: name.to_string(),
+            salt: Vec::new(),
+            password_hash: Vec::new(),
+            roles: Vec::new(),
+        };
+        user.set_password(password);
+        user
+    }
+
+    fn set_password(&mut self, password: &str) {
+        let mut salt = vec![0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        self.password_hash = hash_password(password, &salt);
+        self.salt = salt;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+// A permission on [key, range_end), as in etcd: an empty range_end means the key only, and
+// "\0" means all keys greater than or equal to the key.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct Permission {
+    access: Access,
+    key: Vec<u8>,
+    range_end: Vec<u8>,
+}
+
+impl Permission {
+    fn allows(&self, key: &[u8], access: Access) -> bool {
+        if self.access != Access::ReadWrite && self.access != access {
+            return false;
+        }
+        if self.range_end.is_empty() {
+            key == self.key.as_slice()
+        } else if self.range_end == [0] {
+            key >= self.key.as_slice()
+        } else {
+            key >= self.key.as_slice() && key < self.range_end.as_slice()
+        }
+    }
+
+    // Returns true if the permission covers every key in [key, range_end). Unlike the
+    // range_end of the permission, an empty range_end here means all keys greater than or
+    // equal to the key, as in a scan.
+    fn covers(&self, key: &[u8], range_end: &[u8], access: Access) -> bool {
+        if self.access != Access::ReadWrite && self.access != access {
+            return false;
+        }
+        let unbounded = range_end.is_empty() || range_end == [0];
+        if self.range_end.is_empty() {
+            // The range of a single key only holds the key.
+            return !unbounded && key == self.key.as_slice() && range_end == [key, &[0]].concat();
+        }
+        if key < self.key.as_slice() {
+            return false;
+        }
+        if self.range_end == [0] {
+            return true;
+        }
+        !unbounded && range_end <= self.range_end.as_slice()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Role {
+    name: String,
+    permissions: Vec<Permission>,
+}
+
+#[derive(Clone)]
+pub struct AuthServer {
+    db: Arc<dyn KvEngine>,
+    key_ring: Option<Arc<KeyRing>>,
+    sender: Sender<config::Msg>,
+    seq: Arc<AtomicU64>,
+    node_id: u64,
+}
+
+impl AuthServer {
+    pub fn new(
+        db: Arc<dyn KvEngine>,
+        key_ring: Option<Arc<KeyRing>>,
+        sender: Sender<config::Msg>,
+        seq: Arc<AtomicU64>,
+        node_id: u64,
+    ) -> AuthServer {
+        AuthServer {
+            db,
+            key_ring,
+            sender,
+            seq,
+            node_id,
+        }
+    }
+
+    fn propose(&mut self, op: Op) -> (State, u64, HashMap<u64, NodeAddress>) {
+        let (s1, r1) = mpsc::channel();
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        let node_id = self.node_id;
+
+        self.sender
+            .send(config::Msg::Propose {
+                seq,
+                op,
+                cx: Context::current(),
+                cb: Box::new(
+                    move |leader_id: i32,
+                          addresses: HashMap<u64, NodeAddress>,
+                          result: Option<ApplyResult>| {
+                        let reply = if leader_id >= 0 {
+                            // follower
+                            (State::WRONG_LEADER, leader_id as u64, addresses)
+                        } else {
+                            // leader
+                            let state = match result {
+                                Some(ApplyResult::Error(e)) => {
+                                    error!("failed to apply: {}", e);
+                                    State::IO_ERROR
+                                }
+                                Some(ApplyResult::Dropped) => State::PROPOSAL_DROPPED,
+                                _ => State::OK,
+                            };
+                            (state, node_id, addresses)
+                        };
+                        s1.send(reply).expect("callback channel closed");
+                    },
+                ),
+            })
+            .unwrap();
+
+        match r1.recv_timeout(Duration::from_secs(2)) {
+            Ok(r) => r,
+            Err(_e) => (State::IO_ERROR, 0, HashMap::new()),
+        }
+    }
+
+    pub fn authenticator(&self) -> Authenticator {
+        Authenticator {
+            db: self.db.clone(),
+            key_ring: self.key_ring.clone(),
+        }
+    }
+
+    fn require_root(&self, ctx: &RpcContext) -> State {
+        match authenticate_request(&self.db, self.key_ring.as_deref(), ctx) {
+            Ok(name) if name == ROOT_USER => State::OK,
+            Ok(_) => State::PERMISSION_DENIED,
+            Err(state) => state,
+        }
+    }
+}
+
+impl AuthService for AuthServer {
+    fn authenticate(
+        &mut self,
+        ctx: RpcContext,
+        req: AuthenticateReq,
+        sink: UnarySink<AuthenticateReply>,
+    ) {
+        let mut reply = AuthenticateReply::new();
+
+        let state = match get_user(&self.db, self.key_ring.as_deref(), req.get_name()) {
+            Ok(Some(user)) => {
+                // The hashes are compared in constant time, so that the time taken doesn't tell
+                // how much of the hash is right.
+                let hash = hash_password(req.get_password(), &user.salt);
+                if bool::from(hash.ct_eq(&user.password_hash)) {
+                    State::OK
+                } else {
+                    State::UNAUTHENTICATED
+                }
+            }
+            Ok(None) => State::UNAUTHENTICATED,
+            Err(state) => state,
+        };
+
+        if state == State::OK {
+            let token = generate_token();
+            let op = Op::IssueToken {
+                token: token.clone(),
+                name: req.get_name().to_string(),
+                now: now_millis(),
+            };
+            let (state, leader_id, addresses) = self.propose(op);
+            if state == State::OK {
+                reply.set_token(token);
+            }
+            reply.set_state(state);
+            reply.set_leader_id(leader_id);
+            reply.set_address_map(addresses);
+        } else {
+            reply.set_state(state);
+        }
+
+        let f = sink
+            .success(reply.clone())
+            .map_err(move |err| error!("failed to reply: {:?}", err));
+        ctx.spawn(f);
+    }
+
+    fn user_add(&mut self, ctx: RpcContext, req: UserAddReq, sink: UnarySink<UserAddReply>) {
+        let mut reply = UserAddReply::new();
+
+        // The first user must be the root user, and it can be added without a token.
+        let state = match has_users(&self.db) {
+            Ok(true) => self.require_root(&ctx),
+            Ok(false) if req.get_name() == ROOT_USER => State::OK,
+            Ok(false) => State::UNAUTHENTICATED,
+            Err(state) => state,
+        };
+        let state = match state {
+            State::OK => match get_user(&self.db, self.key_ring.as_deref(), req.get_name()) {
+                Ok(Some(_)) => State::ALREADY_EXISTS,
+                Ok(None) => State::OK,
+                Err(state) => state,
+            },
+            _ => state,
+        };
+
+        if state == State::OK {
+            let op = Op::put(
+                user_key(req.get_name()),
+                serialize(&User::new(req.get_name(), req.get_password())).unwrap(),
+            );
+            let (state, leader_id, addresses) = self.propose(op);
+            reply.set_state(state);
+            reply.set_leader_id(leader_id);
+            reply.set_address_map(addresses);
+        } else {
+            reply.set_state(state);
+        }
+
+        let f = sink
+            .success(reply.clone())
+            .map_err(move |err| error!("failed to reply: {:?}", err));
+        ctx.spawn(f);
+    }
+
+    fn user_delete(
+        &mut self,
+        ctx: RpcContext,
+        req: UserDeleteReq,
+        sink: UnarySink<UserDeleteReply>,
+    ) {
+        let mut reply = UserDeleteReply::new();
+
+        let state = match self.require_root(&ctx) {
+            State::OK => match get_user(&self.db, self.key_ring.as_deref(), req.get_name()) {
+                Ok(Some(_)) => State::OK,
+                Ok(None) => State::NOT_FOUND,
+                Err(state) => state,
+            },
+            state => state,
+        };
+
+        if state == State::OK {
+            let op = Op::Delete {
+                key: user_key(req.get_name()),
+                now: now_millis(),
+            };
+            let (state, leader_id, addresses) = self.propose(op);
+            reply.set_state(state);
+            reply.set_leader_id(leader_id);
+            reply.set_address_map(addresses);
+        } else {
+            reply.set_state(state);
+        }
+
+        let f = sink
+            .success(reply.clone())
+            .map_err(move |err| error!("failed to reply: {:?}", err));
+        ctx.spawn(f);
+    }
+
+    fn change_password(
+        &mut self,
+        ctx: RpcContext,
+        req: ChangePasswordReq,
+        sink: UnarySink<ChangePasswordReply>,
+    ) {
+        let mut reply = ChangePasswordReply::new();
+
+        // Users can change their own password, and the root user can change anyone's.
+        let user = match authenticate_request(&self.db, self.key_ring.as_deref(), &ctx) {
+            Ok(name) if name == ROOT_USER || name == req.get_name() => {
+                match get_user(&self.db, self.key_ring.as_deref(), req.get_name()) {
+                    Ok(Some(user)) => Ok(user),
+                    Ok(None) => Err(State::NOT_FOUND),
+                    Err(state) => Err(state),
+                }
+            }
+            Ok(_) => Err(State::PERMISSION_DENIED),
+            Err(state) => Err(state),
+        };
+
+        match user {
+            Ok(mut user) => {
+                user.set_password(req.get_password());
+                let op = Op::put(user_key(req.get_name()), serialize(&user).unwrap());
+                let (state, leader_id, addresses) = self.propose(op);
+                reply.set_state(state);
+                reply.set_leader_id(leader_id);
+                reply.set_address_map(addresses);
+            }
+            Err(state) => reply.set_state(state),
+        }
+
+        let f = sink
+            .success(reply.clone())
+            .map_err(move |err| error!("failed to reply: {:?}", err));
+        ctx.spawn(f);
+    }
+
+    fn user_grant_role(
+        &mut self,
+        ctx: RpcContext,
+        req: UserGrantRoleReq,
+        sink: UnarySink<UserGrantRoleReply>,
+    ) {
+        let mut reply = UserGrantRoleReply::new();
+
+        let user = match self.require_root(&ctx) {
+            State::OK => match (
+                get_user(&self.db, self.key_ring.as_deref(), req.get_name()),
+                get_role(&self.db, self.key_ring.as_deref(), req.get_role()),
+            ) {
+                (Ok(Some(user)), Ok(Some(_))) => Ok(user),
+                (Ok(_), Ok(_)) => Err(State::NOT_FOUND),
+                (Err(state), _) | (_, Err(state)) => Err(state),
+            },
+            state => Err(state),
+        };
+
+        match user {
+            Ok(mut user) => {
+                if !user.roles.iter().any(|r| r == req.get_role()) {
+                    user.roles.push(req.get_role().to_string());
+                }
+                let op = Op::put(user_key(req.get_name()), serialize(&user).unwrap());
+                let (state, leader_id, addresses) = self.propose(op);
+                reply.set_state(state);
+                reply.set_leader_id(leader_id);
+                reply.set_address_map(addresses);
+            }
+            Err(state) => reply.set_state(state),
+        }
+
+        let f = sink
+            .success(reply.clone())
+            .map_err(move |err| error!("failed to reply: {:?}", err));
+        ctx.spawn(f);
+    }
+
+    fn user_revoke_role(
+        &mut self,
+        ctx: RpcContext,
+        req: UserRevokeRoleReq,
+        sink: UnarySink<UserRevokeRoleReply>,
+    ) {
+        let mut reply = UserRevokeRoleReply::new();
+
+        let user = match self.require_root(&ctx) {
+            State::OK => match get_user(&self.db, self.key_ring.as_deref(), req.get_name()) {
+                Ok(Some(user)) => Ok(user),
+                Ok(None) => Err(State::NOT_FOUND),
+                Err(state) => Err(state),
+            },
+            state => Err(state),
+        };
+
+        match user {
+            Ok(mut user) => {
+                user.roles.retain(|r| r != req.get_role());
+                let op = Op::put(user_key(req.get_name()), serialize(&user).unwrap());
+                let (state, leader_id, addresses) = self.propose(op);
+                reply.set_state(state);
+                reply.set_leader_id(leader_id);
+                reply.set_address_map(addresses);
+            }
+            Err(state) => reply.set_state(state),
+        }
+
+        let f = sink
+            .success(reply.clone())
+            .map_err(move |err| error!("failed to reply: {:?}", err));
+        ctx.spawn(f);
+    }
+
+    fn role_add(&mut self, ctx: RpcContext, req: RoleAddReq, sink: UnarySink<RoleAddReply>) {
+        let mut reply = RoleAddReply::new();
+
+        let state = match self.require_root(&ctx) {
+            State::OK => match get_role(&self.db, self.key_ring.as_deref(), req.get_name()) {
+                Ok(Some(_)) => State::ALREADY_EXISTS,
+                Ok(None) => State::OK,
+                Err(state) => state,
+            },
+            state => state,
+        };
+
+        if state == State::OK {
+            let role = Role {
+                name: req.get_name().to_string(),
+                permissions: Vec::new(),
+            };
+            let op = Op::put(role_key(req.get_name()), serialize(&role).unwrap());
+            let (state, leader_id, addresses) = self.propose(op);
+            reply.set_state(state);
+            reply.set_leader_id(leader_id);
+            reply.set_address_map(addresses);
+        } else {
+            reply.set_state(state);
+        }
+
+        let f = sink
+            .success(reply.clone())
+            .map_err(move |err| error!("failed to reply: {:?}", err));
+        ctx.spawn(f);
+    }
+
+    fn role_delete(
+        &mut self,
+        ctx: RpcContext,
+        req: RoleDeleteReq,
+        sink: UnarySink<RoleDeleteReply>,
+    ) {
+        let mut reply = RoleDeleteReply::new();
+
+        let state = match self.require_root(&ctx) {
+            State::OK => match get_role(&self.db, self.key_ring.as_deref(), req.get_name()) {
+                Ok(Some(_)) => State::OK,
+                Ok(None) => State::NOT_FOUND,
+                Err(state) => state,
+            },
+            state => state,
+        };
+
+        // Users that still refer to the role simply lose its permissions.
+        if state == State::OK {
+            let op = Op::Delete {
+                key: role_key(req.get_name()),
+                now: now_millis(),
+            };
+            let (state, leader_id, addresses) = self.propose(op);
+            reply.set_state(state);
+            reply.set_leader_id(leader_id);
+            reply.set_address_map(addresses);
+        } else {
+            reply.set_state(state);
+        }
+
+        let f = sink
+            .success(reply.clone())
+            .map_err(move |err| error!("failed to reply: {:?}", err));
+        ctx.spawn(f);
+    }
+
+    fn role_grant_permission(
+        &mut self,
+        ctx: RpcContext,
+        req: RoleGrantPermissionReq,
+        sink: UnarySink<RoleGrantPermissionReply>,
+    ) {
+        let mut reply = RoleGrantPermissionReply::new();
+
+        let role = match self.require_root(&ctx) {
+            State::OK => match get_role(&self.db, self.key_ring.as_deref(), req.get_name()) {
+                Ok(Some(role)) => Ok(role),
+                Ok(None) => Err(State::NOT_FOUND),
+                Err(state) => Err(state),
+            },
+            state => Err(state),
+        };
+
+        match role {
+            Ok(mut role) => {
+                let perm = req.get_permission();
+                let access = match perm.get_perm_type() {
+                    PermissionType::READ => Access::Read,
+                    PermissionType::WRITE => Access::Write,
+                    PermissionType::READWRITE => Access::ReadWrite,
+                };
+                // Granting a permission on the same range again replaces it.
+                role.permissions
+                    .retain(|p| p.key != perm.get_key() || p.range_end != perm.get_range_end());
+                role.permissions.push(Permission {
+                    access,
+                    key: perm.get_key().to_vec(),
+                    range_end: perm.get_range_end().to_vec(),
+                });
+                let op = Op::put(role_key(req.get_name()), serialize(&role).unwrap());
+                let (state, leader_id, addresses) = self.propose(op);
+                reply.set_state(state);
+                reply.set_leader_id(leader_id);
+                reply.set_address_map(addresses);
+            }
+            Err(state) => reply.set_state(state),
+        }
+
+        let f = sink
+            .success(reply.clone())
+            .map_err(move |err| error!("failed to reply: {:?}", err));
+        ctx.spawn(f);
+    }
+
+    fn role_revoke_permission(
+        &mut self,
+        ctx: RpcContext,
+        req: RoleRevokePermissionReq,
+        sink: UnarySink<RoleRevokePermissionReply>,
+    ) {
+        let mut reply = RoleRevokePermissionReply::new();
+
+        let role = match self.require_root(&ctx) {
+            State::OK => match get_role(&self.db, self.key_ring.as_deref(), req.get_name()) {
+                Ok(Some(role)) => Ok(role),
+                Ok(None) => Err(State::NOT_FOUND),
+                Err(state) => Err(state),
+            },
+            state => Err(state),
+        };
+
+        match role {
+            Ok(mut role) => {
+                role.permissions
+                    .retain(|p| p.key != req.get_key() || p.range_end != req.get_range_end());
+                let op = Op::put(role_key(req.get_name()), serialize(&role).unwrap());
+                let (state, leader_id, addresses) = self.propose(op);
+                reply.set_state(state);
+                reply.set_leader_id(leader_id);
+                reply.set_address_map(addresses);
+            }
+            Err(state) => reply.set_state(state),
+        }
+
+        let f = sink
+            .success(reply.clone())
+            .map_err(move |err| error!("failed to reply: {:?}", err));
+        ctx.spawn(f);
+    }
+}
+
+// Checks the tokens of requests that don't come through gRPC, such as those of the REST
+// gateway.
+#[derive(Clone)]
+pub struct Authenticator {
+    db: Arc<dyn KvEngine>,
+    key_ring: Option<Arc<KeyRing>>,
+}
+
+impl Authenticator {
+    // Returns the name of the user of the token.
+    pub fn authenticate(&self, token: &str) -> Result<String, State> {
+        authenticate_token(self.db.as_ref(), self.key_ring.as_deref(), token)
+    }
+
+    pub fn require_root(&self, token: &str) -> Result<(), State> {
+        match self.authenticate(token)? {
+            name if name == ROOT_USER => Ok(()),
+            _ => Err(State::PERMISSION_DENIED),
+        }
+    }
+}
+
+pub fn is_reserved_key(key: &[u8]) -> bool {
+    key.starts_with(RESERVED_KEY_PREFIX)
+}
+
+// Validates the bearer token in the `authorization` metadata and returns the user name.
+pub fn authenticate_request(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    ctx: &RpcContext,
+) -> Result<String, State> {
+    match bearer_token(ctx) {
+        Some(token) => authenticate_token(db, key_ring, &token),
+        None => Err(State::UNAUTHENTICATED),
+    }
+}
+
+// Validates the token and returns the user name.
+fn authenticate_token(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    token: &str,
+) -> Result<String, State> {
+    let t = match get_token(db, key_ring, token) {
+        Ok(Some(t)) => t,
+        Ok(None) => return Err(State::UNAUTHENTICATED),
+        Err(e) => {
+            error!("failed to get token: {:?}", e);
+            return Err(State::IO_ERROR);
+        }
+    };
+    if t.expires_at < now() {
+        debug!("token has expired: name={}", t.name);
+        return Err(State::UNAUTHENTICATED);
+    }
+
+    // The user may have been deleted after the token was issued.
+    match get_user(db, key_ring, &t.name)? {
+        Some(_) => Ok(t.name),
+        None => Err(State::UNAUTHENTICATED),
+    }
+}
+
+// Checks that the user of the request has been granted `access` to the key
+// through one of its roles. The root user has access to all keys.
+pub fn authorize(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    ctx: &RpcContext,
+    key: &[u8],
+    access: Access,
+) -> Result<(), State> {
+    authorize_with(db, key_ring, ctx, |p| p.allows(key, access))
+}
+
+// Checks that one of the roles of the user grants `access` to the whole range
+// [key, range_end), as in a scan: an empty range_end or "\0" means all keys greater
+// than or equal to the key, which only a permission on "\0" covers.
+pub fn authorize_range(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    ctx: &RpcContext,
+    key: &[u8],
+    range_end: &[u8],
+    access: Access,
+) -> Result<(), State> {
+    authorize_with(db, key_ring, ctx, |p| p.covers(key, range_end, access))
+}
+
+fn authorize_with<F>(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    ctx: &RpcContext,
+    allows: F,
+) -> Result<(), State>
+where
+    F: Fn(&Permission) -> bool,
+{
+    let name = authenticate_request(db, key_ring, ctx)?;
+    if name == ROOT_USER {
+        return Ok(());
+    }
+
+    let user = match get_user(db, key_ring, &name)? {
+        Some(user) => user,
+        None => return Err(State::UNAUTHENTICATED),
+    };
+    for role_name in &user.roles {
+        if let Some(role) = get_role(db, key_ring, role_name)? {
+            if role.permissions.iter().any(|p| allows(p)) {
+                return Ok(());
+            }
+        }
+    }
+
+    debug!("permission denied: name={}", name);
+    Err(State::PERMISSION_DENIED)
+}
+
+fn bearer_token(ctx: &RpcContext) -> Option<String> {
+    for (key, value) in ctx.request_headers().iter() {
+        if key != "authorization" {
+            continue;
+        }
+        let value = String::from_utf8_lossy(value);
+        if let Some(token) = value.strip_prefix("Bearer ") {
+            return Some(token.trim().to_string());
+        }
+    }
+    None
+}
+
+fn get_user(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    name: &str,
+) -> Result<Option<User>, State> {
+    match get_value(db, key_ring, &user_key(name)) {
+        Ok(Some(v)) => match deserialize(&v) {
+            Ok(user) => Ok(Some(user)),
+            Err(e) => {
+                error!("failed to deserialize user: {:?}", e);
+                Err(State::IO_ERROR)
+            }
+        },
+        Ok(None) => Ok(None),
+        Err(e) => {
+            error!("failed to get user: {:?}", e);
+            Err(State::IO_ERROR)
+        }
+    }
+}
+
+fn get_role(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    name: &str,
+) -> Result<Option<Role>, State> {
+    match get_value(db, key_ring, &role_key(name)) {
+        Ok(Some(v)) => match deserialize(&v) {
+            Ok(role) => Ok(Some(role)),
+            Err(e) => {
+                error!("failed to deserialize role: {:?}", e);
+                Err(State::IO_ERROR)
+            }
+        },
+        Ok(None) => Ok(None),
+        Err(e) => {
+            error!("failed to get role: {:?}", e);
+            Err(State::IO_ERROR)
+        }
+    }
+}
+
+fn has_users(db: &dyn KvEngine) -> Result<bool, State> {
+    match db.iterator(USER_KEY_PREFIX).next() {
+        Some(Ok((key, _))) => Ok(key.starts_with(USER_KEY_PREFIX)),
+        Some(Err(e)) => {
+            error!("failed to look up users: {:?}", e);
+            Err(State::IO_ERROR)
+        }
+        None => Ok(false),
+    }
+}
+
+fn hash_password(password: &str, salt: &[u8]) -> Vec<u8> {
+    let mut hash = vec![0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, PBKDF2_ROUNDS, &mut hash);
+    hash
+}
+
+fn user_key(name: &str) -> Vec<u8> {
+    [USER_KEY_PREFIX, name.as_bytes()].concat()
+}
+
+fn role_key(name: &str) -> Vec<u8> {
+    [ROLE_KEY_PREFIX, name.as_bytes()].concat()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+// Returns the range end that covers all keys with the given prefix.
+pub fn prefix_range_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // All keys are covered if the prefix is empty or consists of 0xff.
+    vec![0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permission(access: Access, key: &[u8], range_end: &[u8]) -> Permission {
+        Permission {
+            access,
+            key: key.to_vec(),
+            range_end: range_end.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_allows() {
+        let single = permission(Access::Read, b"a", b"");
+        assert!(single.allows(b"a", Access::Read));
+        assert!(!single.allows(b"ab", Access::Read));
+        assert!(!single.allows(b"a", Access::Write));
+
+        let range = permission(Access::ReadWrite, b"b", b"d");
+        assert!(range.allows(b"b", Access::Write));
+        assert!(range.allows(b"cz", Access::Read));
+        assert!(!range.allows(b"d", Access::Read));
+        assert!(!range.allows(b"a", Access::Read));
+
+        let all = permission(Access::Write, b"m", &[0]);
+        assert!(all.allows(b"m", Access::Write));
+        assert!(all.allows(b"\xff", Access::Write));
+        assert!(!all.allows(b"l", Access::Write));
+    }
+
+    #[test]
+    fn test_covers_range() {
+        let prefix = permission(Access::Read, b"/app/", &prefix_range_end(b"/app/"));
+        assert!(prefix.covers(b"/app/", b"/app0", Access::Read));
+        assert!(prefix.covers(b"/app/a", b"/app/b", Access::Read));
+        assert!(!prefix.covers(b"/app/a", b"/b", Access::Read));
+        assert!(!prefix.covers(b"/ap", b"/app/b", Access::Read));
+        assert!(!prefix.covers(b"/app/a", b"/app/b", Access::Write));
+    }
+
+    #[test]
+    fn test_covers_unbounded() {
+        // An empty range_end of a scan has no end, so only a permission without one covers it.
+        let prefix = permission(Access::Read, b"/app/", &prefix_range_end(b"/app/"));
+        assert!(!prefix.covers(b"/app/", b"", Access::Read));
+        assert!(!prefix.covers(b"/app/", &[0], Access::Read));
+
+        let all = permission(Access::Read, b"/app/", &[0]);
+        assert!(all.covers(b"/app/", b"", Access::Read));
+        assert!(all.covers(b"/app/", &[0], Access::Read));
+        assert!(all.covers(b"/b", b"/c", Access::Read));
+        assert!(!all.covers(b"/a", b"", Access::Read));
+    }
+
+    #[test]
+    fn test_covers_single_key() {
+        // An empty range_end of a permission is the key only.
+        let single = permission(Access::Read, b"k", b"");
+        assert!(single.covers(b"k", b"k\0", Access::Read));
+        assert!(!single.covers(b"k", b"", Access::Read));
+        assert!(!single.covers(b"k", &[0], Access::Read));
+        assert!(!single.covers(b"k", b"l", Access::Read));
+        assert!(!single.covers(b"j", b"k\0", Access::Read));
+    }
+
+    #[test]
+    fn test_prefix_range_end() {
+        assert_eq!(prefix_range_end(b"/app/"), b"/app0".to_vec());
+        assert_eq!(prefix_range_end(b"a\xff"), b"b".to_vec());
+        assert_eq!(prefix_range_end(b"\xff\xff"), vec![0]);
+        assert_eq!(prefix_range_end(b""), vec![0]);
+    }
+}
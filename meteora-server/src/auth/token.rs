@@ -0,0 +1,120 @@
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind};
+
+use bincode::{deserialize, serialize};
+use log::*;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::KeyRing;
+use crate::engine::KvEngine;
+use crate::kv::record::{get_value, Record};
+use crate::kv::revision::Writer;
+use crate::kv::server::ApplyResult;
+
+const TOKEN_KEY_PREFIX: &[u8] = b"\x00meteora/auth/tokens/";
+// The tokens by the time they expire, so that the expired ones are found first.
+const TOKEN_TIME_KEY_PREFIX: &[u8] = b"\x00meteora/auth/token_times/";
+const TOKEN_TTL_SECS: u64 = 3600;
+// The number of expired tokens deleted by a login, so that a login stays cheap. Since it is
+// more than one, the expired tokens don't pile up.
+const MAX_EXPIRED_TOKENS: usize = 16;
+
+#[derive(Serialize, Deserialize)]
+pub struct Token {
+    pub name: String,
+    // Seconds since the UNIX epoch.
+    pub expires_at: u64,
+}
+
+pub fn generate_token() -> String {
+    let mut token = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut token);
+    hex::encode(token)
+}
+
+fn token_key(token: &str) -> Vec<u8> {
+    [TOKEN_KEY_PREFIX, token.as_bytes()].concat()
+}
+
+fn token_time_key(expires_at: u64, token: &str) -> Vec<u8> {
+    [
+        TOKEN_TIME_KEY_PREFIX,
+        &expires_at.to_be_bytes(),
+        token.as_bytes(),
+    ]
+    .concat()
+}
+
+// Returns the token if it has not expired.
+pub fn get_token(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    token: &str,
+) -> Result<Option<Token>, Error> {
+    match get_value(db, key_ring, &token_key(token))? {
+        Some(v) => deserialize(&v).map(Some).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to decode token: {}", e),
+            )
+        }),
+        None => Ok(None),
+    }
+}
+
+// Stores a token of the user that expires TOKEN_TTL_SECS after `now`, the time of the proposal
+// in milliseconds, and deletes some of the expired ones.
+pub fn issue(writer: &mut Writer, token: &str, name: &str, now: u64) -> ApplyResult {
+    let expires_at = now + TOKEN_TTL_SECS * 1000;
+    let t = Token {
+        name: name.to_string(),
+        expires_at: expires_at / 1000,
+    };
+    let issued = writer
+        .put(
+            &token_key(token),
+            Record::new(serialize(&t).unwrap(), Some(expires_at)),
+            now,
+        )
+        .and_then(|_| {
+            writer.put(
+                &token_time_key(expires_at, token),
+                Record::new(Vec::new(), None),
+                now,
+            )
+        })
+        .and_then(|_| expire_tokens(writer, now));
+    match issued {
+        Ok(()) => ApplyResult::Applied(true),
+        Err(e) => ApplyResult::Error(e.to_string()),
+    }
+}
+
+// Deletes the tokens that expired first. Every node deletes the same ones, since `now` is the
+// time of the proposal.
+fn expire_tokens(writer: &mut Writer, now: u64) -> Result<(), Error> {
+    let mut expired = Vec::new();
+    for item in writer.iterator(TOKEN_TIME_KEY_PREFIX) {
+        let (k, _) = item?;
+        if !k.starts_with(TOKEN_TIME_KEY_PREFIX) || expired.len() >= MAX_EXPIRED_TOKENS {
+            break;
+        }
+        let suffix = &k[TOKEN_TIME_KEY_PREFIX.len()..];
+        if suffix.len() < 8 {
+            continue;
+        }
+        let expires_at = u64::from_be_bytes(suffix[..8].try_into().unwrap());
+        if expires_at > now {
+            break;
+        }
+        let token = String::from_utf8_lossy(&suffix[8..]).into_owned();
+        expired.push((k, token));
+    }
+    for (time_key, token) in expired {
+        debug!("token expired");
+        writer.delete(&time_key)?;
+        writer.delete(&token_key(&token))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use log::*;
+use rand::RngCore;
+
+use crate::auth::server::is_reserved_key;
+use crate::engine::KvEngine;
+
+// Values are authenticated along with their key, so that a value can't be moved to another key.
+const FORMAT_VERSION: u8 = 2;
+// Values written before the key was authenticated. They are still read, and are written in the
+// current format when the key is put again.
+const LEGACY_FORMAT_VERSION: u8 = 1;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+// version (1 byte) + key id (4 bytes) + nonce (12 bytes)
+const HEADER_LEN: usize = 1 + 4 + NONCE_LEN;
+
+// A set of AES-256-GCM keys loaded from a key file.
+//
+// Each non-empty line of the key file is `<KEY_ID> <HEX_ENCODED_256_BIT_KEY>`, and lines
+// starting with `#` are ignored. New values are encrypted with the key that has the largest
+// ID, and older keys are kept to decrypt values written before the key was rotated.
+pub struct KeyRing {
+    ciphers: BTreeMap<u32, Aes256Gcm>,
+    active_id: u32,
+}
+
+impl KeyRing {
+    pub fn load(path: &str) -> Result<KeyRing, Error> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("failed to read key file: path={}, error={}", path, e),
+            )
+        })?;
+        KeyRing::parse(&content, path)
+    }
+
+    pub(crate) fn parse(content: &str, path: &str) -> Result<KeyRing, Error> {
+        let mut ciphers = BTreeMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |msg: &str| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: path={}, line={}", msg, path, i + 1),
+                )
+            };
+            let mut fields = line.split_whitespace();
+            let id = fields
+                .next()
+                .and_then(|f| f.parse::<u32>().ok())
+                .ok_or_else(|| invalid("invalid key ID"))?;
+            let key = fields
+                .next()
+                .and_then(|f| hex::decode(f).ok())
+                .filter(|k| k.len() == KEY_LEN)
+                .ok_or_else(|| invalid("key must be 32 bytes encoded in hex"))?;
+            if ciphers.contains_key(&id) {
+                return Err(invalid("duplicate key ID"));
+            }
+
+            ciphers.insert(id, Aes256Gcm::new(GenericArray::from_slice(&key)));
+        }
+
+        let active_id = match ciphers.keys().next_back() {
+            Some(id) => *id,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("no keys in key file: path={}", path),
+                ));
+            }
+        };
+
+        Ok(KeyRing { ciphers, active_id })
+    }
+
+    // Encrypts the value of the key. The key is authenticated but not encrypted.
+    pub fn encrypt(&self, key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let cipher = &self.ciphers[&self.active_id];
+
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: plaintext,
+            aad: key,
+        };
+        let ciphertext = cipher
+            .encrypt(GenericArray::from_slice(&nonce), payload)
+            .expect("failed to encrypt value");
+
+        let mut data = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        data.push(FORMAT_VERSION);
+        data.extend_from_slice(&self.active_id.to_be_bytes());
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        data
+    }
+
+    // Decrypts the value of the key. It fails if the value was encrypted for another key.
+    pub fn decrypt(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+        if data.len() < HEADER_LEN
+            || (data[0] != FORMAT_VERSION && data[0] != LEGACY_FORMAT_VERSION)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "value is not encrypted in a known format",
+            ));
+        }
+
+        let mut id = [0u8; 4];
+        id.copy_from_slice(&data[1..5]);
+        let id = u32::from_be_bytes(id);
+        let cipher = match self.ciphers.get(&id) {
+            Some(c) => c,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown encryption key: id={}", id),
+                ));
+            }
+        };
+
+        let nonce = GenericArray::from_slice(&data[5..HEADER_LEN]);
+        let aad: &[u8] = if data[0] == LEGACY_FORMAT_VERSION {
+            &[]
+        } else {
+            key
+        };
+        let payload = Payload {
+            msg: &data[HEADER_LEN..],
+            aad,
+        };
+        cipher
+            .decrypt(nonce, payload)
+            .map_err(|_e| Error::new(ErrorKind::InvalidData, "failed to decrypt value"))
+    }
+}
+
+// Whether the data directory is encrypted. It is kept by each node, since it describes the
+// local data rather than the replicated one.
+const ENCRYPTION_KEY: &[u8] = b"\x00meteora/encryption";
+
+// Checks that the data directory is encrypted if and only if a key ring is given, and records
+// it. Values written without encryption can't be read once it is enabled, and the other way
+// around, so the node refuses to start instead.
+pub fn check_data_directory(db: &dyn KvEngine, key_ring: Option<&KeyRing>) -> Result<(), Error> {
+    let encrypted = match db.get(ENCRYPTION_KEY)? {
+        Some(value) => Some(value == [1]),
+        None => detect_encryption(db, key_ring)?,
+    };
+    match encrypted {
+        Some(true) if key_ring.is_none() => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "data directory is encrypted, an encryption key file is required",
+        )),
+        Some(false) if key_ring.is_some() => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "data directory was written without encryption, which can't be enabled on it",
+        )),
+        _ => db.put(ENCRYPTION_KEY, &[key_ring.is_some() as u8]),
+    }
+}
+
+// Data directories written before the encryption was recorded are told apart by the first
+// value of a key of the clients, if there is one.
+fn detect_encryption(db: &dyn KvEngine, key_ring: Option<&KeyRing>) -> Result<Option<bool>, Error> {
+    let key_ring = match key_ring {
+        Some(key_ring) => key_ring,
+        None => return Ok(None),
+    };
+    for item in db.iterator(&[]) {
+        let (key, value) = item?;
+        if is_reserved_key(&key) {
+            continue;
+        }
+        let encrypted = key_ring.decrypt(&key, &value).is_ok();
+        if !encrypted {
+            warn!("value can't be decrypted: key={:?}", key);
+        }
+        return Ok(Some(encrypted));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY1: &str = "1 7f6d0e5a3c1b2a4d8e9f0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f";
+    const KEY2: &str = "2 00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    fn key_ring(content: &str) -> KeyRing {
+        KeyRing::parse(content, "test.keys").unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let key_ring = key_ring(KEY1);
+        let data = key_ring.encrypt(b"key", b"value");
+        assert_ne!(&data[HEADER_LEN..], b"value");
+        assert_eq!(key_ring.decrypt(b"key", &data).unwrap(), b"value".to_vec());
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let data = key_ring(KEY1).encrypt(b"key", b"value");
+        let other = key_ring(&KEY2.replace("2 ", "1 "));
+        assert_eq!(
+            other.decrypt(b"key", &data).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        // The value names a key ID that the key ring doesn't have.
+        assert!(key_ring(KEY2).decrypt(b"key", &data).is_err());
+    }
+
+    #[test]
+    fn test_moved_value() {
+        let key_ring = key_ring(KEY1);
+        let data = key_ring.encrypt(b"key1", b"value");
+        assert!(key_ring.decrypt(b"key2", &data).is_err());
+    }
+
+    #[test]
+    fn test_tampered_value() {
+        let key_ring = key_ring(KEY1);
+        let mut data = key_ring.encrypt(b"key", b"value");
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(key_ring.decrypt(b"key", &data).is_err());
+        assert!(key_ring.decrypt(b"key", b"value").is_err());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let old = key_ring(KEY1);
+        let data = old.encrypt(b"key", b"value");
+
+        let rotated = key_ring(&format!("# keys\n{}\n\n{}\n", KEY1, KEY2));
+        assert_eq!(rotated.active_id, 2);
+        assert_eq!(rotated.decrypt(b"key", &data).unwrap(), b"value".to_vec());
+        let data = rotated.encrypt(b"key", b"value");
+        assert_eq!(&data[1..5], &2u32.to_be_bytes());
+        assert!(old.decrypt(b"key", &data).is_err());
+    }
+
+    #[test]
+    fn test_legacy_format() {
+        let key_ring = key_ring(KEY1);
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = key_ring.ciphers[&1]
+            .encrypt(GenericArray::from_slice(&nonce), &b"value"[..])
+            .unwrap();
+        let data = [
+            &[LEGACY_FORMAT_VERSION][..],
+            &1u32.to_be_bytes(),
+            &nonce,
+            &ciphertext,
+        ]
+        .concat();
+        assert_eq!(key_ring.decrypt(b"key", &data).unwrap(), b"value".to_vec());
+    }
+
+    #[test]
+    fn test_invalid_key_file() {
+        assert!(KeyRing::parse("", "test.keys").is_err());
+        assert!(KeyRing::parse("1 00", "test.keys").is_err());
+        assert!(KeyRing::parse("x 00", "test.keys").is_err());
+        assert!(KeyRing::parse(&format!("{}\n{}", KEY1, KEY1), "test.keys").is_err());
+    }
+}
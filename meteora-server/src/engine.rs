@@ -0,0 +1,289 @@
+pub mod memory;
+#[cfg(feature = "engine-redb")]
+pub mod redb;
+#[cfg(feature = "engine-rocksdb")]
+pub mod rocks;
+
+use std::io::Error;
+
+// The pairs of an engine in key order. A pair that can't be read is an error, after which the
+// pairs that follow must not be relied on.
+pub type KvIterator<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), Error>> + 'a>;
+
+pub enum BatchOp {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+// Puts and deletes that are written together.
+#[derive(Default)]
+pub struct WriteBatch {
+    pub ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.ops.push(BatchOp::Put(key.to_vec(), value.to_vec()));
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.ops.push(BatchOp::Delete(key.to_vec()));
+    }
+}
+
+// A consistent view of an engine at the time it was taken.
+pub trait KvSnapshot {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+    // The pairs with a key greater than or equal to `from`.
+    fn iterator(&self, from: &[u8]) -> KvIterator;
+}
+
+// The ordered key-value store that the data of a node is kept in.
+pub trait KvEngine: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+    // Writes the operations of the batch atomically.
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), Error>;
+
+    // The pairs with a key greater than or equal to `from`. Writes made while iterating may
+    // or may not be seen.
+    fn iterator(&self, from: &[u8]) -> KvIterator;
+
+    fn snapshot(&self) -> Result<Box<dyn KvSnapshot + '_>, Error>;
+
+    // Saves a copy of the data in the directory, which must not exist.
+    fn checkpoint(&self, path: &str) -> Result<(), Error>;
+
+    // A statistic of the engine by its RocksDB property name, if the engine has it.
+    fn property_int_value(&self, _name: &str) -> Result<Option<u64>, Error> {
+        Ok(None)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write_batch(batch)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write_batch(batch)
+    }
+}
+
+// The tests that every engine must pass.
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::{Engine, StorageConfig};
+
+    static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+    // A data directory that is removed when the test ends.
+    pub(crate) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new() -> TempDir {
+            let n = NEXT_DIR.fetch_add(1, Ordering::SeqCst);
+            TempDir(env::temp_dir().join(format!("meteora-engine-{}-{}", process::id(), n)))
+        }
+
+        pub(crate) fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn open(engine: Engine, dir: &TempDir) -> Arc<dyn KvEngine> {
+        open_path(engine, dir.path())
+    }
+
+    fn open_path(engine: Engine, path: &str) -> Arc<dyn KvEngine> {
+        let config = StorageConfig {
+            engine,
+            ..StorageConfig::default()
+        };
+        config.open(path).unwrap()
+    }
+
+    fn keys(engine: &dyn KvEngine, from: &[u8]) -> Vec<Vec<u8>> {
+        engine.iterator(from).map(|item| item.unwrap().0).collect()
+    }
+
+    fn check_get_put_delete(engine: &dyn KvEngine) {
+        assert_eq!(engine.get(b"a").unwrap(), None);
+        engine.put(b"a", b"1").unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+        engine.put(b"a", b"2").unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"2".to_vec()));
+        engine.delete(b"a").unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), None);
+        // Deleting a missing key is not an error.
+        engine.delete(b"a").unwrap();
+        // Empty values are kept apart from missing ones.
+        engine.put(b"empty", b"").unwrap();
+        assert_eq!(engine.get(b"empty").unwrap(), Some(Vec::new()));
+        engine.delete(b"empty").unwrap();
+    }
+
+    fn check_write_batch(engine: &dyn KvEngine) {
+        engine.put(b"b", b"1").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"a", b"1");
+        batch.delete(b"b");
+        batch.put(b"c", b"1");
+        // A later operation on the same key wins.
+        batch.put(b"c", b"2");
+        engine.write_batch(batch).unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get(b"b").unwrap(), None);
+        assert_eq!(engine.get(b"c").unwrap(), Some(b"2".to_vec()));
+        engine.write_batch(WriteBatch::new()).unwrap();
+    }
+
+    fn check_iterator(engine: &dyn KvEngine) {
+        for key in &[&b"b"[..], b"a", b"\x00reserved", b"ab", b"\xff"] {
+            engine.put(key, key).unwrap();
+        }
+        let all = vec![
+            b"\x00reserved".to_vec(),
+            b"a".to_vec(),
+            b"ab".to_vec(),
+            b"b".to_vec(),
+            b"\xff".to_vec(),
+        ];
+        assert_eq!(keys(engine, b""), all);
+        assert_eq!(keys(engine, b"a\x00"), all[2..].to_vec());
+        assert_eq!(keys(engine, b"\xff\x00"), Vec::<Vec<u8>>::new());
+        for item in engine.iterator(b"") {
+            let (k, v) = item.unwrap();
+            assert_eq!(k, v);
+        }
+    }
+
+    // A snapshot does not see the writes made after it was taken.
+    fn check_snapshot(engine: &dyn KvEngine) {
+        engine.put(b"a", b"1").unwrap();
+        engine.put(b"b", b"1").unwrap();
+        let snapshot = engine.snapshot().unwrap();
+        engine.put(b"a", b"2").unwrap();
+        engine.delete(b"b").unwrap();
+        engine.put(b"c", b"1").unwrap();
+
+        assert_eq!(snapshot.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(snapshot.get(b"b").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(snapshot.get(b"c").unwrap(), None);
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = snapshot.iterator(b"").map(Result::unwrap).collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"1".to_vec())
+            ]
+        );
+        assert_eq!(snapshot.iterator(b"b").count(), 1);
+        assert_eq!(keys(engine, b""), vec![b"a".to_vec(), b"c".to_vec()]);
+    }
+
+    fn check_engine(engine: Engine) {
+        let checks: [fn(&dyn KvEngine); 4] = [
+            check_get_put_delete,
+            check_write_batch,
+            check_iterator,
+            check_snapshot,
+        ];
+        for check in &checks {
+            let dir = TempDir::new();
+            check(&*open(engine, &dir));
+        }
+    }
+
+    #[test]
+    fn test_memory() {
+        check_engine(Engine::Memory);
+    }
+
+    // Writes made after the checkpoint are not in it, and a directory that exists is refused.
+    fn check_checkpoint(engine: Engine, load: fn(&str) -> Arc<dyn KvEngine>) {
+        let dir = TempDir::new();
+        let checkpoint = TempDir::new();
+        let db = open(engine, &dir);
+        db.put(b"a", b"1").unwrap();
+        db.checkpoint(checkpoint.path()).unwrap();
+        db.put(b"b", b"1").unwrap();
+        assert!(db.checkpoint(checkpoint.path()).is_err());
+
+        let copy = load(checkpoint.path());
+        assert_eq!(copy.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(copy.get(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn test_memory_checkpoint() {
+        check_checkpoint(Engine::Memory, |path| {
+            Arc::new(memory::MemoryEngine::from_checkpoint(path).unwrap())
+        });
+    }
+
+    #[cfg(feature = "engine-rocksdb")]
+    #[test]
+    fn test_rocksdb_checkpoint() {
+        check_checkpoint(Engine::Rocksdb, |path| open_path(Engine::Rocksdb, path));
+    }
+
+    #[cfg(feature = "engine-redb")]
+    #[test]
+    fn test_redb_checkpoint() {
+        check_checkpoint(Engine::Redb, |path| open_path(Engine::Redb, path));
+    }
+
+    #[cfg(feature = "engine-rocksdb")]
+    #[test]
+    fn test_rocksdb() {
+        check_engine(Engine::Rocksdb);
+    }
+
+    #[cfg(feature = "engine-redb")]
+    #[test]
+    fn test_redb() {
+        check_engine(Engine::Redb);
+    }
+
+    // The data of an engine on disk is there when it is opened again.
+    #[cfg(feature = "engine-rocksdb")]
+    #[test]
+    fn test_rocksdb_reopen() {
+        check_reopen(Engine::Rocksdb);
+    }
+
+    #[cfg(feature = "engine-redb")]
+    #[test]
+    fn test_redb_reopen() {
+        check_reopen(Engine::Redb);
+    }
+
+    #[cfg(any(feature = "engine-rocksdb", feature = "engine-redb"))]
+    fn check_reopen(engine: Engine) {
+        let dir = TempDir::new();
+        open(engine, &dir).put(b"a", b"1").unwrap();
+        assert_eq!(open(engine, &dir).get(b"a").unwrap(), Some(b"1".to_vec()));
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use bincode::{deserialize, serialize};
+
+use crate::engine::{BatchOp, KvEngine, KvIterator, KvSnapshot, WriteBatch};
+
+const CHECKPOINT_FILE: &str = "memory.bin";
+
+type Data = BTreeMap<Vec<u8>, Vec<u8>>;
+
+// Keeps the data in memory only, so it is lost when the node stops. Snapshots and iterators
+// share the map, which is copied by the next write while they are alive.
+#[derive(Default)]
+pub struct MemoryEngine {
+    data: RwLock<Arc<Data>>,
+}
+
+impl MemoryEngine {
+    pub fn new() -> MemoryEngine {
+        MemoryEngine::default()
+    }
+
+    // Loads the data saved by `checkpoint`.
+    pub fn from_checkpoint(path: &str) -> Result<MemoryEngine, Error> {
+        let bytes = fs::read(Path::new(path).join(CHECKPOINT_FILE))?;
+        let data: Data = deserialize(&bytes).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to decode checkpoint: {}", e),
+            )
+        })?;
+        Ok(MemoryEngine {
+            data: RwLock::new(Arc::new(data)),
+        })
+    }
+
+    fn current(&self) -> Arc<Data> {
+        self.data.read().unwrap().clone()
+    }
+}
+
+impl KvEngine for MemoryEngine {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.data.read().unwrap().get(key).cloned())
+    }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+        let mut data = self.data.write().unwrap();
+        let data = Arc::make_mut(&mut data);
+        for op in batch.ops {
+            match op {
+                BatchOp::Put(key, value) => {
+                    data.insert(key, value);
+                }
+                BatchOp::Delete(key) => {
+                    data.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn iterator(&self, from: &[u8]) -> KvIterator {
+        Box::new(MemoryIterator::new(self.current(), from).map(Ok))
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn KvSnapshot + '_>, Error> {
+        Ok(Box::new(MemorySnapshot(self.current())))
+    }
+
+    fn checkpoint(&self, path: &str) -> Result<(), Error> {
+        let bytes = serialize(&*self.current()).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to encode checkpoint: {}", e),
+            )
+        })?;
+        fs::create_dir(path)?;
+        fs::write(Path::new(path).join(CHECKPOINT_FILE), bytes)
+    }
+}
+
+struct MemorySnapshot(Arc<Data>);
+
+impl KvSnapshot for MemorySnapshot {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.0.get(key).cloned())
+    }
+
+    fn iterator(&self, from: &[u8]) -> KvIterator {
+        Box::new(MemoryIterator::new(self.0.clone(), from).map(Ok))
+    }
+}
+
+// Walks a version of the map. Each step looks up the key after the last one, so that the
+// iterator does not borrow the map.
+struct MemoryIterator {
+    data: Arc<Data>,
+    next: Bound<Vec<u8>>,
+}
+
+impl MemoryIterator {
+    fn new(data: Arc<Data>, from: &[u8]) -> MemoryIterator {
+        MemoryIterator {
+            data,
+            next: Bound::Included(from.to_vec()),
+        }
+    }
+}
+
+impl Iterator for MemoryIterator {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (key, value) = self
+            .data
+            .range::<Vec<u8>, _>((self.next.clone(), Bound::Unbounded))
+            .next()?;
+        self.next = Bound::Excluded(key.clone());
+        Some((key.clone(), value.clone()))
+    }
+}
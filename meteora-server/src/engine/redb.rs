@@ -0,0 +1,126 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::iter;
+use std::path::Path;
+
+use redb::{Database, Range, ReadOnlyTable, ReadTransaction, ReadableTable, TableDefinition};
+
+use crate::engine::{BatchOp, KvEngine, KvIterator, KvSnapshot, WriteBatch};
+
+const DATA_FILE: &str = "data.redb";
+
+const TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("kv");
+
+fn redb_error<E: Into<redb::Error>>(e: E) -> Error {
+    Error::new(ErrorKind::Other, e.into().to_string())
+}
+
+fn pairs<'a>(range: Range<'a, &'static [u8], &'static [u8]>) -> KvIterator<'a> {
+    Box::new(range.map(|item| {
+        let (k, v) = item.map_err(redb_error)?;
+        Ok((k.value().to_vec(), v.value().to_vec()))
+    }))
+}
+
+// Keeps the data in a single redb file in the directory, which is pure Rust and builds much
+// faster than RocksDB.
+pub struct RedbEngine {
+    db: Database,
+}
+
+impl RedbEngine {
+    pub fn open(path: &str) -> Result<RedbEngine, Error> {
+        fs::create_dir_all(path)?;
+        let db = create(Path::new(path))?;
+        Ok(RedbEngine { db })
+    }
+
+    fn table(&self) -> Result<ReadOnlyTable<&'static [u8], &'static [u8]>, Error> {
+        let txn = self.db.begin_read().map_err(redb_error)?;
+        txn.open_table(TABLE).map_err(redb_error)
+    }
+}
+
+// Creates the table up front, since a read transaction cannot.
+fn create(dir: &Path) -> Result<Database, Error> {
+    let db = Database::create(dir.join(DATA_FILE)).map_err(redb_error)?;
+    let txn = db.begin_write().map_err(redb_error)?;
+    txn.open_table(TABLE).map_err(redb_error)?;
+    txn.commit().map_err(redb_error)?;
+    Ok(db)
+}
+
+impl KvEngine for RedbEngine {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let value = self.table()?.get(key).map_err(redb_error)?;
+        Ok(value.map(|v| v.value().to_vec()))
+    }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+        let txn = self.db.begin_write().map_err(redb_error)?;
+        {
+            let mut table = txn.open_table(TABLE).map_err(redb_error)?;
+            for op in batch.ops {
+                let written = match op {
+                    BatchOp::Put(key, value) => table.insert(key.as_slice(), value.as_slice()),
+                    BatchOp::Delete(key) => table.remove(key.as_slice()),
+                };
+                written.map_err(redb_error)?;
+            }
+        }
+        txn.commit().map_err(redb_error)
+    }
+
+    // The pairs are read from a read transaction, so writes made while iterating are not seen.
+    fn iterator(&self, from: &[u8]) -> KvIterator {
+        match self
+            .table()
+            .and_then(|t| t.range(from..).map_err(redb_error))
+        {
+            Ok(range) => pairs(range),
+            Err(e) => Box::new(iter::once(Err(e))),
+        }
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn KvSnapshot + '_>, Error> {
+        let txn = self.db.begin_read().map_err(redb_error)?;
+        let table = txn.open_table(TABLE).map_err(redb_error)?;
+        Ok(Box::new(RedbSnapshot { table, _txn: txn }))
+    }
+
+    // redb has no checkpoints, so the pairs of a read transaction are copied to a new file.
+    fn checkpoint(&self, path: &str) -> Result<(), Error> {
+        let table = self.table()?;
+        fs::create_dir(path)?;
+        let db = create(Path::new(path))?;
+        let txn = db.begin_write().map_err(redb_error)?;
+        {
+            let mut dest = txn.open_table(TABLE).map_err(redb_error)?;
+            for item in table.iter().map_err(redb_error)? {
+                let (k, v) = item.map_err(redb_error)?;
+                dest.insert(k.value(), v.value()).map_err(redb_error)?;
+            }
+        }
+        txn.commit().map_err(redb_error)
+    }
+}
+
+// The table of a read transaction, which sees the data as it was when the transaction began.
+struct RedbSnapshot {
+    table: ReadOnlyTable<&'static [u8], &'static [u8]>,
+    _txn: ReadTransaction,
+}
+
+impl KvSnapshot for RedbSnapshot {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let value = self.table.get(key).map_err(redb_error)?;
+        Ok(value.map(|v| v.value().to_vec()))
+    }
+
+    fn iterator(&self, from: &[u8]) -> KvIterator {
+        match self.table.range(from..) {
+            Ok(range) => pairs(range),
+            Err(e) => Box::new(iter::once(Err(redb_error(e)))),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::io::{Error, ErrorKind};
+
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::{Direction, IteratorMode, Snapshot, WriteBatch as RocksWriteBatch, DB};
+
+use crate::engine::{BatchOp, KvEngine, KvIterator, KvSnapshot, WriteBatch};
+use crate::storage::StorageConfig;
+
+fn rocksdb_error(e: rocksdb::Error) -> Error {
+    Error::new(ErrorKind::Other, e.to_string())
+}
+
+// Keeps the data in RocksDB on disk.
+pub struct RocksEngine {
+    db: DB,
+}
+
+impl RocksEngine {
+    pub fn open(path: &str, config: &StorageConfig) -> Result<RocksEngine, Error> {
+        let db = DB::open(&config.options()?, path).map_err(rocksdb_error)?;
+        Ok(RocksEngine { db })
+    }
+}
+
+impl KvEngine for RocksEngine {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.db.get(key).map_err(rocksdb_error)
+    }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+        let mut wb = RocksWriteBatch::default();
+        for op in batch.ops {
+            match op {
+                BatchOp::Put(key, value) => wb.put(key, value),
+                BatchOp::Delete(key) => wb.delete(key),
+            }
+        }
+        self.db.write(wb).map_err(rocksdb_error)
+    }
+
+    fn iterator(&self, from: &[u8]) -> KvIterator {
+        Box::new(
+            self.db
+                .iterator(IteratorMode::From(from, Direction::Forward))
+                .map(|(k, v)| Ok((k.into_vec(), v.into_vec()))),
+        )
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn KvSnapshot + '_>, Error> {
+        Ok(Box::new(RocksSnapshot(self.db.snapshot())))
+    }
+
+    fn checkpoint(&self, path: &str) -> Result<(), Error> {
+        Checkpoint::new(&self.db)
+            .and_then(|checkpoint| checkpoint.create_checkpoint(path))
+            .map_err(rocksdb_error)
+    }
+
+    fn property_int_value(&self, name: &str) -> Result<Option<u64>, Error> {
+        self.db.property_int_value(name).map_err(rocksdb_error)
+    }
+}
+
+struct RocksSnapshot<'a>(Snapshot<'a>);
+
+impl KvSnapshot for RocksSnapshot<'_> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.0.get(key).map_err(rocksdb_error)
+    }
+
+    fn iterator(&self, from: &[u8]) -> KvIterator {
+        Box::new(
+            self.0
+                .iterator(IteratorMode::From(from, Direction::Forward))
+                .map(|(k, v)| Ok((k.into_vec(), v.into_vec()))),
+        )
+    }
+}
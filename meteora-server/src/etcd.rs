@@ -0,0 +1,3 @@
+pub mod lease;
+pub mod server;
+pub mod txn;
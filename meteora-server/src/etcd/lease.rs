@@ -0,0 +1,269 @@
+use std::io::{Error, ErrorKind};
+
+use bincode::{deserialize, serialize};
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::KeyRing;
+use crate::engine::KvEngine;
+use crate::kv::record::{get_record, Record};
+use crate::kv::revision::{self, Writer};
+use crate::kv::server::ApplyResult;
+
+const LEASE_KEY_PREFIX: &[u8] = b"\x00meteora/etcd/leases/";
+
+pub const LEASE_NOT_FOUND: &str = "etcdserver: requested lease not found";
+
+// An etcd lease. The keys attached to it are deleted when it is revoked.
+#[derive(Serialize, Deserialize)]
+pub struct Lease {
+    pub id: i64,
+    // Seconds.
+    pub ttl: i64,
+    // Milliseconds since the UNIX epoch.
+    pub expires_at: u64,
+}
+
+impl Lease {
+    // The remaining TTL in seconds, rounded up.
+    pub fn remaining_ttl(&self, now: u64) -> i64 {
+        ((self.expires_at.saturating_sub(now) + 999) / 1000) as i64
+    }
+}
+
+fn lease_key(id: i64) -> Vec<u8> {
+    [LEASE_KEY_PREFIX, &id.to_be_bytes()].concat()
+}
+
+fn decode_lease(record: Record) -> Result<Lease, Error> {
+    deserialize(&record.value).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("failed to decode lease: {}", e),
+        )
+    })
+}
+
+pub fn get_lease(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    id: i64,
+) -> Result<Option<Lease>, Error> {
+    match get_record(db, key_ring, &lease_key(id))? {
+        Some(record) => decode_lease(record).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Returns the lease, including the changes made so far by the writer.
+pub fn get_written_lease(writer: &Writer, id: i64) -> Result<Option<Lease>, Error> {
+    match writer.get_record(&lease_key(id))? {
+        Some(record) => decode_lease(record).map(Some),
+        None => Ok(None),
+    }
+}
+
+pub fn leases(db: &dyn KvEngine, key_ring: Option<&KeyRing>) -> Result<Vec<Lease>, Error> {
+    let mut leases = Vec::new();
+    for item in db.iterator(LEASE_KEY_PREFIX) {
+        let (k, v) = item?;
+        if !k.starts_with(LEASE_KEY_PREFIX) {
+            break;
+        }
+        leases.push(decode_lease(Record::decode(&k, &v, key_ring)?)?);
+    }
+    Ok(leases)
+}
+
+// Returns the IDs of the leases that have expired by `now`, which the leader revokes.
+pub fn expired_leases(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    now: u64,
+) -> Result<Vec<i64>, Error> {
+    Ok(leases(db, key_ring)?
+        .into_iter()
+        .filter(|lease| lease.expires_at <= now)
+        .map(|lease| lease.id)
+        .collect())
+}
+
+// Returns the keys attached to the lease. Keys don't have an index by lease, so all of them
+// are read.
+pub fn lease_keys(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    id: i64,
+    now: u64,
+) -> Result<Vec<Vec<u8>>, Error> {
+    Ok(revision::range(db, key_ring, &[], &[0], now)?
+        .into_iter()
+        .filter(|(_, record)| record.lease == id)
+        .map(|(key, _)| key)
+        .collect())
+}
+
+fn put_lease(writer: &mut Writer, lease: &Lease, now: u64) -> Result<(), Error> {
+    writer.put(
+        &lease_key(lease.id),
+        Record::new(serialize(lease).unwrap(), None),
+        now,
+    )?;
+    Ok(())
+}
+
+pub fn grant(writer: &mut Writer, id: i64, ttl: i64, now: u64) -> ApplyResult {
+    match get_written_lease(writer, id) {
+        Ok(Some(_)) => {
+            return ApplyResult::InvalidArgument("etcdserver: lease already exists".to_string())
+        }
+        Ok(None) => {}
+        Err(e) => return ApplyResult::Error(e.to_string()),
+    }
+    let lease = Lease {
+        id,
+        ttl,
+        expires_at: now + ttl as u64 * 1000,
+    };
+    match put_lease(writer, &lease, now) {
+        Ok(()) => ApplyResult::Integer(ttl),
+        Err(e) => ApplyResult::Error(e.to_string()),
+    }
+}
+
+// Deletes the lease and the keys attached to it, including expired ones.
+pub fn revoke(writer: &mut Writer, id: i64) -> ApplyResult {
+    match get_written_lease(writer, id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return ApplyResult::NotFound(LEASE_NOT_FOUND.to_string()),
+        Err(e) => return ApplyResult::Error(e.to_string()),
+    }
+    let result = writer.range(&[], &[0], 0).and_then(|records| {
+        for (key, _) in records.into_iter().filter(|(_, r)| r.lease == id) {
+            writer.delete(&key)?;
+        }
+        writer.delete(&lease_key(id))
+    });
+    match result {
+        Ok(_) => ApplyResult::Applied(true),
+        Err(e) => ApplyResult::Error(e.to_string()),
+    }
+}
+
+pub fn keep_alive(writer: &mut Writer, id: i64, now: u64) -> ApplyResult {
+    let mut lease = match get_written_lease(writer, id) {
+        Ok(Some(lease)) => lease,
+        Ok(None) => return ApplyResult::NotFound(LEASE_NOT_FOUND.to_string()),
+        Err(e) => return ApplyResult::Error(e.to_string()),
+    };
+    lease.expires_at = now + lease.ttl as u64 * 1000;
+    match put_lease(writer, &lease, now) {
+        Ok(()) => ApplyResult::Integer(lease.ttl),
+        Err(e) => ApplyResult::Error(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::memory::MemoryEngine;
+    use crate::kv::revision::range;
+
+    fn commit<F>(db: &MemoryEngine, f: F) -> ApplyResult
+    where
+        F: FnOnce(&mut Writer) -> ApplyResult,
+    {
+        let mut writer = Writer::new(db, None).unwrap();
+        let result = f(&mut writer);
+        writer.commit().unwrap();
+        result
+    }
+
+    fn put_with_lease(db: &MemoryEngine, key: &[u8], lease: i64) {
+        let mut writer = Writer::new(db, None).unwrap();
+        let mut record = Record::new(b"value".to_vec(), None);
+        record.lease = lease;
+        writer.put(key, record, 0).unwrap();
+        writer.commit().unwrap();
+    }
+
+    #[test]
+    fn test_grant() {
+        let db = MemoryEngine::new();
+        assert_eq!(
+            commit(&db, |w| grant(w, 1, 10, 1000)),
+            ApplyResult::Integer(10)
+        );
+        match commit(&db, |w| grant(w, 1, 10, 1000)) {
+            ApplyResult::InvalidArgument(_) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let lease = get_lease(&db, None, 1).unwrap().unwrap();
+        assert_eq!(lease.ttl, 10);
+        assert_eq!(lease.expires_at, 11_000);
+        assert_eq!(lease.remaining_ttl(1000), 10);
+        assert_eq!(lease.remaining_ttl(1001), 10);
+        assert_eq!(lease.remaining_ttl(10_001), 1);
+        assert_eq!(lease.remaining_ttl(20_000), 0);
+        assert!(get_lease(&db, None, 2).unwrap().is_none());
+        // Leases are reserved keys, so they are not part of the key space.
+        assert!(range(&db, None, &[], &[0], 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let db = MemoryEngine::new();
+        commit(&db, |w| grant(w, 1, 10, 1000));
+        assert_eq!(
+            commit(&db, |w| keep_alive(w, 1, 5000)),
+            ApplyResult::Integer(10)
+        );
+        assert_eq!(get_lease(&db, None, 1).unwrap().unwrap().expires_at, 15_000);
+        assert_eq!(
+            commit(&db, |w| keep_alive(w, 2, 5000)),
+            ApplyResult::NotFound(LEASE_NOT_FOUND.to_string())
+        );
+    }
+
+    #[test]
+    fn test_revoke() {
+        let db = MemoryEngine::new();
+        commit(&db, |w| grant(w, 1, 10, 0));
+        commit(&db, |w| grant(w, 2, 10, 0));
+        put_with_lease(&db, b"a", 1);
+        put_with_lease(&db, b"b", 2);
+        put_with_lease(&db, b"c", 1);
+        assert_eq!(
+            lease_keys(&db, None, 1, 0).unwrap(),
+            vec![b"a".to_vec(), b"c".to_vec()]
+        );
+
+        assert_eq!(commit(&db, |w| revoke(w, 1)), ApplyResult::Applied(true));
+        assert!(get_lease(&db, None, 1).unwrap().is_none());
+        let keys: Vec<Vec<u8>> = range(&db, None, &[], &[0], 0)
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec![b"b".to_vec()]);
+        assert_eq!(
+            commit(&db, |w| revoke(w, 1)),
+            ApplyResult::NotFound(LEASE_NOT_FOUND.to_string())
+        );
+    }
+
+    #[test]
+    fn test_expired_leases() {
+        let db = MemoryEngine::new();
+        commit(&db, |w| grant(w, 1, 1, 0));
+        commit(&db, |w| grant(w, 2, 10, 0));
+        assert!(expired_leases(&db, None, 999).unwrap().is_empty());
+        assert_eq!(expired_leases(&db, None, 1000).unwrap(), vec![1]);
+        assert_eq!(expired_leases(&db, None, 10_000).unwrap(), vec![1, 2]);
+
+        // A lease that is kept alive expires later.
+        commit(&db, |w| keep_alive(w, 1, 900));
+        assert!(expired_leases(&db, None, 1000).unwrap().is_empty());
+        assert_eq!(leases(&db, None).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,894 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use futures::{Future, Sink, Stream};
+use grpcio::{
+    DuplexSink, RequestStream, RpcContext, RpcStatus, RpcStatusCode, UnarySink, WriteFlags,
+};
+use log::*;
+use opentelemetry::trace::{TraceContextExt, Tracer};
+use opentelemetry::Context;
+use protobuf::Message;
+use rand::Rng;
+
+use meteora_proto::proto::common::NodeAddress;
+use meteora_proto::proto::etcd::{
+    CompactionRequest, CompactionResponse, DeleteRangeRequest, DeleteRangeResponse,
+    LeaseGrantRequest, LeaseGrantResponse, LeaseKeepAliveRequest, LeaseKeepAliveResponse,
+    LeaseLeasesRequest, LeaseLeasesResponse, LeaseRevokeRequest, LeaseRevokeResponse, LeaseStatus,
+    LeaseTimeToLiveRequest, LeaseTimeToLiveResponse, PutRequest, PutResponse, RangeRequest,
+    RangeResponse, RequestOp, RequestOp_oneof_request, ResponseHeader, TxnRequest, TxnResponse,
+    WatchCreateRequest, WatchCreateRequest_FilterType, WatchRequest,
+    WatchRequest_oneof_request_union, WatchResponse,
+};
+use meteora_proto::proto::etcd_grpc::{Kv, Lease as LeaseService, Watch};
+use meteora_proto::proto::mvcc::{Event, Event_EventType, KeyValue};
+
+use crate::auth::server::is_reserved_key;
+use crate::encryption::KeyRing;
+use crate::engine::KvEngine;
+use crate::etcd::lease::{expired_leases, get_lease, lease_keys, leases};
+use crate::etcd::txn::{self, to_key_value};
+use crate::kv::record::now_millis;
+use crate::kv::revision::{current_revision, EventKind};
+use crate::kv::server::{ApplyResult, Op};
+use crate::kv::watch::WatchHub;
+use crate::metrics;
+use crate::raft::config;
+use crate::trace;
+
+// The same limit as etcd.
+const MAX_LEASE_TTL: i64 = 9_000_000_000;
+const LEASE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+// The KV, Watch and Lease services of the etcd v3 API, on top of the Raft log and the engine.
+// Only the latest revision of each key is kept, so reads and watches on past revisions fail
+// in the same way as on a compacted etcd.
+#[derive(Clone)]
+pub struct EtcdServer {
+    db: Arc<dyn KvEngine>,
+    key_ring: Option<Arc<KeyRing>>,
+    sender: Sender<config::Msg>,
+    seq: Arc<AtomicU64>,
+    node_id: u64,
+    watch_hub: Arc<WatchHub>,
+}
+
+fn status(code: RpcStatusCode, message: &str) -> RpcStatus {
+    RpcStatus::new(code, Some(message.to_string()))
+}
+
+fn reply<T>(ctx: &RpcContext, sink: UnarySink<T>, result: Result<T, RpcStatus>) {
+    let f = match result {
+        Ok(resp) => sink.success(resp),
+        Err(status) => sink.fail(status),
+    };
+    ctx.spawn(f.map_err(move |err| error!("failed to reply: {:?}", err)));
+}
+
+// Returns whether the key is in [key, range_end), in the same way as etcd.
+fn in_range(key: &[u8], start: &[u8], range_end: &[u8]) -> bool {
+    if range_end.is_empty() {
+        key == start
+    } else if range_end == [0] {
+        key >= start
+    } else {
+        key >= start && key < range_end
+    }
+}
+
+// Checks the revisions of the reads and the keys of the writes before a transaction is proposed.
+fn check_ops(ops: &[RequestOp], revision: i64) -> Result<(), RpcStatus> {
+    for op in ops {
+        match &op.request {
+            Some(RequestOp_oneof_request::request_range(req)) => {
+                check_revision(req.get_revision(), revision)?
+            }
+            Some(RequestOp_oneof_request::request_put(req)) => {
+                if is_reserved_key(req.get_key()) {
+                    return Err(status(
+                        RpcStatusCode::PermissionDenied,
+                        "etcdserver: permission denied",
+                    ));
+                }
+            }
+            Some(RequestOp_oneof_request::request_txn(txn)) => {
+                check_ops(txn.get_success(), revision)?;
+                check_ops(txn.get_failure(), revision)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_revision(requested: i64, revision: i64) -> Result<(), RpcStatus> {
+    if requested > revision {
+        Err(status(
+            RpcStatusCode::OutOfRange,
+            "etcdserver: mvcc: required revision is a future revision",
+        ))
+    } else if requested > 0 && requested < revision {
+        Err(status(
+            RpcStatusCode::OutOfRange,
+            "etcdserver: mvcc: required revision has been compacted",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+impl EtcdServer {
+    pub fn new(
+        db: Arc<dyn KvEngine>,
+        key_ring: Option<Arc<KeyRing>>,
+        sender: Sender<config::Msg>,
+        seq: Arc<AtomicU64>,
+        node_id: u64,
+        watch_hub: Arc<WatchHub>,
+    ) -> EtcdServer {
+        EtcdServer {
+            db,
+            key_ring,
+            sender,
+            seq,
+            node_id,
+            watch_hub,
+        }
+    }
+
+    // Revokes expired leases while this node is the leader.
+    pub fn start_lease_expiry(&self) {
+        let server = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(LEASE_CHECK_INTERVAL);
+            if !server.is_leader() {
+                continue;
+            }
+            let now = now_millis();
+            let expired = match expired_leases(&server.db, server.key_ring.as_deref(), now) {
+                Ok(expired) => expired,
+                Err(e) => {
+                    error!("failed to get leases: {:?}", e);
+                    continue;
+                }
+            };
+            for id in expired {
+                debug!("revoke expired lease: id={}", id);
+                if let Err(e) = server.propose(Op::LeaseRevoke { id }, Context::new()) {
+                    warn!("failed to revoke lease: id={}, error={:?}", id, e);
+                }
+            }
+        });
+    }
+
+    fn is_leader(&self) -> bool {
+        let (s1, r1) = mpsc::channel();
+        self.sender
+            .send(config::Msg::Read {
+                cb: Box::new(
+                    move |leader_id: i32, _addresses: HashMap<u64, NodeAddress>| {
+                        s1.send(leader_id < 0).expect("callback channel closed");
+                    },
+                ),
+            })
+            .unwrap();
+        r1.recv_timeout(Duration::from_secs(2)).unwrap_or(false)
+    }
+
+    fn header(&self) -> ResponseHeader {
+        let mut header = ResponseHeader::new();
+        header.set_member_id(self.node_id);
+        header.set_revision(current_revision(&self.db).unwrap_or(0) as i64);
+        header
+    }
+
+    // Proposes the operation and waits until it is applied on this node. etcd clients don't
+    // follow the leader, so a follower fails with UNAVAILABLE and the client tries another
+    // endpoint.
+    fn propose(&self, op: Op, cx: Context) -> Result<ApplyResult, RpcStatus> {
+        let (s1, r1) = mpsc::channel();
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+
+        self.sender
+            .send(config::Msg::Propose {
+                seq,
+                op,
+                cx,
+                cb: Box::new(
+                    move |leader_id: i32,
+                          _addresses: HashMap<u64, NodeAddress>,
+                          result: Option<ApplyResult>| {
+                        let result = if leader_id >= 0 { None } else { result };
+                        s1.send(result).expect("callback channel closed");
+                    },
+                ),
+            })
+            .unwrap();
+
+        match r1.recv_timeout(Duration::from_secs(2)) {
+            Ok(Some(ApplyResult::NotFound(e))) => Err(status(RpcStatusCode::NotFound, &e)),
+            Ok(Some(ApplyResult::InvalidArgument(e))) => {
+                Err(status(RpcStatusCode::InvalidArgument, &e))
+            }
+            Ok(Some(ApplyResult::Error(e))) => {
+                error!("failed to apply: {}", e);
+                Err(status(RpcStatusCode::Internal, &e))
+            }
+            Ok(Some(ApplyResult::Dropped)) => Err(status(
+                RpcStatusCode::Unavailable,
+                "etcdserver: leader changed",
+            )),
+            Ok(Some(result)) => Ok(result),
+            Ok(None) => Err(status(RpcStatusCode::Unavailable, "etcdserver: not leader")),
+            Err(_) => Err(status(
+                RpcStatusCode::Unavailable,
+                "etcdserver: request timed out",
+            )),
+        }
+    }
+
+    fn txn_request(&self, txn: TxnRequest, cx: Context) -> Result<TxnResponse, RpcStatus> {
+        let revision = current_revision(&self.db).unwrap_or(0) as i64;
+        check_ops(txn.get_success(), revision)?;
+        check_ops(txn.get_failure(), revision)?;
+
+        let op = Op::EtcdTxn {
+            txn: txn.write_to_bytes().unwrap(),
+            now: now_millis(),
+        };
+        match self.propose(op, cx)? {
+            ApplyResult::Bytes(data) => {
+                let mut resp = TxnResponse::new();
+                resp.merge_from_bytes(&data)
+                    .map_err(|e| status(RpcStatusCode::Internal, &e.to_string()))?;
+                resp.mut_header().set_member_id(self.node_id);
+                Ok(resp)
+            }
+            result => Err(status(
+                RpcStatusCode::Internal,
+                &format!("unexpected result: {:?}", result),
+            )),
+        }
+    }
+
+    fn create_watch(
+        &self,
+        create: &WatchCreateRequest,
+        watch_id: i64,
+        sender: &UnboundedSender<WatchResponse>,
+    ) -> Option<u64> {
+        let start_revision = create.get_start_revision();
+        let key = create.get_key().to_vec();
+        let range_end = create.get_range_end().to_vec();
+        let no_put = create
+            .get_filters()
+            .contains(&WatchCreateRequest_FilterType::NOPUT);
+        let no_delete = create
+            .get_filters()
+            .contains(&WatchCreateRequest_FilterType::NODELETE);
+        let prev_kv = create.get_prev_kv();
+        let node_id = self.node_id;
+        // The revision is read while no changes are published, so that the watcher gets every
+        // change after it, and gets it after the created response.
+        self.watch_hub.watch(|| {
+            let mut resp = WatchResponse::new();
+            resp.set_header(self.header());
+            resp.set_watch_id(watch_id);
+            resp.set_created(true);
+
+            // Events are not kept once they are published, so a watch can't start before the
+            // next revision.
+            let revision = resp.get_header().get_revision();
+            if start_revision > 0 && start_revision <= revision {
+                resp.set_canceled(true);
+                resp.set_compact_revision(revision + 1);
+                resp.set_cancel_reason(
+                    "etcdserver: mvcc: required revision has been compacted".to_string(),
+                );
+                let _ = sender.unbounded_send(resp);
+                return None;
+            }
+            if sender.unbounded_send(resp).is_err() {
+                return None;
+            }
+            let start_revision = start_revision.max(revision + 1);
+            let sender = sender.clone();
+            Some(Box::new(move |revision, events| {
+                if (revision as i64) < start_revision {
+                    return true;
+                }
+                let mut resp = WatchResponse::new();
+                for event in events {
+                    if !in_range(&event.key, &key, &range_end) {
+                        continue;
+                    }
+                    let mut e = Event::new();
+                    match event.kind {
+                        EventKind::Put if !no_put => {
+                            e.set_field_type(Event_EventType::PUT);
+                            e.set_kv(to_key_value(&event.key, event.record.as_ref().unwrap()));
+                        }
+                        EventKind::Delete if !no_delete => {
+                            e.set_field_type(Event_EventType::DELETE);
+                            let mut kv = KeyValue::new();
+                            kv.set_key(event.key.clone());
+                            kv.set_mod_revision(revision as i64);
+                            e.set_kv(kv);
+                        }
+                        _ => continue,
+                    }
+                    if prev_kv {
+                        if let Some(prev) = &event.prev {
+                            e.set_prev_kv(to_key_value(&event.key, prev));
+                        }
+                    }
+                    resp.mut_events().push(e);
+                }
+                if resp.get_events().is_empty() {
+                    return true;
+                }
+                resp.mut_header().set_member_id(node_id);
+                resp.mut_header().set_revision(revision as i64);
+                resp.set_watch_id(watch_id);
+                sender.unbounded_send(resp).is_ok()
+            }))
+        })
+    }
+}
+
+impl Kv for EtcdServer {
+    fn range(&mut self, ctx: RpcContext, req: RangeRequest, sink: UnarySink<RangeResponse>) {
+        let _timer = metrics::start_request_timer("etcd", "range");
+        let _span = trace::tracer().start_with_context("EtcdServer::range", trace::extract(&ctx));
+
+        // Reads are served by this node, as serializable reads are in etcd.
+        let header = self.header();
+        let result = check_revision(req.get_revision(), header.get_revision()).and_then(|_| {
+            txn::range(&self.db, self.key_ring.as_deref(), &req, now_millis())
+                .map(|mut resp| {
+                    resp.set_header(header);
+                    resp
+                })
+                .map_err(|e| status(RpcStatusCode::Internal, &e.to_string()))
+        });
+        reply(&ctx, sink, result);
+    }
+
+    fn put(&mut self, ctx: RpcContext, req: PutRequest, sink: UnarySink<PutResponse>) {
+        let _timer = metrics::start_request_timer("etcd", "put");
+        let span = trace::tracer().start_with_context("EtcdServer::put", trace::extract(&ctx));
+
+        let mut op = RequestOp::new();
+        op.set_request_put(req);
+        let mut txn = TxnRequest::new();
+        txn.mut_success().push(op);
+        let result = self
+            .txn_request(txn, Context::current_with_span(span))
+            .map(|mut txn_resp| {
+                let mut resp = txn_resp.mut_responses()[0].take_response_put();
+                resp.set_header(txn_resp.take_header());
+                resp
+            });
+        reply(&ctx, sink, result);
+    }
+
+    fn delete_range(
+        &mut self,
+        ctx: RpcContext,
+        req: DeleteRangeRequest,
+        sink: UnarySink<DeleteRangeResponse>,
+    ) {
+        let _timer = metrics::start_request_timer("etcd", "delete_range");
+        let span =
+            trace::tracer().start_with_context("EtcdServer::delete_range", trace::extract(&ctx));
+
+        let mut op = RequestOp::new();
+        op.set_request_delete_range(req);
+        let mut txn = TxnRequest::new();
+        txn.mut_success().push(op);
+        let result = self
+            .txn_request(txn, Context::current_with_span(span))
+            .map(|mut txn_resp| {
+                let mut resp = txn_resp.mut_responses()[0].take_response_delete_range();
+                resp.set_header(txn_resp.take_header());
+                resp
+            });
+        reply(&ctx, sink, result);
+    }
+
+    fn txn(&mut self, ctx: RpcContext, req: TxnRequest, sink: UnarySink<TxnResponse>) {
+        let _timer = metrics::start_request_timer("etcd", "txn");
+        let span = trace::tracer().start_with_context("EtcdServer::txn", trace::extract(&ctx));
+
+        let result = self.txn_request(req, Context::current_with_span(span));
+        reply(&ctx, sink, result);
+    }
+
+    // There is no history to compact, so only the revision is checked.
+    fn compact(
+        &mut self,
+        ctx: RpcContext,
+        req: CompactionRequest,
+        sink: UnarySink<CompactionResponse>,
+    ) {
+        let _timer = metrics::start_request_timer("etcd", "compact");
+
+        let header = self.header();
+        let result = if req.get_revision() > header.get_revision() {
+            Err(status(
+                RpcStatusCode::OutOfRange,
+                "etcdserver: mvcc: required revision is a future revision",
+            ))
+        } else {
+            let mut resp = CompactionResponse::new();
+            resp.set_header(header);
+            Ok(resp)
+        };
+        reply(&ctx, sink, result);
+    }
+}
+
+impl Watch for EtcdServer {
+    fn watch(
+        &mut self,
+        ctx: RpcContext,
+        stream: RequestStream<WatchRequest>,
+        sink: DuplexSink<WatchResponse>,
+    ) {
+        let (sender, receiver) = unbounded();
+        let server = self.clone();
+        // The IDs in the watch hub of the watchers of this stream, by watch ID.
+        let watchers = Arc::new(Mutex::new(HashMap::new()));
+        let stream_watchers = Arc::clone(&watchers);
+        let watch_hub = Arc::clone(&self.watch_hub);
+        let mut next_watch_id = 0;
+
+        let requests = stream
+            .for_each(move |req| {
+                match req.request_union {
+                    Some(WatchRequest_oneof_request_union::create_request(create)) => {
+                        let mut watchers = stream_watchers.lock().unwrap();
+                        // A watch ID of 0 asks the server to choose one.
+                        let watch_id = if create.get_watch_id() != 0 {
+                            create.get_watch_id()
+                        } else {
+                            while watchers.contains_key(&next_watch_id) {
+                                next_watch_id += 1;
+                            }
+                            next_watch_id
+                        };
+                        if watchers.contains_key(&watch_id) {
+                            let mut resp = WatchResponse::new();
+                            resp.set_header(server.header());
+                            resp.set_watch_id(watch_id);
+                            resp.set_canceled(true);
+                            resp.set_cancel_reason("etcdserver: duplicate watch ID".to_string());
+                            let _ = sender.unbounded_send(resp);
+                        } else if let Some(id) = server.create_watch(&create, watch_id, &sender) {
+                            watchers.insert(watch_id, id);
+                        }
+                    }
+                    Some(WatchRequest_oneof_request_union::cancel_request(cancel)) => {
+                        let watch_id = cancel.get_watch_id();
+                        if let Some(id) = stream_watchers.lock().unwrap().remove(&watch_id) {
+                            server.watch_hub.cancel(id);
+                        }
+                        let mut resp = WatchResponse::new();
+                        resp.set_header(server.header());
+                        resp.set_watch_id(watch_id);
+                        resp.set_canceled(true);
+                        let _ = sender.unbounded_send(resp);
+                    }
+                    Some(WatchRequest_oneof_request_union::progress_request(_)) => {
+                        let mut resp = WatchResponse::new();
+                        resp.set_header(server.header());
+                        resp.set_watch_id(-1);
+                        let _ = sender.unbounded_send(resp);
+                    }
+                    None => {}
+                }
+                Ok(())
+            })
+            .then(move |result| {
+                // Remove the watchers, so that the response stream ends.
+                for (_, id) in watchers.lock().unwrap().drain() {
+                    watch_hub.cancel(id);
+                }
+                if let Err(e) = result {
+                    debug!("watch stream closed: {:?}", e);
+                }
+                Ok(())
+            });
+        ctx.spawn(requests);
+
+        let responses = receiver
+            .map(|resp| (resp, WriteFlags::default()))
+            .map_err(|_| grpcio::Error::RemoteStopped);
+        ctx.spawn(
+            sink.send_all(responses)
+                .map(|_| ())
+                .map_err(|e| debug!("failed to send watch response: {:?}", e)),
+        );
+    }
+}
+
+impl LeaseService for EtcdServer {
+    fn lease_grant(
+        &mut self,
+        ctx: RpcContext,
+        req: LeaseGrantRequest,
+        sink: UnarySink<LeaseGrantResponse>,
+    ) {
+        let _timer = metrics::start_request_timer("etcd", "lease_grant");
+        let span =
+            trace::tracer().start_with_context("EtcdServer::lease_grant", trace::extract(&ctx));
+
+        if req.get_TTL() > MAX_LEASE_TTL {
+            let result = Err(status(
+                RpcStatusCode::OutOfRange,
+                "etcdserver: too large lease TTL",
+            ));
+            reply(&ctx, sink, result);
+            return;
+        }
+        // The ID is chosen here, so that every node applies the same one.
+        let id = if req.get_ID() != 0 {
+            req.get_ID()
+        } else {
+            rand::thread_rng().gen_range(1..i64::MAX)
+        };
+        let op = Op::LeaseGrant {
+            id,
+            ttl: req.get_TTL().max(1),
+            now: now_millis(),
+        };
+        let result = self
+            .propose(op, Context::current_with_span(span))
+            .map(|result| {
+                let mut resp = LeaseGrantResponse::new();
+                resp.set_header(self.header());
+                resp.set_ID(id);
+                if let ApplyResult::Integer(ttl) = result {
+                    resp.set_TTL(ttl);
+                }
+                resp
+            });
+        reply(&ctx, sink, result);
+    }
+
+    fn lease_revoke(
+        &mut self,
+        ctx: RpcContext,
+        req: LeaseRevokeRequest,
+        sink: UnarySink<LeaseRevokeResponse>,
+    ) {
+        let _timer = metrics::start_request_timer("etcd", "lease_revoke");
+        let span =
+            trace::tracer().start_with_context("EtcdServer::lease_revoke", trace::extract(&ctx));
+
+        let op = Op::LeaseRevoke { id: req.get_ID() };
+        let result = self.propose(op, Context::current_with_span(span)).map(|_| {
+            let mut resp = LeaseRevokeResponse::new();
+            resp.set_header(self.header());
+            resp
+        });
+        reply(&ctx, sink, result);
+    }
+
+    fn lease_keep_alive(
+        &mut self,
+        ctx: RpcContext,
+        stream: RequestStream<LeaseKeepAliveRequest>,
+        sink: DuplexSink<LeaseKeepAliveResponse>,
+    ) {
+        let server = self.clone();
+        let responses = stream.and_then(move |req| {
+            let op = Op::LeaseKeepAlive {
+                id: req.get_ID(),
+                now: now_millis(),
+            };
+            let mut resp = LeaseKeepAliveResponse::new();
+            resp.set_ID(req.get_ID());
+            match server.propose(op, Context::new()) {
+                Ok(ApplyResult::Integer(ttl)) => resp.set_TTL(ttl),
+                Ok(_) => {}
+                // A TTL of 0 tells the client that the lease has expired.
+                Err(status) if status.status == RpcStatusCode::NotFound => {}
+                Err(status) => return Err(grpcio::Error::RpcFailure(status)),
+            }
+            resp.set_header(server.header());
+            Ok((resp, WriteFlags::default()))
+        });
+        ctx.spawn(
+            sink.send_all(responses)
+                .map(|_| ())
+                .map_err(|e| debug!("lease keep-alive stream closed: {:?}", e)),
+        );
+    }
+
+    fn lease_time_to_live(
+        &mut self,
+        ctx: RpcContext,
+        req: LeaseTimeToLiveRequest,
+        sink: UnarySink<LeaseTimeToLiveResponse>,
+    ) {
+        let _timer = metrics::start_request_timer("etcd", "lease_time_to_live");
+
+        let db = &self.db;
+        let key_ring = self.key_ring.as_deref();
+        let now = now_millis();
+        let mut resp = LeaseTimeToLiveResponse::new();
+        resp.set_header(self.header());
+        resp.set_ID(req.get_ID());
+        let result = match get_lease(db, key_ring, req.get_ID()) {
+            Ok(Some(lease)) => {
+                resp.set_TTL(lease.remaining_ttl(now));
+                resp.set_grantedTTL(lease.ttl);
+                if req.get_keys() {
+                    match lease_keys(db, key_ring, lease.id, now) {
+                        Ok(keys) => {
+                            resp.set_keys(keys.into());
+                            Ok(resp)
+                        }
+                        Err(e) => Err(status(RpcStatusCode::Internal, &e.to_string())),
+                    }
+                } else {
+                    Ok(resp)
+                }
+            }
+            // etcd returns a TTL of -1 for a lease that does not exist.
+            Ok(None) => {
+                resp.set_TTL(-1);
+                Ok(resp)
+            }
+            Err(e) => Err(status(RpcStatusCode::Internal, &e.to_string())),
+        };
+        reply(&ctx, sink, result);
+    }
+
+    fn lease_leases(
+        &mut self,
+        ctx: RpcContext,
+        _req: LeaseLeasesRequest,
+        sink: UnarySink<LeaseLeasesResponse>,
+    ) {
+        let _timer = metrics::start_request_timer("etcd", "lease_leases");
+
+        let result = leases(&self.db, self.key_ring.as_deref())
+            .map(|leases| {
+                let mut resp = LeaseLeasesResponse::new();
+                resp.set_header(self.header());
+                for lease in leases {
+                    let mut status = LeaseStatus::new();
+                    status.set_ID(lease.id);
+                    resp.mut_leases().push(status);
+                }
+                resp
+            })
+            .map_err(|e| status(RpcStatusCode::Internal, &e.to_string()));
+        reply(&ctx, sink, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::sync::mpsc::UnboundedReceiver;
+
+    use crate::engine::memory::MemoryEngine;
+    use crate::kv::record::Record;
+    use crate::kv::revision::Writer;
+
+    fn server() -> EtcdServer {
+        let (sender, _) = mpsc::channel();
+        EtcdServer::new(
+            Arc::new(MemoryEngine::new()),
+            None,
+            sender,
+            Arc::new(AtomicU64::new(0)),
+            1,
+            Arc::new(WatchHub::new()),
+        )
+    }
+
+    // Writes the changes in the same way as the apply daemon.
+    fn write<F>(server: &EtcdServer, f: F)
+    where
+        F: FnOnce(&mut Writer),
+    {
+        let mut writer = Writer::new(&*server.db, None).unwrap();
+        f(&mut writer);
+        let (revision, events) = writer.commit().unwrap();
+        server.watch_hub.publish(revision, &events);
+    }
+
+    fn put(server: &EtcdServer, key: &[u8], value: &[u8]) {
+        write(server, |writer| {
+            writer
+                .put(key, Record::new(value.to_vec(), None), 0)
+                .unwrap();
+        });
+    }
+
+    fn watch(
+        server: &EtcdServer,
+        create: WatchCreateRequest,
+    ) -> (Option<u64>, UnboundedReceiver<WatchResponse>) {
+        let (sender, receiver) = unbounded();
+        (server.create_watch(&create, 7, &sender), receiver)
+    }
+
+    // Cancels the watch and returns the responses sent so far.
+    fn responses(
+        server: &EtcdServer,
+        id: Option<u64>,
+        receiver: UnboundedReceiver<WatchResponse>,
+    ) -> Vec<WatchResponse> {
+        if let Some(id) = id {
+            server.watch_hub.cancel(id);
+        }
+        receiver.wait().map(|resp| resp.unwrap()).collect()
+    }
+
+    fn create_request(key: &[u8], range_end: &[u8], start_revision: i64) -> WatchCreateRequest {
+        let mut create = WatchCreateRequest::new();
+        create.set_key(key.to_vec());
+        create.set_range_end(range_end.to_vec());
+        create.set_start_revision(start_revision);
+        create
+    }
+
+    fn event_keys(resp: &WatchResponse) -> Vec<(Event_EventType, Vec<u8>)> {
+        resp.get_events()
+            .iter()
+            .map(|e| (e.get_field_type(), e.get_kv().get_key().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_watch() {
+        let server = server();
+        put(&server, b"a", b"1");
+
+        let (id, receiver) = watch(&server, create_request(b"a", b"b", 0));
+        assert!(id.is_some());
+        put(&server, b"a", b"2");
+        put(&server, b"b", b"2");
+        write(&server, |writer| {
+            writer.delete(b"a").unwrap();
+        });
+
+        let resps = responses(&server, id, receiver);
+        assert_eq!(resps.len(), 3);
+        assert!(resps[0].get_created());
+        assert_eq!(resps[0].get_watch_id(), 7);
+        assert_eq!(resps[0].get_header().get_revision(), 1);
+
+        assert_eq!(resps[1].get_header().get_revision(), 2);
+        assert_eq!(
+            event_keys(&resps[1]),
+            vec![(Event_EventType::PUT, b"a".to_vec())]
+        );
+        let kv = resps[1].get_events()[0].get_kv();
+        assert_eq!(kv.get_value(), b"2");
+        assert_eq!(kv.get_version(), 2);
+        assert!(!resps[1].get_events()[0].has_prev_kv());
+
+        // The put of b is out of the range.
+        assert_eq!(resps[2].get_header().get_revision(), 4);
+        assert_eq!(
+            event_keys(&resps[2]),
+            vec![(Event_EventType::DELETE, b"a".to_vec())]
+        );
+        assert_eq!(resps[2].get_events()[0].get_kv().get_mod_revision(), 4);
+    }
+
+    #[test]
+    fn test_watch_from_revision() {
+        let server = server();
+        put(&server, b"a", b"1");
+        put(&server, b"a", b"2");
+
+        // Past revisions are not kept.
+        for start_revision in &[1, 2] {
+            let (id, receiver) = watch(&server, create_request(b"a", b"", *start_revision));
+            assert!(id.is_none());
+            let resps = responses(&server, id, receiver);
+            assert_eq!(resps.len(), 1);
+            assert!(resps[0].get_created());
+            assert!(resps[0].get_canceled());
+            assert_eq!(resps[0].get_compact_revision(), 3);
+        }
+
+        // Changes before a future revision are skipped.
+        let (id, receiver) = watch(&server, create_request(b"a", b"", 4));
+        put(&server, b"a", b"3");
+        put(&server, b"a", b"4");
+        put(&server, b"a", b"5");
+        let resps = responses(&server, id, receiver);
+        assert_eq!(resps.len(), 3);
+        assert!(!resps[0].get_canceled());
+        let revisions: Vec<i64> = resps[1..]
+            .iter()
+            .map(|resp| resp.get_header().get_revision())
+            .collect();
+        assert_eq!(revisions, vec![4, 5]);
+        assert_eq!(resps[1].get_events()[0].get_kv().get_value(), b"4");
+    }
+
+    #[test]
+    fn test_watch_filters() {
+        let server = server();
+        put(&server, b"a", b"1");
+
+        let mut create = create_request(b"", &[0], 0);
+        create.set_prev_kv(true);
+        create
+            .mut_filters()
+            .push(WatchCreateRequest_FilterType::NOPUT);
+        let (id, receiver) = watch(&server, create);
+        put(&server, b"a", b"2");
+        write(&server, |writer| {
+            writer.delete(b"a").unwrap();
+        });
+
+        let resps = responses(&server, id, receiver);
+        assert_eq!(resps.len(), 2);
+        assert_eq!(
+            event_keys(&resps[1]),
+            vec![(Event_EventType::DELETE, b"a".to_vec())]
+        );
+        assert_eq!(resps[1].get_events()[0].get_prev_kv().get_value(), b"2");
+    }
+
+    #[test]
+    fn test_in_range() {
+        assert!(in_range(b"a", b"a", b""));
+        assert!(!in_range(b"ab", b"a", b""));
+        assert!(in_range(b"ab", b"a", b"b"));
+        assert!(!in_range(b"b", b"a", b"b"));
+        assert!(in_range(b"z", b"a", &[0]));
+        assert!(!in_range(b"0", b"a", &[0]));
+    }
+
+    #[test]
+    fn test_check_revision() {
+        assert!(check_revision(0, 5).is_ok());
+        assert!(check_revision(5, 5).is_ok());
+        let e = check_revision(6, 5).unwrap_err();
+        assert_eq!(e.status, RpcStatusCode::OutOfRange);
+        assert!(e.details.unwrap().contains("future revision"));
+        let e = check_revision(4, 5).unwrap_err();
+        assert!(e.details.unwrap().contains("compacted"));
+    }
+
+    #[test]
+    fn test_check_ops() {
+        let mut req = PutRequest::new();
+        req.set_key(b"\x00meteora/users/root".to_vec());
+        let mut op = RequestOp::new();
+        op.set_request_put(req);
+        let e = check_ops(&[op.clone()], 1).unwrap_err();
+        assert_eq!(e.status, RpcStatusCode::PermissionDenied);
+
+        // Nested transactions are checked too.
+        let mut txn = TxnRequest::new();
+        txn.mut_failure().push(op);
+        let mut nested = RequestOp::new();
+        nested.set_request_txn(txn);
+        assert!(check_ops(&[nested], 1).is_err());
+
+        let mut range = RangeRequest::new();
+        range.set_revision(2);
+        let mut op = RequestOp::new();
+        op.set_request_range(range);
+        assert!(check_ops(&[op.clone()], 2).is_ok());
+        assert!(check_ops(&[op], 3).is_err());
+    }
+}
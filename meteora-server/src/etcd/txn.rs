@@ -0,0 +1,523 @@
+use std::cmp::Ordering;
+use std::io::Error;
+
+use protobuf::Message;
+
+use meteora_proto::proto::etcd::{
+    Compare, Compare_CompareResult, Compare_CompareTarget, DeleteRangeRequest, DeleteRangeResponse,
+    PutRequest, PutResponse, RangeRequest, RangeRequest_SortOrder, RangeRequest_SortTarget,
+    RangeResponse, RequestOp, RequestOp_oneof_request, ResponseOp, TxnRequest, TxnResponse,
+};
+use meteora_proto::proto::mvcc::KeyValue;
+
+use crate::encryption::KeyRing;
+use crate::engine::KvEngine;
+use crate::etcd::lease::{get_written_lease, LEASE_NOT_FOUND};
+use crate::kv::record::Record;
+use crate::kv::revision::{self, Writer};
+use crate::kv::server::ApplyResult;
+
+pub fn to_key_value(key: &[u8], record: &Record) -> KeyValue {
+    let mut kv = KeyValue::new();
+    kv.set_key(key.to_vec());
+    kv.set_value(record.value.clone());
+    kv.set_create_revision(record.create_revision as i64);
+    kv.set_mod_revision(record.mod_revision as i64);
+    kv.set_version(record.version as i64);
+    kv.set_lease(record.lease);
+    kv
+}
+
+// Serves a range request from the data of this node.
+pub fn range(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    req: &RangeRequest,
+    now: u64,
+) -> Result<RangeResponse, Error> {
+    let records = revision::range(db, key_ring, req.get_key(), req.get_range_end(), now)?;
+    Ok(range_response(records, req))
+}
+
+fn range_response(records: Vec<(Vec<u8>, Record)>, req: &RangeRequest) -> RangeResponse {
+    let within = |value: u64, min: i64, max: i64| {
+        (min <= 0 || value as i64 >= min) && (max <= 0 || value as i64 <= max)
+    };
+    let mut records: Vec<(Vec<u8>, Record)> = records
+        .into_iter()
+        .filter(|(_, r)| {
+            within(
+                r.mod_revision,
+                req.get_min_mod_revision(),
+                req.get_max_mod_revision(),
+            ) && within(
+                r.create_revision,
+                req.get_min_create_revision(),
+                req.get_max_create_revision(),
+            )
+        })
+        .collect();
+
+    // Records are in key order already. The order of the other targets defaults to ascending.
+    let target = req.get_sort_target();
+    let order = match (req.get_sort_order(), target) {
+        (RangeRequest_SortOrder::NONE, RangeRequest_SortTarget::KEY) => {
+            RangeRequest_SortOrder::NONE
+        }
+        (RangeRequest_SortOrder::NONE, _) => RangeRequest_SortOrder::ASCEND,
+        (order, _) => order,
+    };
+    if order != RangeRequest_SortOrder::NONE {
+        records.sort_by(|(ak, ar), (bk, br)| match target {
+            RangeRequest_SortTarget::KEY => ak.cmp(bk),
+            RangeRequest_SortTarget::VERSION => ar.version.cmp(&br.version),
+            RangeRequest_SortTarget::CREATE => ar.create_revision.cmp(&br.create_revision),
+            RangeRequest_SortTarget::MOD => ar.mod_revision.cmp(&br.mod_revision),
+            RangeRequest_SortTarget::VALUE => ar.value.cmp(&br.value),
+        });
+        if order == RangeRequest_SortOrder::DESCEND {
+            records.reverse();
+        }
+    }
+
+    let mut resp = RangeResponse::new();
+    resp.set_count(records.len() as i64);
+    if req.get_limit() > 0 && records.len() as i64 > req.get_limit() {
+        records.truncate(req.get_limit() as usize);
+        resp.set_more(true);
+    }
+    if !req.get_count_only() {
+        for (key, record) in &records {
+            let mut kv = to_key_value(key, record);
+            if req.get_keys_only() {
+                kv.clear_value();
+            }
+            resp.mut_kvs().push(kv);
+        }
+    }
+    resp
+}
+
+// Applies an encoded transaction and returns the encoded response.
+pub fn apply(writer: &mut Writer, data: &[u8], now: u64) -> ApplyResult {
+    let mut txn = TxnRequest::new();
+    if let Err(e) = txn.merge_from_bytes(data) {
+        return ApplyResult::InvalidArgument(format!("failed to decode transaction: {}", e));
+    }
+
+    // Leases are checked first, so that a transaction is either applied entirely or not at all.
+    if let Err(result) = check_leases(writer, txn.get_success())
+        .and_then(|_| check_leases(writer, txn.get_failure()))
+    {
+        return result;
+    }
+
+    match apply_txn(writer, &txn, now) {
+        Ok(mut resp) => {
+            resp.mut_header().set_revision(writer.revision() as i64);
+            match resp.write_to_bytes() {
+                Ok(data) => ApplyResult::Bytes(data),
+                Err(e) => ApplyResult::Error(format!("failed to encode response: {}", e)),
+            }
+        }
+        Err(e) => ApplyResult::Error(e.to_string()),
+    }
+}
+
+fn check_leases(writer: &Writer, ops: &[RequestOp]) -> Result<(), ApplyResult> {
+    for op in ops {
+        match &op.request {
+            Some(RequestOp_oneof_request::request_put(req))
+                if req.get_lease() != 0 && !req.get_ignore_lease() =>
+            {
+                match get_written_lease(writer, req.get_lease()) {
+                    Ok(Some(_)) => {}
+                    Ok(None) => return Err(ApplyResult::NotFound(LEASE_NOT_FOUND.to_string())),
+                    Err(e) => return Err(ApplyResult::Error(e.to_string())),
+                }
+            }
+            Some(RequestOp_oneof_request::request_txn(txn)) => {
+                check_leases(writer, txn.get_success())?;
+                check_leases(writer, txn.get_failure())?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn apply_txn(writer: &mut Writer, txn: &TxnRequest, now: u64) -> Result<TxnResponse, Error> {
+    let mut succeeded = true;
+    for cmp in txn.get_compare() {
+        if !compare(writer, cmp, now)? {
+            succeeded = false;
+            break;
+        }
+    }
+
+    let mut resp = TxnResponse::new();
+    resp.set_succeeded(succeeded);
+    let ops = if succeeded {
+        txn.get_success()
+    } else {
+        txn.get_failure()
+    };
+    for op in ops {
+        let mut response = ResponseOp::new();
+        match &op.request {
+            Some(RequestOp_oneof_request::request_range(req)) => {
+                let records = writer.range(req.get_key(), req.get_range_end(), now)?;
+                response.set_response_range(range_response(records, req));
+            }
+            Some(RequestOp_oneof_request::request_put(req)) => {
+                response.set_response_put(put(writer, req, now)?);
+            }
+            Some(RequestOp_oneof_request::request_delete_range(req)) => {
+                response.set_response_delete_range(delete_range(writer, req, now)?);
+            }
+            Some(RequestOp_oneof_request::request_txn(req)) => {
+                response.set_response_txn(apply_txn(writer, req, now)?);
+            }
+            None => {}
+        }
+        resp.mut_responses().push(response);
+    }
+    Ok(resp)
+}
+
+// A comparison on a range holds if it holds for every key in the range.
+fn compare(writer: &Writer, cmp: &Compare, now: u64) -> Result<bool, Error> {
+    if cmp.get_range_end().is_empty() {
+        let record = writer.get(cmp.get_key(), now)?;
+        return Ok(compare_record(cmp, record.as_ref()));
+    }
+    Ok(writer
+        .range(cmp.get_key(), cmp.get_range_end(), now)?
+        .iter()
+        .all(|(_, record)| compare_record(cmp, Some(record))))
+}
+
+// A missing key has a version, revisions and a lease of 0, and its value never matches.
+fn compare_record(cmp: &Compare, record: Option<&Record>) -> bool {
+    let ordering = match cmp.get_target() {
+        Compare_CompareTarget::VERSION => record
+            .map_or(0, |r| r.version as i64)
+            .cmp(&cmp.get_version()),
+        Compare_CompareTarget::CREATE => record
+            .map_or(0, |r| r.create_revision as i64)
+            .cmp(&cmp.get_create_revision()),
+        Compare_CompareTarget::MOD => record
+            .map_or(0, |r| r.mod_revision as i64)
+            .cmp(&cmp.get_mod_revision()),
+        Compare_CompareTarget::LEASE => record.map_or(0, |r| r.lease).cmp(&cmp.get_lease()),
+        Compare_CompareTarget::VALUE => match record {
+            Some(r) => r.value.as_slice().cmp(cmp.get_value()),
+            None => return false,
+        },
+    };
+    match cmp.get_result() {
+        Compare_CompareResult::EQUAL => ordering == Ordering::Equal,
+        Compare_CompareResult::GREATER => ordering == Ordering::Greater,
+        Compare_CompareResult::LESS => ordering == Ordering::Less,
+        Compare_CompareResult::NOT_EQUAL => ordering != Ordering::Equal,
+    }
+}
+
+fn put(writer: &mut Writer, req: &PutRequest, now: u64) -> Result<PutResponse, Error> {
+    let current = writer.get(req.get_key(), now)?;
+    let (value, lease) = match &current {
+        Some(current) => (
+            if req.get_ignore_value() {
+                current.value.clone()
+            } else {
+                req.get_value().to_vec()
+            },
+            if req.get_ignore_lease() {
+                current.lease
+            } else {
+                req.get_lease()
+            },
+        ),
+        None => (req.get_value().to_vec(), req.get_lease()),
+    };
+
+    let mut record = Record::new(value, None);
+    record.lease = lease;
+    let prev = writer.put(req.get_key(), record, now)?;
+    let mut resp = PutResponse::new();
+    if req.get_prev_kv() {
+        if let Some(prev) = prev {
+            resp.set_prev_kv(to_key_value(req.get_key(), &prev));
+        }
+    }
+    Ok(resp)
+}
+
+fn delete_range(
+    writer: &mut Writer,
+    req: &DeleteRangeRequest,
+    now: u64,
+) -> Result<DeleteRangeResponse, Error> {
+    let mut resp = DeleteRangeResponse::new();
+    for (key, record) in writer.range(req.get_key(), req.get_range_end(), now)? {
+        writer.delete(&key)?;
+        resp.set_deleted(resp.get_deleted() + 1);
+        if req.get_prev_kv() {
+            resp.mut_prev_kvs().push(to_key_value(&key, &record));
+        }
+    }
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::memory::MemoryEngine;
+    use crate::etcd::lease;
+
+    fn put_op(key: &[u8], value: &[u8]) -> RequestOp {
+        let mut req = PutRequest::new();
+        req.set_key(key.to_vec());
+        req.set_value(value.to_vec());
+        req.set_prev_kv(true);
+        let mut op = RequestOp::new();
+        op.set_request_put(req);
+        op
+    }
+
+    fn delete_op(key: &[u8], range_end: &[u8]) -> RequestOp {
+        let mut req = DeleteRangeRequest::new();
+        req.set_key(key.to_vec());
+        req.set_range_end(range_end.to_vec());
+        req.set_prev_kv(true);
+        let mut op = RequestOp::new();
+        op.set_request_delete_range(req);
+        op
+    }
+
+    fn range_op(key: &[u8]) -> RequestOp {
+        let mut req = RangeRequest::new();
+        req.set_key(key.to_vec());
+        let mut op = RequestOp::new();
+        op.set_request_range(req);
+        op
+    }
+
+    fn compare(
+        key: &[u8],
+        target: Compare_CompareTarget,
+        result: Compare_CompareResult,
+    ) -> Compare {
+        let mut cmp = Compare::new();
+        cmp.set_key(key.to_vec());
+        cmp.set_target(target);
+        cmp.set_result(result);
+        cmp
+    }
+
+    fn txn(db: &MemoryEngine, txn: TxnRequest) -> Result<TxnResponse, ApplyResult> {
+        let mut writer = Writer::new(db, None).unwrap();
+        let result = apply(&mut writer, &txn.write_to_bytes().unwrap(), 0);
+        writer.commit().unwrap();
+        match result {
+            ApplyResult::Bytes(data) => {
+                let mut resp = TxnResponse::new();
+                resp.merge_from_bytes(&data).unwrap();
+                Ok(resp)
+            }
+            result => Err(result),
+        }
+    }
+
+    fn put(db: &MemoryEngine, key: &[u8], value: &[u8]) -> PutResponse {
+        let mut req = TxnRequest::new();
+        req.mut_success().push(put_op(key, value));
+        txn(db, req).unwrap().mut_responses()[0].take_response_put()
+    }
+
+    fn range_keys(db: &MemoryEngine, req: &RangeRequest) -> (Vec<Vec<u8>>, RangeResponse) {
+        let resp = range(db, None, req, 0).unwrap();
+        let keys = resp
+            .get_kvs()
+            .iter()
+            .map(|kv| kv.get_key().to_vec())
+            .collect();
+        (keys, resp)
+    }
+
+    #[test]
+    fn test_range() {
+        let db = MemoryEngine::new();
+        for key in &[&b"a"[..], b"b1", b"b2", b"b3", b"c"] {
+            put(&db, key, b"value");
+        }
+        db.put(b"\x00meteora/users/root", b"secret").unwrap();
+
+        let mut req = RangeRequest::new();
+        req.set_key(b"b1".to_vec());
+        let (keys, resp) = range_keys(&db, &req);
+        assert_eq!(keys, vec![b"b1".to_vec()]);
+        assert_eq!(resp.get_kvs()[0].get_value(), b"value");
+        assert_eq!(resp.get_kvs()[0].get_create_revision(), 2);
+
+        // The prefix b.
+        req.set_key(b"b".to_vec());
+        req.set_range_end(b"c".to_vec());
+        let (keys, resp) = range_keys(&db, &req);
+        assert_eq!(keys, vec![b"b1".to_vec(), b"b2".to_vec(), b"b3".to_vec()]);
+        assert!(!resp.get_more());
+
+        req.set_limit(2);
+        let (keys, resp) = range_keys(&db, &req);
+        assert_eq!(keys, vec![b"b1".to_vec(), b"b2".to_vec()]);
+        assert_eq!(resp.get_count(), 3);
+        assert!(resp.get_more());
+
+        req.set_sort_order(RangeRequest_SortOrder::DESCEND);
+        let (keys, _) = range_keys(&db, &req);
+        assert_eq!(keys, vec![b"b3".to_vec(), b"b2".to_vec()]);
+
+        // Reserved keys are hidden from every range.
+        let mut req = RangeRequest::new();
+        req.set_key(vec![0]);
+        req.set_range_end(vec![0]);
+        let (keys, resp) = range_keys(&db, &req);
+        assert_eq!(keys.len(), 5);
+        assert_eq!(resp.get_count(), 5);
+        req.set_key(b"\x00meteora/users/root".to_vec());
+        req.clear_range_end();
+        assert!(range_keys(&db, &req).0.is_empty());
+    }
+
+    #[test]
+    fn test_put_prev_kv() {
+        let db = MemoryEngine::new();
+        let resp = put(&db, b"a", b"1");
+        assert!(!resp.has_prev_kv());
+
+        let resp = put(&db, b"a", b"2");
+        let prev = resp.get_prev_kv();
+        assert_eq!(prev.get_key(), b"a");
+        assert_eq!(prev.get_value(), b"1");
+        assert_eq!(prev.get_version(), 1);
+        assert_eq!(prev.get_mod_revision(), 1);
+
+        let mut req = RangeRequest::new();
+        req.set_key(b"a".to_vec());
+        let kv = &range_keys(&db, &req).1.get_kvs()[0];
+        assert_eq!(kv.get_value(), b"2");
+        assert_eq!(kv.get_version(), 2);
+        assert_eq!(kv.get_create_revision(), 1);
+        assert_eq!(kv.get_mod_revision(), 2);
+    }
+
+    #[test]
+    fn test_txn_compare() {
+        let db = MemoryEngine::new();
+
+        // Create the key only if it does not exist.
+        let mut req = TxnRequest::new();
+        req.mut_compare().push(compare(
+            b"a",
+            Compare_CompareTarget::VERSION,
+            Compare_CompareResult::EQUAL,
+        ));
+        req.mut_success().push(put_op(b"a", b"1"));
+        req.mut_failure().push(range_op(b"a"));
+        let resp = txn(&db, req.clone()).unwrap();
+        assert!(resp.get_succeeded());
+        assert!(resp.get_responses()[0].has_response_put());
+        assert_eq!(resp.get_header().get_revision(), 1);
+
+        let resp = txn(&db, req).unwrap();
+        assert!(!resp.get_succeeded());
+        let range = resp.get_responses()[0].get_response_range();
+        assert_eq!(range.get_kvs()[0].get_value(), b"1");
+        assert_eq!(resp.get_header().get_revision(), 1);
+
+        // Compare the value, and update it if it matches.
+        let mut cmp = compare(
+            b"a",
+            Compare_CompareTarget::VALUE,
+            Compare_CompareResult::EQUAL,
+        );
+        cmp.set_value(b"1".to_vec());
+        let mut req = TxnRequest::new();
+        req.mut_compare().push(cmp);
+        req.mut_success().push(put_op(b"a", b"2"));
+        req.mut_success().push(put_op(b"b", b"2"));
+        assert!(txn(&db, req.clone()).unwrap().get_succeeded());
+        assert!(!txn(&db, req).unwrap().get_succeeded());
+
+        // Every key of a range must match.
+        let mut cmp = compare(
+            b"a",
+            Compare_CompareTarget::MOD,
+            Compare_CompareResult::EQUAL,
+        );
+        cmp.set_range_end(b"c".to_vec());
+        cmp.set_mod_revision(2);
+        let mut req = TxnRequest::new();
+        req.mut_compare().push(cmp.clone());
+        assert!(txn(&db, req).unwrap().get_succeeded());
+        cmp.set_range_end(vec![0]);
+        put(&db, b"c", b"3");
+        let mut req = TxnRequest::new();
+        req.mut_compare().push(cmp);
+        assert!(!txn(&db, req).unwrap().get_succeeded());
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let db = MemoryEngine::new();
+        for key in &[&b"a"[..], b"b1", b"b2", b"c"] {
+            put(&db, key, b"value");
+        }
+
+        let mut req = TxnRequest::new();
+        req.mut_success().push(delete_op(b"b", b"c"));
+        let mut resp = txn(&db, req).unwrap();
+        let resp = resp.mut_responses()[0].take_response_delete_range();
+        assert_eq!(resp.get_deleted(), 2);
+        let prev_keys: Vec<&[u8]> = resp.get_prev_kvs().iter().map(|kv| kv.get_key()).collect();
+        assert_eq!(prev_keys, vec![&b"b1"[..], b"b2"]);
+
+        let mut req = RangeRequest::new();
+        req.set_key(vec![0]);
+        req.set_range_end(vec![0]);
+        assert_eq!(range_keys(&db, &req).0, vec![b"a".to_vec(), b"c".to_vec()]);
+
+        // Deleting nothing does not change the revision.
+        let mut req = TxnRequest::new();
+        req.mut_success().push(delete_op(b"b", b"c"));
+        let mut resp = txn(&db, req).unwrap();
+        assert_eq!(resp.get_header().get_revision(), 5);
+        assert_eq!(
+            resp.mut_responses()[0]
+                .take_response_delete_range()
+                .get_deleted(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_put_with_lease() {
+        let db = MemoryEngine::new();
+        let mut op = put_op(b"a", b"1");
+        op.mut_request_put().set_lease(1);
+        let mut req = TxnRequest::new();
+        req.mut_success().push(op);
+        assert_eq!(
+            txn(&db, req.clone()).unwrap_err(),
+            ApplyResult::NotFound(LEASE_NOT_FOUND.to_string())
+        );
+
+        let mut writer = Writer::new(&db, None).unwrap();
+        lease::grant(&mut writer, 1, 10, 0);
+        writer.commit().unwrap();
+        assert!(txn(&db, req).unwrap().get_succeeded());
+        let mut req = RangeRequest::new();
+        req.set_key(b"a".to_vec());
+        assert_eq!(range_keys(&db, &req).1.get_kvs()[0].get_lease(), 1);
+    }
+}
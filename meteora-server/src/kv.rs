@@ -1 +1,5 @@
+pub mod record;
+pub mod revision;
 pub mod server;
+pub mod session;
+pub mod watch;
@@ -0,0 +1,180 @@
+use std::io::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bincode::{DefaultOptions, Options};
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::KeyRing;
+use crate::engine::KvEngine;
+
+// The first byte of an encoded record, so that the format can be changed later. Values written
+// before records were introduced are stored as they are, and are read as records without
+// metadata.
+const RECORD_FORMAT_VERSION: u8 = 1;
+
+// The encoding of `bincode::serialize`, except that trailing bytes are rejected, so that a
+// legacy value is not taken for a record only because it starts like one.
+fn options() -> impl Options {
+    DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+}
+
+// A value as it is stored in the engine, along with its metadata.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Record {
+    pub value: Vec<u8>,
+    // Milliseconds since the UNIX epoch. The record is treated as missing from then on.
+    pub expires_at: Option<u64>,
+    // The revision in which the key was created and last modified, and the number of times it
+    // has been modified since it was created. See `kv::revision`.
+    pub create_revision: u64,
+    pub mod_revision: u64,
+    pub version: u64,
+    // The ID of the etcd lease attached to the key, or 0.
+    pub lease: i64,
+    // Opaque flags set by the client, as in memcached.
+    pub flags: u32,
+}
+
+impl Record {
+    pub fn new(value: Vec<u8>, expires_at: Option<u64>) -> Record {
+        Record {
+            value,
+            expires_at,
+            create_revision: 0,
+            mod_revision: 0,
+            version: 0,
+            lease: 0,
+            flags: 0,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= now,
+            None => false,
+        }
+    }
+
+    // Serializes the record of the key, encrypting it if a key ring is given.
+    pub fn encode(&self, key: &[u8], key_ring: Option<&KeyRing>) -> Vec<u8> {
+        let mut data = vec![RECORD_FORMAT_VERSION];
+        options().serialize_into(&mut data, self).unwrap();
+        match key_ring {
+            Some(key_ring) => key_ring.encrypt(key, &data),
+            None => data,
+        }
+    }
+
+    pub fn decode(key: &[u8], data: &[u8], key_ring: Option<&KeyRing>) -> Result<Record, Error> {
+        let data = match key_ring {
+            Some(key_ring) => key_ring.decrypt(key, data)?,
+            None => data.to_vec(),
+        };
+        if data.first() == Some(&RECORD_FORMAT_VERSION) {
+            if let Ok(record) = options().deserialize(&data[1..]) {
+                return Ok(record);
+            }
+        }
+        Ok(Record::new(data, None))
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+// Reads a record from the DB, including an expired one, since whether it has expired
+// depends on the time the caller uses.
+pub fn get_record(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    key: &[u8],
+) -> Result<Option<Record>, Error> {
+    match db.get(key)? {
+        Some(data) => Record::decode(key, &data, key_ring).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Reads a record from the DB. Expired records are not returned.
+pub fn get_live_record(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    key: &[u8],
+) -> Result<Option<Record>, Error> {
+    let now = now_millis();
+    Ok(get_record(db, key_ring, key)?.filter(|record| !record.is_expired(now)))
+}
+
+// Reads a value from the DB. Expired values are not returned.
+pub fn get_value(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    key: &[u8],
+) -> Result<Option<Vec<u8>>, Error> {
+    Ok(get_live_record(db, key_ring, key)?.map(|record| record.value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "1 7f6d0e5a3c1b2a4d8e9f0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f";
+
+    fn record() -> Record {
+        let mut record = Record::new(b"value".to_vec(), Some(1000));
+        record.create_revision = 2;
+        record.mod_revision = 3;
+        record.version = 2;
+        record.lease = 7;
+        record.flags = 42;
+        record
+    }
+
+    fn assert_same(a: &Record, b: &Record) {
+        assert_eq!(a.value, b.value);
+        assert_eq!(a.expires_at, b.expires_at);
+        assert_eq!(a.create_revision, b.create_revision);
+        assert_eq!(a.mod_revision, b.mod_revision);
+        assert_eq!(a.version, b.version);
+        assert_eq!(a.lease, b.lease);
+        assert_eq!(a.flags, b.flags);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = record().encode(b"key", None);
+        assert_eq!(data[0], RECORD_FORMAT_VERSION);
+        assert_same(&Record::decode(b"key", &data, None).unwrap(), &record());
+    }
+
+    #[test]
+    fn test_encrypted_round_trip() {
+        let key_ring = KeyRing::parse(KEY, "test.keys").unwrap();
+        let data = record().encode(b"key", Some(&key_ring));
+        let decoded = Record::decode(b"key", &data, Some(&key_ring)).unwrap();
+        assert_same(&decoded, &record());
+        assert!(Record::decode(b"other", &data, Some(&key_ring)).is_err());
+    }
+
+    #[test]
+    fn test_legacy_value() {
+        for value in &[&b""[..], b"value", b"\x01", b"\x01value"] {
+            let decoded = Record::decode(b"key", value, None).unwrap();
+            assert_same(&decoded, &Record::new(value.to_vec(), None));
+        }
+    }
+
+    #[test]
+    fn test_expired() {
+        let record = record();
+        assert!(!record.is_expired(999));
+        assert!(record.is_expired(1000));
+        assert!(!Record::new(Vec::new(), None).is_expired(u64::MAX));
+    }
+}
@@ -0,0 +1,381 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind};
+use std::iter;
+use std::ops::Bound;
+
+use crate::auth::server::is_reserved_key;
+use crate::encryption::KeyRing;
+use crate::engine::{KvEngine, KvIterator, WriteBatch};
+use crate::kv::record::{get_record, Record};
+
+// The revision of the store is a counter that is incremented by each Raft entry that changes
+// a key, in the same way as etcd. Reserved keys are not versioned.
+const REVISION_KEY: &[u8] = b"\x00meteora/revision";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EventKind {
+    Put,
+    Delete,
+}
+
+// A change of a key. `record` is the new record of a put, and `prev` is the record before
+// the change, if any.
+#[derive(Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    pub key: Vec<u8>,
+    pub record: Option<Record>,
+    pub prev: Option<Record>,
+}
+
+pub fn current_revision(db: &dyn KvEngine) -> Result<u64, Error> {
+    match db.get(REVISION_KEY)? {
+        Some(data) => {
+            let bytes: [u8; 8] = data.as_slice().try_into().map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid revision: {:?}", data),
+                )
+            })?;
+            Ok(u64::from_be_bytes(bytes))
+        }
+        None => Ok(0),
+    }
+}
+
+// Writes the changes of a Raft entry, which all get the same revision. The changes are kept
+// in memory and read back by the writer until they are written in a single batch by `commit`,
+// so that an entry is either applied as a whole or not at all.
+pub struct Writer<'a> {
+    db: &'a dyn KvEngine,
+    pub key_ring: Option<&'a KeyRing>,
+    base: u64,
+    // The encoded records put by the entry, or None for the deleted keys.
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    events: Vec<Event>,
+    written: bool,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(db: &'a dyn KvEngine, key_ring: Option<&'a KeyRing>) -> Result<Writer<'a>, Error> {
+        Ok(Writer {
+            db,
+            key_ring,
+            base: current_revision(db)?,
+            writes: BTreeMap::new(),
+            events: Vec::new(),
+            written: false,
+        })
+    }
+
+    // The revision of the store, including the changes made so far.
+    pub fn revision(&self) -> u64 {
+        if self.written {
+            self.base + 1
+        } else {
+            self.base
+        }
+    }
+
+    // Returns the record of the key, including an expired one.
+    pub fn get_record(&self, key: &[u8]) -> Result<Option<Record>, Error> {
+        match self.writes.get(key) {
+            Some(Some(data)) => Record::decode(key, data, self.key_ring).map(Some),
+            Some(None) => Ok(None),
+            None => get_record(self.db, self.key_ring, key),
+        }
+    }
+
+    // Returns the record of the key if it has not expired at `now`.
+    pub fn get(&self, key: &[u8], now: u64) -> Result<Option<Record>, Error> {
+        Ok(self.get_record(key)?.filter(|r| !r.is_expired(now)))
+    }
+
+    // The pairs with a key greater than or equal to `from`, including the changes made so far.
+    pub fn iterator(&self, from: &[u8]) -> KvIterator {
+        let mut stored = self.db.iterator(from).peekable();
+        let mut writes = self
+            .writes
+            .range::<[u8], _>((Bound::Included(from), Bound::Unbounded))
+            .peekable();
+        Box::new(iter::from_fn(move || loop {
+            let order = match (stored.peek(), writes.peek()) {
+                (None, None) => return None,
+                // An error is returned as soon as it is reached.
+                (Some(Err(_)), _) | (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(Ok((k, _))), Some((written, _))) => k.as_slice().cmp(written.as_slice()),
+            };
+            if order == Ordering::Less {
+                return stored.next();
+            }
+            if order == Ordering::Equal {
+                stored.next();
+            }
+            // A deleted key is skipped.
+            if let (k, Some(v)) = writes.next().unwrap() {
+                return Some(Ok((k.clone(), v.clone())));
+            }
+        }))
+    }
+
+    // Returns the unexpired records in [key, range_end), in the same way as etcd: an empty
+    // range_end means the key only, and "\0" means all keys greater than or equal to the key.
+    pub fn range(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        now: u64,
+    ) -> Result<Vec<(Vec<u8>, Record)>, Error> {
+        if range_end.is_empty() {
+            return Ok(self
+                .get(key, now)?
+                .filter(|_| !is_reserved_key(key))
+                .map(|record| (key.to_vec(), record))
+                .into_iter()
+                .collect());
+        }
+        collect_range(self.iterator(key), self.key_ring, range_end, now)
+    }
+
+    // Puts the record and returns the previous one, if it has not expired at `now`. The
+    // revisions and the version of the record are set here.
+    pub fn put(
+        &mut self,
+        key: &[u8],
+        mut record: Record,
+        now: u64,
+    ) -> Result<Option<Record>, Error> {
+        let prev = self.get(key, now)?;
+        if !is_reserved_key(key) {
+            self.written = true;
+            let revision = self.revision();
+            record.create_revision = prev.as_ref().map_or(revision, |p| p.create_revision);
+            record.mod_revision = revision;
+            record.version = prev.as_ref().map_or(1, |p| p.version + 1);
+        }
+        self.writes
+            .insert(key.to_vec(), Some(record.encode(key, self.key_ring)));
+        if !is_reserved_key(key) {
+            self.events.push(Event {
+                kind: EventKind::Put,
+                key: key.to_vec(),
+                record: Some(record),
+                prev: prev.clone(),
+            });
+        }
+        Ok(prev)
+    }
+
+    // Deletes the key and returns its record, including an expired one. Whether the revision
+    // changes only depends on the data, so that it is the same on every node.
+    pub fn delete(&mut self, key: &[u8]) -> Result<Option<Record>, Error> {
+        let prev = match self.get_record(key)? {
+            Some(prev) => prev,
+            None => return Ok(None),
+        };
+        self.writes.insert(key.to_vec(), None);
+        if !is_reserved_key(key) {
+            self.written = true;
+            self.events.push(Event {
+                kind: EventKind::Delete,
+                key: key.to_vec(),
+                record: None,
+                prev: Some(prev.clone()),
+            });
+        }
+        Ok(Some(prev))
+    }
+
+    // Writes the changes along with the revision, and returns it along with the events.
+    pub fn commit(self) -> Result<(u64, Vec<Event>), Error> {
+        let revision = self.revision();
+        let mut batch = WriteBatch::new();
+        for (key, value) in &self.writes {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        if self.written {
+            batch.put(REVISION_KEY, &revision.to_be_bytes());
+        }
+        if !batch.ops.is_empty() {
+            self.db.write_batch(batch)?;
+        }
+        Ok((revision, self.events))
+    }
+}
+
+pub fn range(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    key: &[u8],
+    range_end: &[u8],
+    now: u64,
+) -> Result<Vec<(Vec<u8>, Record)>, Error> {
+    let mut records = Vec::new();
+    if range_end.is_empty() {
+        if !is_reserved_key(key) {
+            if let Some(record) = get_record(db, key_ring, key)? {
+                if !record.is_expired(now) {
+                    records.push((key.to_vec(), record));
+                }
+            }
+        }
+        return Ok(records);
+    }
+
+    let snapshot = db.snapshot()?;
+    collect_range(snapshot.iterator(key), key_ring, range_end, now)
+}
+
+// Collects the unexpired records of the pairs up to range_end, which must not be empty.
+fn collect_range(
+    pairs: KvIterator,
+    key_ring: Option<&KeyRing>,
+    range_end: &[u8],
+    now: u64,
+) -> Result<Vec<(Vec<u8>, Record)>, Error> {
+    let mut records = Vec::new();
+    for item in pairs {
+        let (k, v) = item?;
+        if range_end != [0] && k.as_slice() >= range_end {
+            break;
+        }
+        if is_reserved_key(&k) {
+            continue;
+        }
+        let record = Record::decode(&k, &v, key_ring)?;
+        if !record.is_expired(now) {
+            records.push((k.to_vec(), record));
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::memory::MemoryEngine;
+    use crate::engine::KvSnapshot;
+
+    // An engine whose pairs can't all be read. The ones after "b" fail.
+    struct BrokenEngine(MemoryEngine);
+
+    impl KvEngine for BrokenEngine {
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+            self.0.get(key)
+        }
+
+        fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+            self.0.write_batch(batch)
+        }
+
+        fn iterator(&self, from: &[u8]) -> KvIterator {
+            Box::new(self.0.iterator(from).map(|item| match item {
+                Ok((k, _)) if k.as_slice() > &b"b"[..] => {
+                    Err(Error::new(ErrorKind::Other, "broken"))
+                }
+                item => item,
+            }))
+        }
+
+        fn snapshot(&self) -> Result<Box<dyn KvSnapshot + '_>, Error> {
+            self.0.snapshot()
+        }
+
+        fn checkpoint(&self, path: &str) -> Result<(), Error> {
+            self.0.checkpoint(path)
+        }
+    }
+
+    fn put(writer: &mut Writer, key: &[u8], value: &[u8]) {
+        writer
+            .put(key, Record::new(value.to_vec(), None), 0)
+            .unwrap();
+    }
+
+    fn keys(writer: &Writer, from: &[u8]) -> Vec<Vec<u8>> {
+        writer.iterator(from).map(|item| item.unwrap().0).collect()
+    }
+
+    #[test]
+    fn test_writes_on_commit() {
+        let db = MemoryEngine::new();
+        let mut writer = Writer::new(&db, None).unwrap();
+        put(&mut writer, b"a", b"1");
+        assert_eq!(writer.get(b"a", 0).unwrap().unwrap().value, b"1".to_vec());
+        assert_eq!(db.get(b"a").unwrap(), None);
+        assert_eq!(current_revision(&db).unwrap(), 0);
+
+        let (revision, events) = writer.commit().unwrap();
+        assert_eq!(revision, 1);
+        assert_eq!(events.len(), 1);
+        assert!(db.get(b"a").unwrap().is_some());
+        assert_eq!(current_revision(&db).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_read_own_writes() {
+        let db = MemoryEngine::new();
+        let mut writer = Writer::new(&db, None).unwrap();
+        put(&mut writer, b"a", b"1");
+        put(&mut writer, b"c", b"1");
+        put(&mut writer, b"e", b"1");
+        writer.commit().unwrap();
+
+        let mut writer = Writer::new(&db, None).unwrap();
+        put(&mut writer, b"b", b"2");
+        put(&mut writer, b"c", b"2");
+        writer.delete(b"e").unwrap();
+        put(&mut writer, b"f", b"2");
+        assert_eq!(
+            keys(&writer, b"a"),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"f".to_vec()]
+        );
+        assert_eq!(keys(&writer, b"c"), vec![b"c".to_vec(), b"f".to_vec()]);
+        assert!(writer.get_record(b"e").unwrap().is_none());
+
+        let records = writer.range(b"b", b"d", 0).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].1.value, b"2".to_vec());
+        assert_eq!(records[1].1.version, 2);
+        assert_eq!(records[1].1.create_revision, 1);
+        assert_eq!(records[1].1.mod_revision, 2);
+    }
+
+    #[test]
+    fn test_no_changes() {
+        let db = MemoryEngine::new();
+        let mut writer = Writer::new(&db, None).unwrap();
+        assert!(writer.delete(b"a").unwrap().is_none());
+        let (revision, events) = writer.commit().unwrap();
+        assert_eq!(revision, 0);
+        assert!(events.is_empty());
+        assert!(db.iterator(b"").next().is_none());
+    }
+
+    #[test]
+    fn test_read_error() {
+        let db = BrokenEngine(MemoryEngine::new());
+        let mut writer = Writer::new(&db, None).unwrap();
+        put(&mut writer, b"a", b"1");
+        put(&mut writer, b"c", b"1");
+        writer.commit().unwrap();
+
+        let mut writer = Writer::new(&db, None).unwrap();
+        put(&mut writer, b"b", b"2");
+        put(&mut writer, b"d", b"2");
+        // The error has no key, so it is returned as soon as it is read, even before the
+        // writes that come first in key order.
+        let items: Vec<_> = writer.iterator(b"a").collect();
+        assert_eq!(items[0].as_ref().unwrap().0, b"a".to_vec());
+        assert_eq!(items[1].as_ref().unwrap_err().to_string(), "broken");
+
+        let err = writer.range(b"a", b"\0", 0).unwrap_err();
+        assert_eq!(err.to_string(), "broken");
+    }
+}
@@ -1,35 +1,174 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+use futures::future::{self, Either};
 use futures::Future;
-use grpcio::{RpcContext, UnarySink};
+use grpcio::{
+    CallOption, Channel, ClientUnaryReceiver, Environment, MetadataBuilder, RpcContext, UnarySink,
+};
 use log::*;
+use opentelemetry::trace::{TraceContextExt, Tracer};
+use opentelemetry::Context;
 use raft::storage::MemStorage;
-use rocksdb::DB;
 use serde::{Deserialize, Serialize};
 
 use meteora_proto::proto::common::{NodeAddress, State};
-use meteora_proto::proto::kv::{DeleteReply, DeleteReq, GetReply, GetReq, PutReply, PutReq};
-use meteora_proto::proto::kv_grpc::KvService;
+use meteora_proto::proto::kv::{
+    BatchOp_oneof_op, BatchReply, BatchReq, DeleteReply, DeleteReq, GetReply, GetReq, IncrReply,
+    IncrReq, KeyValue, PutCondition, PutReply, PutReq, ScanReply, ScanReq,
+};
+use meteora_proto::proto::kv_grpc::{KvService, KvServiceClient};
 
-use crate::raft::config;
+use crate::auth::server::{authorize, authorize_range, is_reserved_key, Access, AuthServer};
+use crate::auth::token;
+use crate::encryption::{self, KeyRing};
+use crate::engine::KvEngine;
+use crate::etcd;
+use crate::etcd::lease;
+use crate::etcd::server::EtcdServer;
+use crate::kv::record::{get_live_record, now_millis, Record};
+use crate::kv::revision::Writer;
+use crate::kv::session;
+use crate::kv::watch::WatchHub;
+use crate::metrics;
+use crate::raft::config::{self, RaftConfig};
 use crate::raft::server::RaftServer;
+use crate::storage::{Engine, StorageConfig};
+use crate::tls::{self, TlsConfig};
+use crate::trace;
+
+// A forwarded write carries the number of times it has been forwarded in this metadata.
+const FORWARD_HOPS_KEY: &str = "meteora-forward-hops";
+// A write is not forwarded further than this, so that it can't loop while the leader changes.
+const MAX_FORWARD_HOPS: u32 = 2;
+// The number of committed entries waiting to be applied. The Raft loop waits when it is full,
+// so that it doesn't commit faster than the disk can apply.
+const APPLY_QUEUE_SIZE: usize = 1024;
 
 #[derive(Clone)]
 pub struct KVServer {
-    db: Arc<DB>,
+    db: Arc<dyn KvEngine>,
     sender: Sender<config::Msg>,
-    seq: u64,
+    // Shared with the other services, since the callbacks of all proposals are kept together.
+    seq: Arc<AtomicU64>,
     node_id: u64,
+    auth_enabled: bool,
+    key_ring: Option<Arc<KeyRing>>,
+    tls: Option<TlsConfig>,
+    // The environment that a follower forwards writes to the leader on, instead of answering
+    // WRONG_LEADER, if forwarding is enabled.
+    forward_env: Option<Arc<Environment>>,
+    // Channels to the leaders that writes have been forwarded to, by address.
+    forward_channels: Arc<Mutex<HashMap<String, Channel>>>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub enum Op {
-    Put { key: Vec<u8>, val: Vec<u8> },
-    Delete { key: Vec<u8> },
+    // `now` is the time of the proposal, so that every node decides whether the current value
+    // has expired in the same way.
+    Put {
+        key: Vec<u8>,
+        val: Vec<u8>,
+        expires_at: Option<u64>,
+        // Whether the expiration time of the current value is kept instead of `expires_at`.
+        keep_ttl: bool,
+        flags: u32,
+        condition: Condition,
+        now: u64,
+    },
+    // `now` is the time of the proposal, so that the cached result of a retried delete is
+    // the same on every node.
+    Delete {
+        key: Vec<u8>,
+        now: u64,
+    },
+    Incr {
+        key: Vec<u8>,
+        delta: i64,
+        now: u64,
+    },
+    Batch {
+        ops: Vec<Op>,
+    },
+    // An etcdserverpb.TxnRequest, encoded in the protobuf format.
+    EtcdTxn {
+        txn: Vec<u8>,
+        now: u64,
+    },
+    LeaseGrant {
+        id: i64,
+        ttl: i64,
+        now: u64,
+    },
+    LeaseRevoke {
+        id: i64,
+    },
+    LeaseKeepAlive {
+        id: i64,
+        now: u64,
+    },
+    // A login. The token expires an hour after `now`.
+    IssueToken {
+        token: String,
+        name: String,
+        now: u64,
+    },
+    // A write of a client, applied only once even if the client retries it.
+    Session {
+        client_id: u64,
+        request_seq: u64,
+        op: Box<Op>,
+        now: u64,
+    },
+}
+
+impl Op {
+    pub fn put(key: Vec<u8>, val: Vec<u8>) -> Op {
+        Op::Put {
+            key,
+            val,
+            expires_at: None,
+            keep_ttl: false,
+            flags: 0,
+            condition: Condition::Always,
+            now: now_millis(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Always,
+    NotExists,
+    Exists,
+    // The key exists and its mod revision is the given one.
+    Version(u64),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ApplyResult {
+    // Whether the value was put, or whether the key existed and was deleted.
+    Applied(bool),
+    Integer(i64),
+    Batch(Vec<ApplyResult>),
+    // A response encoded in the protobuf format.
+    Bytes(Vec<u8>),
+    NotFound(String),
+    InvalidArgument(String),
+    Error(String),
+    // The proposal was dropped before being applied, for example because the leader changed.
+    Dropped,
+}
+
+// A committed operation sent to the apply daemon. `cb` is set on the node that proposed it.
+pub struct Apply {
+    pub op: Op,
+    pub cx: Context,
+    pub cb: Option<Box<dyn FnOnce(ApplyResult) + Send>>,
 }
 
 impl KVServer {
@@ -39,53 +178,238 @@ impl KVServer {
         node_id: u64,
         node_address: NodeAddress,
         addresses: HashMap<u64, NodeAddress>,
-    ) -> (KVServer, RaftServer) {
-        let db = DB::open_default(&db_path).unwrap();
+        tls: Option<TlsConfig>,
+        auth_enabled: bool,
+        key_ring: Option<KeyRing>,
+        raft_config: RaftConfig,
+        storage_config: StorageConfig,
+    ) -> Result<(KVServer, RaftServer, AuthServer, EtcdServer), std::io::Error> {
+        let db = storage_config.open(&db_path)?;
+        encryption::check_data_directory(db.as_ref(), key_ring.as_ref())?;
 
         let (rs, rr) = mpsc::channel();
-        let (apply_s, apply_r) = mpsc::channel();
+        let (apply_s, apply_r) = mpsc::sync_channel(APPLY_QUEUE_SIZE);
+        let raft_tls = tls.clone();
+        let rs_raft = rs.clone();
         thread::spawn(move || {
-            config::init_and_run(raft_storage, rr, apply_s, node_id, node_address, addresses);
+            config::init_and_run(
+                raft_storage,
+                rr,
+                rs_raft,
+                apply_s,
+                node_id,
+                node_address,
+                addresses,
+                raft_tls,
+                raft_config,
+            );
         });
 
         let kv_server = KVServer {
-            db: Arc::new(db),
+            db,
             sender: rs.clone(),
-            seq: 0,
+            seq: Arc::new(AtomicU64::new(0)),
             node_id,
+            auth_enabled,
+            key_ring: key_ring.map(Arc::new),
+            tls,
+            forward_env: None,
+            forward_channels: Arc::new(Mutex::new(HashMap::new())),
         };
-        let raft_server = RaftServer::new(rs, node_id);
+        let raft_server = RaftServer::new(
+            rs.clone(),
+            kv_server.seq.clone(),
+            node_id,
+            storage_config.status(),
+        );
+        let auth_server = AuthServer::new(
+            kv_server.db.clone(),
+            kv_server.key_ring.clone(),
+            rs.clone(),
+            kv_server.seq.clone(),
+            node_id,
+        );
+        let watch_hub = Arc::new(WatchHub::new());
+        let etcd_server = EtcdServer::new(
+            kv_server.db.clone(),
+            kv_server.key_ring.clone(),
+            rs,
+            kv_server.seq.clone(),
+            node_id,
+            watch_hub.clone(),
+        );
+
+        if storage_config.engine == Engine::Rocksdb {
+            metrics::register_rocksdb_collector(kv_server.db.clone());
+        }
 
         let db = kv_server.db.clone();
+        let key_ring = kv_server.key_ring.clone();
         thread::spawn(move || {
-            apply_daemon(apply_r, db);
+            apply_daemon(apply_r, db, key_ring, watch_hub);
         });
 
-        return (kv_server, raft_server);
+        Ok((kv_server, raft_server, auth_server, etcd_server))
+    }
+
+    // Makes a follower forward writes to the leader on `env`, which is the environment of the
+    // key-value service.
+    pub fn forward_writes(&mut self, env: Arc<Environment>) {
+        self.forward_env = Some(env);
+    }
+
+    // Sends a write that this follower can't serve to the leader, and returns the reply of the
+    // leader to come. None is returned if the write can't be forwarded, so that WRONG_LEADER is
+    // answered instead.
+    fn forward<R, F>(
+        &self,
+        ctx: &RpcContext,
+        cx: &Context,
+        leader_id: u64,
+        addresses: &HashMap<u64, NodeAddress>,
+        f: F,
+    ) -> Option<ClientUnaryReceiver<R>>
+    where
+        F: FnOnce(&KvServiceClient, CallOption) -> grpcio::Result<ClientUnaryReceiver<R>>,
+    {
+        let env = self.forward_env.as_ref()?;
+        let hops = forward_hops(ctx);
+        if hops >= MAX_FORWARD_HOPS {
+            debug!("hop limit has been reached: hops={}", hops);
+            return None;
+        }
+        // The leader is unknown during an election.
+        let address = match addresses.get(&leader_id) {
+            Some(address) if leader_id != self.node_id => address.get_kv_address().to_string(),
+            _ => return None,
+        };
+
+        let ch = {
+            let mut channels = self.forward_channels.lock().unwrap();
+            match channels.get(&address) {
+                Some(ch) => ch.clone(),
+                None => {
+                    let ch = match tls::connect(Arc::clone(env), &address, self.tls.as_ref()) {
+                        Ok(ch) => ch,
+                        Err(e) => {
+                            error!(
+                                "failed to connect to leader: address={}, error={}",
+                                address, e
+                            );
+                            return None;
+                        }
+                    };
+                    channels.insert(address.clone(), ch.clone());
+                    ch
+                }
+            }
+        };
+
+        // The leader authorizes the write again with the same token.
+        let mut builder = MetadataBuilder::with_capacity(3);
+        for (key, value) in ctx.request_headers().iter() {
+            if key == "authorization" {
+                builder
+                    .add_str(key, &String::from_utf8_lossy(value))
+                    .unwrap();
+            }
+        }
+        builder
+            .add_str(FORWARD_HOPS_KEY, &(hops + 1).to_string())
+            .unwrap();
+        trace::inject(cx, &mut builder);
+        let opt = CallOption::default()
+            .headers(builder.build())
+            .timeout(Duration::from_secs(2));
+
+        debug!(
+            "forward write to leader: id={}, address={}",
+            leader_id, address
+        );
+        match f(&KvServiceClient::new(ch), opt) {
+            Ok(receiver) => Some(receiver),
+            Err(e) => {
+                warn!(
+                    "failed to forward write: leader_id={}, error={:?}",
+                    leader_id, e
+                );
+                None
+            }
+        }
+    }
+
+    fn authorize(&self, ctx: &RpcContext, key: &[u8], access: Access) -> Result<(), State> {
+        if is_reserved_key(key) {
+            debug!("key is reserved: key={:?}", key);
+            return Err(State::PERMISSION_DENIED);
+        }
+        if self.auth_enabled {
+            authorize(&self.db, self.key_ring.as_deref(), ctx, key, access)?;
+        }
+        Ok(())
+    }
+
+    // Reserved keys are never scanned, so only the roles of the user are checked.
+    fn authorize_range(
+        &self,
+        ctx: &RpcContext,
+        key: &[u8],
+        range_end: &[u8],
+        access: Access,
+    ) -> Result<(), State> {
+        if self.auth_enabled {
+            authorize_range(
+                &self.db,
+                self.key_ring.as_deref(),
+                ctx,
+                key,
+                range_end,
+                access,
+            )?;
+        }
+        Ok(())
     }
 }
 
 impl KvService for KVServer {
     fn get(&mut self, ctx: RpcContext, req: GetReq, sink: UnarySink<GetReply>) {
+        let _timer = metrics::start_request_timer("kv", "get");
+        let _span = trace::tracer().start_with_context("KVServer::get", trace::extract(&ctx));
+
+        if let Err(state) = self.authorize(&ctx, req.get_key(), Access::Read) {
+            let mut reply = GetReply::new();
+            reply.set_state(state);
+            metrics::count_request("kv", "get", state);
+            let f = sink
+                .success(reply)
+                .map_err(move |err| error!("failed to reply: {:?}", err));
+            ctx.spawn(f);
+            return;
+        }
+
         let (s1, r1) = mpsc::channel();
         let db = Arc::clone(&self.db);
+        let key_ring = self.key_ring.clone();
         let sender = self.sender.clone();
         let node_id = self.node_id;
 
-        self.seq += 1;
-
         sender
             .send(config::Msg::Read {
                 cb: Box::new(
                     move |leader_id: i32, addresses: HashMap<u64, NodeAddress>| {
                         // Get
                         let mut reply = GetReply::new();
-                        let (state, value) = match db.get(req.get_key()) {
-                            Ok(Some(v)) => (State::OK, v),
-                            Ok(None) => (State::NOT_FOUND, Vec::new()),
+                        let state = match get_live_record(&db, key_ring.as_deref(), req.get_key()) {
+                            Ok(Some(record)) => {
+                                reply.set_value(record.value);
+                                reply.set_flags(record.flags);
+                                reply.set_version(record.mod_revision);
+                                State::OK
+                            }
+                            Ok(None) => State::NOT_FOUND,
                             Err(e) => {
                                 error!("failed to get value: {:?}", e);
-                                (State::IO_ERROR, Vec::new())
+                                State::IO_ERROR
                             }
                         };
                         reply.set_state(state);
@@ -96,7 +420,6 @@ impl KvService for KVServer {
                             // leader
                             reply.set_leader_id(node_id);
                         }
-                        reply.set_value(value);
                         reply.set_address_map(addresses);
                         s1.send(reply).expect("callback channel closed");
                     },
@@ -113,6 +436,8 @@ impl KvService for KVServer {
             }
         };
 
+        metrics::count_request("kv", "get", reply.get_state());
+
         let f = sink
             .success(reply.clone())
             .map_err(move |err| error!("failed to reply: {:?}", err));
@@ -120,23 +445,36 @@ impl KvService for KVServer {
     }
 
     fn put(&mut self, ctx: RpcContext, req: PutReq, sink: UnarySink<PutReply>) {
+        let _timer = metrics::start_request_timer("kv", "put");
+        let span = trace::tracer().start_with_context("KVServer::put", trace::extract(&ctx));
+        let cx = Context::current_with_span(span);
+
+        if let Err(state) = self.authorize(&ctx, req.get_key(), Access::Write) {
+            let mut reply = PutReply::new();
+            reply.set_state(state);
+            metrics::count_request("kv", "put", state);
+            let f = sink
+                .success(reply)
+                .map_err(move |err| error!("failed to reply: {:?}", err));
+            ctx.spawn(f);
+            return;
+        }
+
         let (s1, r1) = mpsc::channel();
         let sender = self.sender.clone();
-        let op = Op::Put {
-            key: req.get_key().to_vec(),
-            val: req.get_value().to_vec(),
-        };
-        let seq = self.seq;
+        let op = session_op(put_op(&req), req.get_client_id(), req.get_request_seq());
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
         let node_id = self.node_id;
 
-        self.seq += 1;
-
         sender
             .send(config::Msg::Propose {
                 seq,
                 op,
+                cx: cx.clone(),
                 cb: Box::new(
-                    move |leader_id: i32, addresses: HashMap<u64, NodeAddress>| {
+                    move |leader_id: i32,
+                          addresses: HashMap<u64, NodeAddress>,
+                          result: Option<ApplyResult>| {
                         let mut reply = PutReply::new();
                         if leader_id >= 0 {
                             // follower
@@ -144,7 +482,13 @@ impl KvService for KVServer {
                             reply.set_leader_id(leader_id as u64);
                         } else {
                             // leader
-                            reply.set_state(State::OK);
+                            match result {
+                                Some(ApplyResult::Applied(applied)) => {
+                                    reply.set_state(State::OK);
+                                    reply.set_applied(applied);
+                                }
+                                result => reply.set_state(result_state(result)),
+                            }
                             reply.set_leader_id(node_id);
                         }
                         reply.set_address_map(addresses);
@@ -162,30 +506,57 @@ impl KvService for KVServer {
                 r
             }
         };
-
-        let f = sink
-            .success(reply.clone())
-            .map_err(move |err| error!("failed to reply: {:?}", err));
-        ctx.spawn(f);
+        let forwarded = match reply.get_state() {
+            State::WRONG_LEADER => self.forward(
+                &ctx,
+                &cx,
+                reply.get_leader_id(),
+                reply.get_address_map(),
+                |client, opt| client.put_async_opt(&req, opt),
+            ),
+            _ => None,
+        };
+        reply_forwarded(&ctx, sink, "put", reply, forwarded, PutReply::get_state);
     }
 
     fn delete(&mut self, ctx: RpcContext, req: DeleteReq, sink: UnarySink<DeleteReply>) {
+        let _timer = metrics::start_request_timer("kv", "delete");
+        let span = trace::tracer().start_with_context("KVServer::delete", trace::extract(&ctx));
+        let cx = Context::current_with_span(span);
+
+        if let Err(state) = self.authorize(&ctx, req.get_key(), Access::Write) {
+            let mut reply = DeleteReply::new();
+            reply.set_state(state);
+            metrics::count_request("kv", "delete", state);
+            let f = sink
+                .success(reply)
+                .map_err(move |err| error!("failed to reply: {:?}", err));
+            ctx.spawn(f);
+            return;
+        }
+
         let (s1, r1) = mpsc::channel();
         let sender = self.sender.clone();
-        let op = Op::Delete {
-            key: req.get_key().to_vec(),
-        };
-        let seq = self.seq;
+        let op = session_op(
+            Op::Delete {
+                key: req.get_key().to_vec(),
+                now: now_millis(),
+            },
+            req.get_client_id(),
+            req.get_request_seq(),
+        );
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
         let node_id = self.node_id;
 
-        self.seq += 1;
-
         sender
             .send(config::Msg::Propose {
                 seq,
                 op,
+                cx: cx.clone(),
                 cb: Box::new(
-                    move |leader_id: i32, addresses: HashMap<u64, NodeAddress>| {
+                    move |leader_id: i32,
+                          addresses: HashMap<u64, NodeAddress>,
+                          result: Option<ApplyResult>| {
                         let mut reply = DeleteReply::new();
                         if leader_id >= 0 {
                             // follower
@@ -193,7 +564,13 @@ impl KvService for KVServer {
                             reply.set_leader_id(leader_id as u64);
                         } else {
                             // leader
-                            reply.set_state(State::OK);
+                            match result {
+                                Some(ApplyResult::Applied(deleted)) => {
+                                    reply.set_state(State::OK);
+                                    reply.set_deleted(deleted);
+                                }
+                                result => reply.set_state(result_state(result)),
+                            }
                             reply.set_leader_id(node_id);
                         }
                         reply.set_address_map(addresses);
@@ -211,30 +588,685 @@ impl KvService for KVServer {
                 r
             }
         };
+        let forwarded = match reply.get_state() {
+            State::WRONG_LEADER => self.forward(
+                &ctx,
+                &cx,
+                reply.get_leader_id(),
+                reply.get_address_map(),
+                |client, opt| client.delete_async_opt(&req, opt),
+            ),
+            _ => None,
+        };
+        reply_forwarded(
+            &ctx,
+            sink,
+            "delete",
+            reply,
+            forwarded,
+            DeleteReply::get_state,
+        );
+    }
+
+    fn scan(&mut self, ctx: RpcContext, req: ScanReq, sink: UnarySink<ScanReply>) {
+        let _timer = metrics::start_request_timer("kv", "scan");
+        let _span = trace::tracer().start_with_context("KVServer::scan", trace::extract(&ctx));
+
+        if let Err(state) =
+            self.authorize_range(&ctx, req.get_key(), req.get_range_end(), Access::Read)
+        {
+            let mut reply = ScanReply::new();
+            reply.set_state(state);
+            metrics::count_request("kv", "scan", state);
+            let f = sink
+                .success(reply)
+                .map_err(move |err| error!("failed to reply: {:?}", err));
+            ctx.spawn(f);
+            return;
+        }
+
+        let (s1, r1) = mpsc::channel();
+        let db = Arc::clone(&self.db);
+        let key_ring = self.key_ring.clone();
+        let sender = self.sender.clone();
+        let node_id = self.node_id;
+
+        sender
+            .send(config::Msg::Read {
+                cb: Box::new(
+                    move |leader_id: i32, addresses: HashMap<u64, NodeAddress>| {
+                        // Scan
+                        let mut reply = ScanReply::new();
+                        match scan(
+                            &db,
+                            key_ring.as_deref(),
+                            req.get_key(),
+                            req.get_range_end(),
+                            req.get_limit(),
+                        ) {
+                            Ok(kvs) => {
+                                reply.set_state(State::OK);
+                                reply.set_kvs(kvs.into());
+                            }
+                            Err(e) => {
+                                error!("failed to scan values: {:?}", e);
+                                reply.set_state(State::IO_ERROR);
+                            }
+                        };
+                        if leader_id >= 0 {
+                            // follower
+                            reply.set_leader_id(leader_id as u64);
+                        } else {
+                            // leader
+                            reply.set_leader_id(node_id);
+                        }
+                        reply.set_address_map(addresses);
+                        s1.send(reply).expect("callback channel closed");
+                    },
+                ),
+            })
+            .unwrap();
+
+        let reply = match r1.recv_timeout(Duration::from_secs(2)) {
+            Ok(r) => r,
+            Err(_e) => {
+                let mut r = ScanReply::new();
+                r.set_state(State::IO_ERROR);
+                r
+            }
+        };
+
+        metrics::count_request("kv", "scan", reply.get_state());
 
         let f = sink
             .success(reply.clone())
             .map_err(move |err| error!("failed to reply: {:?}", err));
         ctx.spawn(f);
     }
+
+    fn incr(&mut self, ctx: RpcContext, req: IncrReq, sink: UnarySink<IncrReply>) {
+        let _timer = metrics::start_request_timer("kv", "incr");
+        let span = trace::tracer().start_with_context("KVServer::incr", trace::extract(&ctx));
+        let cx = Context::current_with_span(span);
+
+        if let Err(state) = self.authorize(&ctx, req.get_key(), Access::ReadWrite) {
+            let mut reply = IncrReply::new();
+            reply.set_state(state);
+            metrics::count_request("kv", "incr", state);
+            let f = sink
+                .success(reply)
+                .map_err(move |err| error!("failed to reply: {:?}", err));
+            ctx.spawn(f);
+            return;
+        }
+
+        let (s1, r1) = mpsc::channel();
+        let sender = self.sender.clone();
+        let op = session_op(
+            Op::Incr {
+                key: req.get_key().to_vec(),
+                delta: req.get_delta(),
+                now: now_millis(),
+            },
+            req.get_client_id(),
+            req.get_request_seq(),
+        );
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        let node_id = self.node_id;
+
+        sender
+            .send(config::Msg::Propose {
+                seq,
+                op,
+                cx: cx.clone(),
+                cb: Box::new(
+                    move |leader_id: i32,
+                          addresses: HashMap<u64, NodeAddress>,
+                          result: Option<ApplyResult>| {
+                        let mut reply = IncrReply::new();
+                        if leader_id >= 0 {
+                            // follower
+                            reply.set_state(State::WRONG_LEADER);
+                            reply.set_leader_id(leader_id as u64);
+                        } else {
+                            // leader
+                            match result {
+                                Some(ApplyResult::Integer(value)) => {
+                                    reply.set_state(State::OK);
+                                    reply.set_value(value);
+                                }
+                                result => reply.set_state(result_state(result)),
+                            }
+                            reply.set_leader_id(node_id);
+                        }
+                        reply.set_address_map(addresses);
+                        s1.send(reply).expect("callback channel closed");
+                    },
+                ),
+            })
+            .unwrap();
+
+        let reply = match r1.recv_timeout(Duration::from_secs(2)) {
+            Ok(r) => r,
+            Err(_e) => {
+                let mut r = IncrReply::new();
+                r.set_state(State::IO_ERROR);
+                r
+            }
+        };
+        let forwarded = match reply.get_state() {
+            State::WRONG_LEADER => self.forward(
+                &ctx,
+                &cx,
+                reply.get_leader_id(),
+                reply.get_address_map(),
+                |client, opt| client.incr_async_opt(&req, opt),
+            ),
+            _ => None,
+        };
+        reply_forwarded(&ctx, sink, "incr", reply, forwarded, IncrReply::get_state);
+    }
+
+    fn batch(&mut self, ctx: RpcContext, req: BatchReq, sink: UnarySink<BatchReply>) {
+        let _timer = metrics::start_request_timer("kv", "batch");
+        let span = trace::tracer().start_with_context("KVServer::batch", trace::extract(&ctx));
+        let cx = Context::current_with_span(span);
+
+        let mut ops = Vec::new();
+        let mut state = State::OK;
+        for batch_op in req.get_ops() {
+            let (op, key) = match &batch_op.op {
+                Some(BatchOp_oneof_op::put(put)) => (put_op(put), put.get_key()),
+                Some(BatchOp_oneof_op::delete(delete)) => (
+                    Op::Delete {
+                        key: delete.get_key().to_vec(),
+                        now: now_millis(),
+                    },
+                    delete.get_key(),
+                ),
+                None => {
+                    debug!("empty operation in batch");
+                    state = State::INVALID_ARGUMENT;
+                    break;
+                }
+            };
+            if let Err(s) = self.authorize(&ctx, key, Access::Write) {
+                state = s;
+                break;
+            }
+            ops.push(op);
+        }
+        if state != State::OK {
+            let mut reply = BatchReply::new();
+            reply.set_state(state);
+            metrics::count_request("kv", "batch", state);
+            let f = sink
+                .success(reply)
+                .map_err(move |err| error!("failed to reply: {:?}", err));
+            ctx.spawn(f);
+            return;
+        }
+
+        let (s1, r1) = mpsc::channel();
+        let sender = self.sender.clone();
+        let op = session_op(
+            Op::Batch { ops },
+            req.get_client_id(),
+            req.get_request_seq(),
+        );
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        let node_id = self.node_id;
+
+        sender
+            .send(config::Msg::Propose {
+                seq,
+                op,
+                cx: cx.clone(),
+                cb: Box::new(
+                    move |leader_id: i32,
+                          addresses: HashMap<u64, NodeAddress>,
+                          result: Option<ApplyResult>| {
+                        let mut reply = BatchReply::new();
+                        if leader_id >= 0 {
+                            // follower
+                            reply.set_state(State::WRONG_LEADER);
+                            reply.set_leader_id(leader_id as u64);
+                        } else {
+                            // leader
+                            match result {
+                                Some(ApplyResult::Batch(results)) => {
+                                    reply.set_state(State::OK);
+                                    reply.set_applied(
+                                        results
+                                            .iter()
+                                            .map(|r| matches!(r, ApplyResult::Applied(true)))
+                                            .collect(),
+                                    );
+                                }
+                                result => reply.set_state(result_state(result)),
+                            }
+                            reply.set_leader_id(node_id);
+                        }
+                        reply.set_address_map(addresses);
+                        s1.send(reply).expect("callback channel closed");
+                    },
+                ),
+            })
+            .unwrap();
+
+        let reply = match r1.recv_timeout(Duration::from_secs(2)) {
+            Ok(r) => r,
+            Err(_e) => {
+                let mut r = BatchReply::new();
+                r.set_state(State::IO_ERROR);
+                r
+            }
+        };
+        let forwarded = match reply.get_state() {
+            State::WRONG_LEADER => self.forward(
+                &ctx,
+                &cx,
+                reply.get_leader_id(),
+                reply.get_address_map(),
+                |client, opt| client.batch_async_opt(&req, opt),
+            ),
+            _ => None,
+        };
+        reply_forwarded(&ctx, sink, "batch", reply, forwarded, BatchReply::get_state);
+    }
+}
+
+// Replies with the reply of the leader if the write was forwarded, or with the reply of this
+// node if it wasn't or the leader could not be reached.
+fn reply_forwarded<R>(
+    ctx: &RpcContext,
+    sink: UnarySink<R>,
+    method: &'static str,
+    reply: R,
+    forwarded: Option<ClientUnaryReceiver<R>>,
+    state: fn(&R) -> State,
+) where
+    R: Send + 'static,
+{
+    let reply = match forwarded {
+        Some(receiver) => Either::A(receiver.or_else(move |e| {
+            warn!("failed to forward write: error={:?}", e);
+            Ok(reply)
+        })),
+        None => Either::B(future::ok(reply)),
+    };
+    let f = reply
+        .and_then(move |reply| {
+            metrics::count_request("kv", method, state(&reply));
+            sink.success(reply)
+        })
+        .map_err(move |err| error!("failed to reply: {:?}", err));
+    ctx.spawn(f);
 }
 
-fn apply_daemon(receiver: Receiver<Op>, db: Arc<DB>) {
+// Returns the number of times a request has been forwarded.
+fn forward_hops(ctx: &RpcContext) -> u32 {
+    for (key, value) in ctx.request_headers().iter() {
+        if key == FORWARD_HOPS_KEY {
+            return String::from_utf8_lossy(value).parse::<u32>().unwrap_or(0);
+        }
+    }
+    0
+}
+
+// Wraps the operation of a client so that it is applied only once. A request without a client
+// ID is applied as it is.
+fn session_op(op: Op, client_id: u64, request_seq: u64) -> Op {
+    if client_id == 0 {
+        return op;
+    }
+    Op::Session {
+        client_id,
+        request_seq,
+        op: Box::new(op),
+        now: now_millis(),
+    }
+}
+
+// Converts a put request into an operation. The expiration time is fixed on the node that
+// receives the request, so that all nodes store the same one.
+fn put_op(req: &PutReq) -> Op {
+    let now = now_millis();
+    Op::Put {
+        key: req.get_key().to_vec(),
+        val: req.get_value().to_vec(),
+        expires_at: if req.get_ttl() > 0 {
+            Some(now + req.get_ttl())
+        } else {
+            None
+        },
+        keep_ttl: req.get_keep_ttl(),
+        flags: req.get_flags(),
+        condition: match req.get_condition() {
+            PutCondition::ALWAYS => Condition::Always,
+            PutCondition::IF_NOT_EXISTS => Condition::NotExists,
+            PutCondition::IF_EXISTS => Condition::Exists,
+            PutCondition::IF_VERSION => Condition::Version(req.get_version()),
+        },
+        now,
+    }
+}
+
+// Returns the state of a reply for a result that carries no value.
+fn result_state(result: Option<ApplyResult>) -> State {
+    match result {
+        Some(ApplyResult::NotFound(e)) => {
+            debug!("not found: {}", e);
+            State::NOT_FOUND
+        }
+        Some(ApplyResult::InvalidArgument(e)) => {
+            debug!("invalid argument: {}", e);
+            State::INVALID_ARGUMENT
+        }
+        Some(ApplyResult::Error(e)) => {
+            error!("failed to apply: {}", e);
+            State::IO_ERROR
+        }
+        Some(ApplyResult::Dropped) => State::PROPOSAL_DROPPED,
+        Some(_) => State::OK,
+        None => State::IO_ERROR,
+    }
+}
+
+fn scan(
+    db: &dyn KvEngine,
+    key_ring: Option<&KeyRing>,
+    key: &[u8],
+    range_end: &[u8],
+    limit: u64,
+) -> Result<Vec<KeyValue>, std::io::Error> {
+    let now = now_millis();
+    let mut kvs = Vec::new();
+    // The pairs are read from a snapshot, so that a scan sees the writes applied before it
+    // only.
+    let snapshot = db.snapshot()?;
+    for item in snapshot.iterator(key) {
+        let (k, v) = item?;
+        if !range_end.is_empty() && range_end != [0] && k.as_slice() >= range_end {
+            break;
+        }
+        if limit > 0 && kvs.len() as u64 >= limit {
+            break;
+        }
+        if is_reserved_key(&k) {
+            continue;
+        }
+
+        let record = Record::decode(&k, &v, key_ring)?;
+        if record.is_expired(now) {
+            continue;
+        }
+
+        let mut kv = KeyValue::new();
+        kv.set_key(k);
+        kv.set_value(record.value);
+        kvs.push(kv);
+    }
+    Ok(kvs)
+}
+
+fn apply_daemon(
+    receiver: Receiver<Apply>,
+    db: Arc<dyn KvEngine>,
+    key_ring: Option<Arc<KeyRing>>,
+    watch_hub: Arc<WatchHub>,
+) {
     loop {
-        let op = match receiver.recv() {
+        // The apply span ends when its context is dropped after the write.
+        let Apply { op, cx: _cx, cb } = match receiver.recv() {
             Ok(o) => o,
             _ => {
                 debug!("exit the apply daemon");
                 return;
             }
         };
-        match op {
-            Op::Put { key, val } => {
-                db.put(key.as_slice(), val.as_slice()).unwrap();
+        let result = match Writer::new(&db, key_ring.as_deref()) {
+            Ok(mut writer) => {
+                let result = apply(&mut writer, op);
+                match writer.commit() {
+                    Ok((revision, events)) => {
+                        if !events.is_empty() {
+                            watch_hub.publish(revision, &events);
+                        }
+                        result
+                    }
+                    Err(e) => ApplyResult::Error(e.to_string()),
+                }
+            }
+            Err(e) => ApplyResult::Error(e.to_string()),
+        };
+        if let Some(cb) = cb {
+            cb(result);
+        }
+    }
+}
+
+// Applies an operation. It must give the same result on every node, so it only depends on the
+// operation and the data.
+fn apply(writer: &mut Writer, op: Op) -> ApplyResult {
+    match op {
+        Op::Put {
+            key,
+            val,
+            expires_at,
+            keep_ttl,
+            flags,
+            condition,
+            now,
+        } => {
+            let current = match writer.get(&key, now) {
+                Ok(record) => record,
+                Err(e) => return ApplyResult::Error(e.to_string()),
+            };
+            let holds = match condition {
+                Condition::Always => true,
+                Condition::NotExists => current.is_none(),
+                Condition::Exists => current.is_some(),
+                Condition::Version(version) => current
+                    .as_ref()
+                    .map_or(false, |r| r.mod_revision == version),
+            };
+            if !holds {
+                return ApplyResult::Applied(false);
             }
-            Op::Delete { key } => {
-                db.delete(key.as_slice()).unwrap();
+            let expires_at = if keep_ttl {
+                current.and_then(|r| r.expires_at)
+            } else {
+                expires_at
+            };
+            let mut record = Record::new(val, expires_at);
+            record.flags = flags;
+            match writer.put(&key, record, now) {
+                Ok(_) => ApplyResult::Applied(true),
+                Err(e) => ApplyResult::Error(e.to_string()),
             }
         }
+        Op::Delete { key, now } => match writer.delete(&key) {
+            Ok(prev) => ApplyResult::Applied(prev.map_or(false, |r| !r.is_expired(now))),
+            Err(e) => ApplyResult::Error(e.to_string()),
+        },
+        Op::Incr { key, delta, now } => {
+            // The expiration time, the lease and the flags of the key are kept.
+            let (current, mut record) = match writer.get(&key, now) {
+                Ok(Some(record)) => {
+                    let current = std::str::from_utf8(&record.value)
+                        .ok()
+                        .and_then(|s| s.parse::<i64>().ok());
+                    match current {
+                        Some(current) => (current, record),
+                        None => {
+                            return ApplyResult::InvalidArgument(format!(
+                                "value is not an integer: key={:?}",
+                                key
+                            ))
+                        }
+                    }
+                }
+                Ok(None) => (0, Record::new(Vec::new(), None)),
+                Err(e) => return ApplyResult::Error(e.to_string()),
+            };
+            let value = match current.checked_add(delta) {
+                Some(value) => value,
+                None => {
+                    return ApplyResult::InvalidArgument(format!(
+                        "increment would overflow: key={:?}",
+                        key
+                    ))
+                }
+            };
+            record.value = value.to_string().into_bytes();
+            match writer.put(&key, record, now) {
+                Ok(_) => ApplyResult::Integer(value),
+                Err(e) => ApplyResult::Error(e.to_string()),
+            }
+        }
+        Op::Batch { ops } => {
+            ApplyResult::Batch(ops.into_iter().map(|op| apply(writer, op)).collect())
+        }
+        Op::EtcdTxn { txn, now } => etcd::txn::apply(writer, &txn, now),
+        Op::LeaseGrant { id, ttl, now } => lease::grant(writer, id, ttl, now),
+        Op::LeaseRevoke { id } => lease::revoke(writer, id),
+        Op::LeaseKeepAlive { id, now } => lease::keep_alive(writer, id, now),
+        Op::IssueToken { token, name, now } => token::issue(writer, &token, &name, now),
+        Op::Session {
+            client_id,
+            request_seq,
+            op,
+            now,
+        } => session::apply(writer, client_id, request_seq, now, |w| apply(w, *op)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grpcio::{ChannelBuilder, EnvBuilder, Server, ServerBuilder};
+
+    use meteora_proto::proto::kv_grpc::create_kv_service;
+
+    use crate::engine::memory::MemoryEngine;
+
+    // The key-value service of a node whose Raft node is faked. Every proposal is answered with
+    // the leader that is set, which is -1 if the node is the leader itself.
+    struct Node {
+        _server: Server,
+        address: String,
+        leader: Arc<Mutex<(i32, HashMap<u64, NodeAddress>)>>,
+        proposals: Arc<AtomicU64>,
+    }
+
+    fn start_node(env: &Arc<Environment>, node_id: u64) -> Node {
+        let (sender, receiver) = mpsc::channel();
+        let leader = Arc::new(Mutex::new((-1, HashMap::new())));
+        let proposals = Arc::new(AtomicU64::new(0));
+        let raft_leader = Arc::clone(&leader);
+        let raft_proposals = Arc::clone(&proposals);
+        thread::spawn(move || {
+            for msg in receiver {
+                if let config::Msg::Propose { cb, .. } = msg {
+                    raft_proposals.fetch_add(1, Ordering::SeqCst);
+                    let (leader_id, addresses) = raft_leader.lock().unwrap().clone();
+                    cb(leader_id, addresses, Some(ApplyResult::Applied(true)));
+                }
+            }
+        });
+
+        let mut kv_server = KVServer {
+            db: Arc::new(MemoryEngine::new()),
+            sender,
+            seq: Arc::new(AtomicU64::new(0)),
+            node_id,
+            auth_enabled: false,
+            key_ring: None,
+            tls: None,
+            forward_env: None,
+            forward_channels: Arc::new(Mutex::new(HashMap::new())),
+        };
+        kv_server.forward_writes(Arc::clone(env));
+        let mut server = ServerBuilder::new(Arc::clone(env))
+            .register_service(create_kv_service(kv_server))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let (host, port) = server.bind_addrs()[0].clone();
+        Node {
+            _server: server,
+            address: format!("{}:{}", host, port),
+            leader,
+            proposals,
+        }
+    }
+
+    // Makes the node report `leader_id` as the leader, at the address of `leader`.
+    fn follow(node: &Node, leader_id: u64, leader: &Node) {
+        let mut address = NodeAddress::new();
+        address.set_kv_address(leader.address.clone());
+        let mut addresses = HashMap::new();
+        addresses.insert(leader_id, address);
+        *node.leader.lock().unwrap() = (leader_id as i32, addresses);
+    }
+
+    fn put(env: &Arc<Environment>, node: &Node, hops: Option<u32>) -> PutReply {
+        let client =
+            KvServiceClient::new(ChannelBuilder::new(Arc::clone(env)).connect(&node.address));
+        let mut req = PutReq::new();
+        req.set_key(b"key".to_vec());
+        req.set_value(b"value".to_vec());
+        let mut opt = CallOption::default().timeout(Duration::from_secs(10));
+        if let Some(hops) = hops {
+            let mut builder = MetadataBuilder::with_capacity(1);
+            builder
+                .add_str(FORWARD_HOPS_KEY, &hops.to_string())
+                .unwrap();
+            opt = opt.headers(builder.build());
+        }
+        client.put_opt(&req, opt).unwrap()
+    }
+
+    #[test]
+    fn test_forward_to_leader() {
+        let env = Arc::new(EnvBuilder::new().build());
+        let leader = start_node(&env, 1);
+        let follower = start_node(&env, 2);
+        follow(&follower, 1, &leader);
+
+        for _ in 0..2 {
+            let reply = put(&env, &follower, None);
+            assert_eq!(reply.get_state(), State::OK);
+            assert!(reply.get_applied());
+            assert_eq!(reply.get_leader_id(), 1);
+        }
+        assert_eq!(leader.proposals.load(Ordering::SeqCst), 2);
+        assert_eq!(follower.proposals.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_forward_hop_limit() {
+        let env = Arc::new(EnvBuilder::new().build());
+        let leader = start_node(&env, 1);
+        let follower = start_node(&env, 2);
+        follow(&follower, 1, &leader);
+
+        // A write that has been forwarded too many times is refused.
+        let reply = put(&env, &follower, Some(MAX_FORWARD_HOPS));
+        assert_eq!(reply.get_state(), State::WRONG_LEADER);
+        assert_eq!(reply.get_leader_id(), 1);
+        assert_eq!(leader.proposals.load(Ordering::SeqCst), 0);
+
+        // Two followers that take each other for the leader stop forwarding at the limit.
+        let a = start_node(&env, 3);
+        let b = start_node(&env, 4);
+        follow(&a, 4, &b);
+        follow(&b, 3, &a);
+        let reply = put(&env, &a, None);
+        assert_eq!(reply.get_state(), State::WRONG_LEADER);
+        assert_eq!(a.proposals.load(Ordering::SeqCst), 2);
+        assert_eq!(b.proposals.load(Ordering::SeqCst), 1);
     }
 }
@@ -0,0 +1,214 @@
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind};
+
+use bincode::{deserialize, serialize};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::kv::record::Record;
+use crate::kv::revision::Writer;
+use crate::kv::server::ApplyResult;
+
+// The sessions are stored with the data, so that they are replicated by applying the same
+// entries and are part of any snapshot of the store.
+const SESSION_KEY_PREFIX: &[u8] = b"\x00meteora/sessions/";
+// The sessions by the time they were last used, so that the expired ones are found first.
+const SESSION_TIME_KEY_PREFIX: &[u8] = b"\x00meteora/session_times/";
+// Milliseconds. A client that has not written for this long is forgotten.
+const SESSION_TTL: u64 = 60 * 60 * 1000;
+// The number of expired sessions deleted by a write, so that a write stays cheap.
+const MAX_EXPIRED_SESSIONS: usize = 16;
+
+// The last write of a client. Its result is returned again if the write is retried.
+#[derive(Serialize, Deserialize)]
+struct Session {
+    request_seq: u64,
+    result: ApplyResult,
+    // Milliseconds since the UNIX epoch.
+    updated_at: u64,
+}
+
+fn session_key(client_id: u64) -> Vec<u8> {
+    [SESSION_KEY_PREFIX, &client_id.to_be_bytes()].concat()
+}
+
+fn session_time_key(updated_at: u64, client_id: u64) -> Vec<u8> {
+    [
+        SESSION_TIME_KEY_PREFIX,
+        &updated_at.to_be_bytes(),
+        &client_id.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn get_session(writer: &Writer, client_id: u64) -> Result<Option<Session>, Error> {
+    match writer.get_record(&session_key(client_id))? {
+        Some(record) => deserialize(&record.value).map(Some).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to decode session: {}", e),
+            )
+        }),
+        None => Ok(None),
+    }
+}
+
+// Applies a write of the client with `f`, unless the request has already been applied, in
+// which case the result it had is returned. The requests of a client must be numbered in
+// increasing order and sent one at a time.
+pub fn apply<F>(
+    writer: &mut Writer,
+    client_id: u64,
+    request_seq: u64,
+    now: u64,
+    f: F,
+) -> ApplyResult
+where
+    F: FnOnce(&mut Writer) -> ApplyResult,
+{
+    let prev = match get_session(writer, client_id) {
+        Ok(Some(session)) if request_seq == session.request_seq => {
+            debug!(
+                "duplicate request: client_id={}, request_seq={}",
+                client_id, request_seq
+            );
+            return session.result;
+        }
+        Ok(Some(session)) if request_seq < session.request_seq => {
+            return ApplyResult::InvalidArgument(format!(
+                "request is older than the last one: client_id={}, request_seq={}, last={}",
+                client_id, request_seq, session.request_seq
+            ))
+        }
+        Ok(session) => session,
+        Err(e) => return ApplyResult::Error(e.to_string()),
+    };
+
+    let result = f(writer);
+
+    let session = Session {
+        request_seq,
+        result: result.clone(),
+        updated_at: now,
+    };
+    let saved = prev
+        .map_or(Ok(None), |prev| {
+            writer.delete(&session_time_key(prev.updated_at, client_id))
+        })
+        .and_then(|_| {
+            writer.put(
+                &session_key(client_id),
+                Record::new(serialize(&session).unwrap(), None),
+                now,
+            )
+        })
+        .and_then(|_| {
+            writer.put(
+                &session_time_key(now, client_id),
+                Record::new(Vec::new(), None),
+                now,
+            )
+        })
+        .and_then(|_| expire_sessions(writer, now));
+    match saved {
+        Ok(()) => result,
+        Err(e) => ApplyResult::Error(e.to_string()),
+    }
+}
+
+// Deletes the oldest of the expired sessions. Every node deletes the same ones, since `now` is
+// the time of the proposal.
+fn expire_sessions(writer: &mut Writer, now: u64) -> Result<(), Error> {
+    let mut expired = Vec::new();
+    for item in writer.iterator(SESSION_TIME_KEY_PREFIX) {
+        let (k, _) = item?;
+        if !k.starts_with(SESSION_TIME_KEY_PREFIX) || expired.len() >= MAX_EXPIRED_SESSIONS {
+            break;
+        }
+        let suffix = &k[SESSION_TIME_KEY_PREFIX.len()..];
+        if suffix.len() != 16 {
+            continue;
+        }
+        let updated_at = u64::from_be_bytes(suffix[..8].try_into().unwrap());
+        if updated_at.saturating_add(SESSION_TTL) > now {
+            break;
+        }
+        let client_id = u64::from_be_bytes(suffix[8..].try_into().unwrap());
+        expired.push((k, client_id));
+    }
+    for (time_key, client_id) in expired {
+        debug!("session expired: client_id={}", client_id);
+        writer.delete(&time_key)?;
+        writer.delete(&session_key(client_id))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::memory::MemoryEngine;
+
+    // Applies an increment of the counter of the client, and returns the result of the write.
+    fn incr(db: &MemoryEngine, client_id: u64, request_seq: u64, now: u64) -> ApplyResult {
+        let mut writer = Writer::new(db, None).unwrap();
+        let result = apply(&mut writer, client_id, request_seq, now, |writer| {
+            let count = match writer.get(b"count", now).unwrap() {
+                Some(record) => record.value[0] + 1,
+                None => 1,
+            };
+            writer
+                .put(b"count", Record::new(vec![count], None), now)
+                .unwrap();
+            ApplyResult::Integer(count as i64)
+        });
+        writer.commit().unwrap();
+        result
+    }
+
+    fn count(db: &MemoryEngine) -> u8 {
+        let writer = Writer::new(db, None).unwrap();
+        writer.get(b"count", 0).unwrap().unwrap().value[0]
+    }
+
+    #[test]
+    fn test_retry_is_applied_once() {
+        let db = MemoryEngine::new();
+        assert_eq!(incr(&db, 1, 1, 0), ApplyResult::Integer(1));
+        // A retry returns the result of the first attempt.
+        assert_eq!(incr(&db, 1, 1, 10), ApplyResult::Integer(1));
+        assert_eq!(count(&db), 1);
+        assert_eq!(incr(&db, 1, 2, 20), ApplyResult::Integer(2));
+        assert_eq!(count(&db), 2);
+    }
+
+    #[test]
+    fn test_clients_are_separate() {
+        let db = MemoryEngine::new();
+        assert_eq!(incr(&db, 1, 1, 0), ApplyResult::Integer(1));
+        assert_eq!(incr(&db, 2, 1, 0), ApplyResult::Integer(2));
+        assert_eq!(count(&db), 2);
+    }
+
+    #[test]
+    fn test_old_request_is_rejected() {
+        let db = MemoryEngine::new();
+        incr(&db, 1, 2, 0);
+        match incr(&db, 1, 1, 10) {
+            ApplyResult::InvalidArgument(_) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(count(&db), 1);
+    }
+
+    #[test]
+    fn test_expired_session_is_forgotten() {
+        let db = MemoryEngine::new();
+        incr(&db, 1, 1, 0);
+        // Another client's write deletes the session once it has expired, so the same request
+        // is applied again.
+        incr(&db, 2, 1, SESSION_TTL);
+        assert_eq!(incr(&db, 1, 1, SESSION_TTL + 1), ApplyResult::Integer(3));
+        assert_eq!(count(&db), 3);
+    }
+}
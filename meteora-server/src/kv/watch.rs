@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::kv::revision::Event;
+
+// Called with the revision and the changes of each applied entry. A watcher that returns
+// false is removed.
+pub type Watcher = Box<dyn Fn(u64, &[Event]) -> bool + Send>;
+
+// Notifies watchers of the changes applied on this node.
+#[derive(Default)]
+pub struct WatchHub {
+    next_id: AtomicU64,
+    watchers: Mutex<HashMap<u64, Watcher>>,
+}
+
+impl WatchHub {
+    pub fn new() -> WatchHub {
+        WatchHub {
+            next_id: AtomicU64::new(0),
+            watchers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Adds the watcher returned by `f`, if any. No changes are published while `f` runs, so
+    // the watcher is called with every change committed after what `f` reads.
+    pub fn watch<F>(&self, f: F) -> Option<u64>
+    where
+        F: FnOnce() -> Option<Watcher>,
+    {
+        let mut watchers = self.watchers.lock().unwrap();
+        let watcher = f()?;
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        watchers.insert(id, watcher);
+        Some(id)
+    }
+
+    pub fn cancel(&self, id: u64) {
+        self.watchers.lock().unwrap().remove(&id);
+    }
+
+    pub fn publish(&self, revision: u64, events: &[Event]) {
+        self.watchers
+            .lock()
+            .unwrap()
+            .retain(|_, watcher| watcher(revision, events));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::kv::revision::EventKind;
+
+    fn event(key: &[u8]) -> Event {
+        Event {
+            kind: EventKind::Delete,
+            key: key.to_vec(),
+            record: None,
+            prev: None,
+        }
+    }
+
+    #[test]
+    fn test_publish() {
+        let hub = WatchHub::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let watcher_seen = Arc::clone(&seen);
+        let id = hub
+            .watch(|| {
+                Some(Box::new(move |revision: u64, events: &[Event]| {
+                    watcher_seen.lock().unwrap().push((revision, events.len()));
+                    true
+                }))
+            })
+            .unwrap();
+        hub.publish(1, &[event(b"a")]);
+        hub.publish(2, &[event(b"a"), event(b"b")]);
+        hub.cancel(id);
+        hub.publish(3, &[event(b"a")]);
+        assert_eq!(*seen.lock().unwrap(), vec![(1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_remove_finished_watcher() {
+        let hub = WatchHub::new();
+        let count = Arc::new(AtomicU64::new(0));
+        let watcher_count = Arc::clone(&count);
+        hub.watch(|| {
+            Some(Box::new(move |_: u64, _: &[Event]| {
+                watcher_count.fetch_add(1, Ordering::SeqCst);
+                false
+            }))
+        });
+        hub.publish(1, &[event(b"a")]);
+        hub.publish(2, &[event(b"a")]);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert!(hub.watch(|| None).is_none());
+        assert!(hub.watchers.lock().unwrap().is_empty());
+    }
+}
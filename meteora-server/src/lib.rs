@@ -1,2 +1,10 @@
+pub mod auth;
+pub mod encryption;
+pub mod engine;
+pub mod etcd;
 pub mod kv;
+pub mod metrics;
 pub mod raft;
+pub mod storage;
+pub mod tls;
+pub mod trace;
@@ -0,0 +1,296 @@
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use std::thread;
+
+use lazy_static::lazy_static;
+use log::*;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use tiny_http::{Header, Response, Server};
+
+use meteora_proto::proto::common::State;
+
+use crate::engine::KvEngine;
+
+lazy_static! {
+    static ref GRPC_REQUESTS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "meteora_grpc_requests_total",
+        "Total number of gRPC requests by service, method and reply state.",
+        &["service", "method", "state"]
+    )
+    .unwrap();
+    static ref GRPC_REQUEST_DURATION_SECONDS: HistogramVec = register_histogram_vec!(
+        "meteora_grpc_request_duration_seconds",
+        "gRPC request latencies in seconds by service and method.",
+        &["service", "method"]
+    )
+    .unwrap();
+    pub static ref RAFT_TERM: IntGauge =
+        register_int_gauge!("meteora_raft_term", "Current Raft term.").unwrap();
+    pub static ref RAFT_COMMIT_INDEX: IntGauge =
+        register_int_gauge!("meteora_raft_commit_index", "Raft commit index.").unwrap();
+    pub static ref RAFT_APPLIED_INDEX: IntGauge =
+        register_int_gauge!("meteora_raft_applied_index", "Raft applied index.").unwrap();
+    pub static ref RAFT_LEADER_CHANGES_TOTAL: IntCounter = register_int_counter!(
+        "meteora_raft_leader_changes_total",
+        "Number of leader changes seen by this node."
+    )
+    .unwrap();
+    pub static ref RAFT_PROPOSALS_PENDING: IntGauge = register_int_gauge!(
+        "meteora_raft_proposals_pending",
+        "Number of proposals waiting to be committed."
+    )
+    .unwrap();
+    pub static ref RAFT_PROPOSALS_DROPPED_TOTAL: IntCounter = register_int_counter!(
+        "meteora_raft_proposals_dropped_total",
+        "Number of proposals failed because the leader changed before they were applied."
+    )
+    .unwrap();
+    pub static ref RAFT_APPLY_QUEUE_FULL_TOTAL: IntCounter = register_int_counter!(
+        "meteora_raft_apply_queue_full_total",
+        "Number of times the Raft loop waited for the apply queue to have room."
+    )
+    .unwrap();
+    pub static ref RAFT_MESSAGES_DROPPED_TOTAL: IntCounter = register_int_counter!(
+        "meteora_raft_messages_dropped_total",
+        "Number of Raft messages dropped because the queue of the peer was full."
+    )
+    .unwrap();
+}
+
+// Starts a timer that records the latency of a gRPC request when it is dropped.
+pub fn start_request_timer(service: &str, method: &str) -> prometheus::HistogramTimer {
+    GRPC_REQUEST_DURATION_SECONDS
+        .with_label_values(&[service, method])
+        .start_timer()
+}
+
+pub fn count_request(service: &str, method: &str, state: State) {
+    GRPC_REQUESTS_TOTAL
+        .with_label_values(&[service, method, &format!("{:?}", state)])
+        .inc();
+}
+
+// Reads RocksDB statistics when the metrics are scraped.
+pub struct RocksDBCollector {
+    db: Arc<dyn KvEngine>,
+    sst_files_size: IntGauge,
+    memtables_size: IntGauge,
+    estimated_num_keys: IntGauge,
+}
+
+impl RocksDBCollector {
+    pub fn new(db: Arc<dyn KvEngine>) -> RocksDBCollector {
+        RocksDBCollector {
+            db,
+            sst_files_size: IntGauge::new(
+                "meteora_rocksdb_sst_files_size_bytes",
+                "Total size of all SST files.",
+            )
+            .unwrap(),
+            memtables_size: IntGauge::new(
+                "meteora_rocksdb_memtables_size_bytes",
+                "Approximate size of active and unflushed immutable memtables.",
+            )
+            .unwrap(),
+            estimated_num_keys: IntGauge::new(
+                "meteora_rocksdb_estimated_keys",
+                "Estimated number of keys.",
+            )
+            .unwrap(),
+        }
+    }
+
+    fn update(&self, gauge: &IntGauge, property: &str) {
+        match self.db.property_int_value(property) {
+            Ok(Some(v)) => gauge.set(v as i64),
+            Ok(None) => {}
+            Err(e) => warn!("failed to get property: name={}, error={:?}", property, e),
+        }
+    }
+}
+
+impl Collector for RocksDBCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = Vec::new();
+        descs.extend(self.sst_files_size.desc());
+        descs.extend(self.memtables_size.desc());
+        descs.extend(self.estimated_num_keys.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.update(&self.sst_files_size, "rocksdb.total-sst-files-size");
+        self.update(&self.memtables_size, "rocksdb.cur-size-all-mem-tables");
+        self.update(&self.estimated_num_keys, "rocksdb.estimate-num-keys");
+
+        let mut mfs = Vec::new();
+        mfs.extend(self.sst_files_size.collect());
+        mfs.extend(self.memtables_size.collect());
+        mfs.extend(self.estimated_num_keys.collect());
+        mfs
+    }
+}
+
+pub fn register_rocksdb_collector(db: Arc<dyn KvEngine>) {
+    if let Err(e) = prometheus::register(Box::new(RocksDBCollector::new(db))) {
+        warn!("failed to register RocksDB collector: {:?}", e);
+    }
+}
+
+// Serves the metrics in the Prometheus text format on `/metrics`.
+pub fn serve(address: &str) -> Result<(), Error> {
+    let server = Server::http(address).map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed to start metrics server: address={}, error={}",
+                address, e
+            ),
+        )
+    })?;
+    info!("start metrics service on {}", address);
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let encoder = TextEncoder::new();
+                let mut buffer = Vec::new();
+                if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+                    error!("failed to encode metrics: {:?}", e);
+                }
+                let content_type =
+                    Header::from_bytes(&b"Content-Type"[..], encoder.format_type().as_bytes())
+                        .unwrap();
+                Response::from_data(buffer).with_header(content_type)
+            } else {
+                Response::from_data(b"not found".to_vec()).with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                error!("failed to reply: {:?}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    use crate::engine::memory::MemoryEngine;
+    use crate::engine::{KvIterator, KvSnapshot, WriteBatch};
+
+    // An engine that reports fixed RocksDB properties.
+    struct PropertyEngine(MemoryEngine);
+
+    impl KvEngine for PropertyEngine {
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+            self.0.get(key)
+        }
+
+        fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+            self.0.write_batch(batch)
+        }
+
+        fn iterator(&self, from: &[u8]) -> KvIterator {
+            self.0.iterator(from)
+        }
+
+        fn snapshot(&self) -> Result<Box<dyn KvSnapshot + '_>, Error> {
+            self.0.snapshot()
+        }
+
+        fn checkpoint(&self, path: &str) -> Result<(), Error> {
+            self.0.checkpoint(path)
+        }
+
+        fn property_int_value(&self, name: &str) -> Result<Option<u64>, Error> {
+            Ok(match name {
+                "rocksdb.total-sst-files-size" => Some(100),
+                "rocksdb.cur-size-all-mem-tables" => Some(20),
+                _ => None,
+            })
+        }
+    }
+
+    fn gauge(mfs: &[MetricFamily], name: &str) -> Option<f64> {
+        mfs.iter()
+            .find(|mf| mf.get_name() == name)
+            .map(|mf| mf.get_metric()[0].get_gauge().get_value())
+    }
+
+    // Returns the status code and the whole response.
+    fn get(address: &str, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, address).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response.split_whitespace().nth(1).unwrap().to_string();
+        (status, response)
+    }
+
+    #[test]
+    fn test_count_request() {
+        count_request("test", "count", State::OK);
+        count_request("test", "count", State::OK);
+        count_request("test", "count", State::NOT_FOUND);
+        let ok = GRPC_REQUESTS_TOTAL.with_label_values(&["test", "count", "OK"]);
+        let not_found = GRPC_REQUESTS_TOTAL.with_label_values(&["test", "count", "NOT_FOUND"]);
+        assert_eq!(ok.get(), 2);
+        assert_eq!(not_found.get(), 1);
+    }
+
+    #[test]
+    fn test_request_timer() {
+        drop(start_request_timer("test", "timer"));
+        let histogram = GRPC_REQUEST_DURATION_SECONDS.with_label_values(&["test", "timer"]);
+        assert_eq!(histogram.get_sample_count(), 1);
+    }
+
+    #[test]
+    fn test_rocksdb_collector() {
+        let collector = RocksDBCollector::new(Arc::new(PropertyEngine(MemoryEngine::new())));
+        assert_eq!(collector.desc().len(), 3);
+        let mfs = collector.collect();
+        assert_eq!(
+            gauge(&mfs, "meteora_rocksdb_sst_files_size_bytes"),
+            Some(100.0)
+        );
+        assert_eq!(
+            gauge(&mfs, "meteora_rocksdb_memtables_size_bytes"),
+            Some(20.0)
+        );
+        // A property that the engine doesn't have is left at 0.
+        assert_eq!(gauge(&mfs, "meteora_rocksdb_estimated_keys"), Some(0.0));
+    }
+
+    #[test]
+    fn test_serve() {
+        // A free port, which is released for the server.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        serve(&address).unwrap();
+        count_request("test", "serve", State::OK);
+
+        let (status, response) = get(&address, "/metrics");
+        assert_eq!(status, "200");
+        assert!(response.contains("text/plain"));
+        assert!(response.contains(
+            "meteora_grpc_requests_total{method=\"serve\",service=\"test\",state=\"OK\"} 1"
+        ));
+
+        assert_eq!(get(&address, "/other").0, "404");
+    }
+}
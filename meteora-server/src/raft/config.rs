@@ -1,56 +1,174 @@
 use std::collections::HashMap;
-use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
 use std::sync::Arc;
-use std::thread;
 use std::time::{Duration, Instant};
 
 use bincode::{deserialize, serialize};
-use grpcio::{ChannelBuilder, EnvBuilder};
 use log::*;
+use opentelemetry::trace::{TraceContextExt, Tracer};
+use opentelemetry::Context;
 use protobuf::Message as PMessage;
 use raft::prelude::*;
 use raft::storage::MemStorage;
+use serde::{Deserialize, Serialize};
 
 use meteora_proto::proto::common::NodeAddress;
 use meteora_proto::proto::raft::AddressState;
-use meteora_proto::proto::raft_grpc::RaftServiceClient;
 
-use crate::kv::server::Op;
+use crate::kv::server::{Apply, ApplyResult, Op};
+use crate::metrics;
+use crate::raft::transport::Transport;
+use crate::tls::TlsConfig;
+use crate::trace;
 
 type ProposeCallback = Box<dyn Fn(i32, HashMap<u64, NodeAddress>) + Send>;
+// Called with whether the change has been applied. It is not if the proposal was dropped.
+pub type ChangeCallback = Box<dyn Fn(i32, HashMap<u64, NodeAddress>, bool) + Send>;
+// Called with the result once the entry has been applied on this node, or without a result
+// if this node is not the leader.
+pub type ApplyCallback = Box<dyn Fn(i32, HashMap<u64, NodeAddress>, Option<ApplyResult>) + Send>;
 
 pub enum Msg {
     Propose {
         seq: u64,
         op: Op,
-        cb: ProposeCallback,
+        cx: Context,
+        cb: ApplyCallback,
     },
     ConfigChange {
         seq: u64,
         change: ConfChange,
-        cb: ProposeCallback,
+        cb: ChangeCallback,
     },
     Read {
         cb: ProposeCallback,
     },
     Address(AddressState),
     Raft(Message),
+    // A message to the peer could not be sent.
+    Unreachable(u64),
+}
+
+// A proposal waiting for its entry to be applied. The entry is identified by its term and
+// index, since the sequence numbers of different nodes may be the same.
+struct Pending<C> {
+    term: u64,
+    index: u64,
+    cb: C,
+}
+
+// Caps the messages handled before the next ready, so that ticks are not delayed under load.
+const MAX_MSGS_PER_ROUND: usize = 4096;
+
+// The context of a normal entry. It carries the trace context of the proposal so that
+// committing and applying the entry on every node are recorded in the same trace.
+#[derive(Serialize, Deserialize)]
+struct ProposalContext {
+    seq: u64,
+    trace: HashMap<String, String>,
+}
+
+// The tuning of the Raft node. The timeouts are counted in ticks.
+#[derive(Clone, Debug)]
+pub struct RaftConfig {
+    pub tick_interval: Duration,
+    // How long a follower waits for the leader before it campaigns.
+    pub election_tick: usize,
+    // How often the leader sends heartbeats.
+    pub heartbeat_tick: usize,
+    // The max size in bytes of the entries in an append message. A larger entry is still sent
+    // alone.
+    pub max_size_per_msg: u64,
+    // The max number of append messages sent to a follower without an ack.
+    pub max_inflight_msgs: usize,
+    // Whether a node checks that it could win before it campaigns, so that a partitioned node
+    // does not disrupt the cluster when it comes back.
+    pub pre_vote: bool,
+    // Whether the leader steps down when it has not heard from a quorum for an election
+    // timeout.
+    pub check_quorum: bool,
+    // Whether the leader waits for the next message to tell the followers that entries are
+    // committed, instead of sending it at once.
+    pub skip_bcast_commit: bool,
+}
+
+impl Default for RaftConfig {
+    fn default() -> RaftConfig {
+        RaftConfig {
+            tick_interval: Duration::from_millis(100),
+            election_tick: 10,
+            heartbeat_tick: 3,
+            max_size_per_msg: 1024 * 1024 * 1024,
+            max_inflight_msgs: 256,
+            pre_vote: false,
+            check_quorum: false,
+            skip_bcast_commit: false,
+        }
+    }
+}
+
+impl RaftConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.tick_interval == Duration::from_millis(0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "tick interval must be greater than 0",
+            ));
+        }
+        if self.max_size_per_msg == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "max size per message must be greater than 0",
+            ));
+        }
+        self.to_raft(1, vec![]).validate().map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid Raft config: {}", e),
+            )
+        })
+    }
+
+    fn to_raft(&self, node_id: u64, peers: Vec<u64>) -> Config {
+        Config {
+            id: node_id,
+            // Mostly, the peers need to be saved in the storage.
+            peers,
+            election_tick: self.election_tick,
+            heartbeat_tick: self.heartbeat_tick,
+            max_size_per_msg: self.max_size_per_msg,
+            max_inflight_msgs: self.max_inflight_msgs,
+            pre_vote: self.pre_vote,
+            check_quorum: self.check_quorum,
+            skip_bcast_commit: self.skip_bcast_commit,
+            // The Raft applied index.
+            // You need to save your applied index when you apply the committed Raft logs.
+            applied: 0,
+            // Just for log
+            tag: format!("[{}]", node_id),
+            ..Default::default()
+        }
+    }
 }
 
 pub fn init_and_run(
     storage: MemStorage,
     receiver: Receiver<Msg>,
-    apply_sender: Sender<Op>,
+    sender: Sender<Msg>,
+    apply_sender: SyncSender<Apply>,
     node_id: u64,
     node_address: NodeAddress,
     addresses: HashMap<u64, NodeAddress>,
+    tls: Option<TlsConfig>,
+    raft_config: RaftConfig,
 ) {
     let mut peers = vec![];
     let mut addresses = addresses;
-    let mut rpc_clients = HashMap::new();
+    let mut transport = Transport::new(node_id, sender, tls);
     for (id, address) in &addresses {
         peers.push(id.clone());
-        insert_client(id.clone(), address.raft_address.as_str(), &mut rpc_clients);
+        transport.add_peer(id.clone(), address.raft_address.as_str());
     }
     if peers.is_empty() {
         addresses.insert(node_id, node_address);
@@ -58,109 +176,44 @@ pub fn init_and_run(
     }
     debug!("{:?}", peers);
 
-    // Create the configuration for the Raft node.
-    let cfg = Config {
-        // The unique ID for the Raft node.
-        id: node_id,
-        // The Raft node list.
-        // Mostly, the peers need to be saved in the storage
-        peers,
-        // Election tick is for how long the follower may campaign again after
-        // it doesn't receive any message from the leader.
-        election_tick: 10,
-        // Heartbeat tick is for how long the leader needs to send
-        // a heartbeat to keep alive.
-        heartbeat_tick: 3,
-        // The max size limits the max size of each appended message. Mostly, 1 MB is enough.
-        max_size_per_msg: 1024 * 1024 * 1024,
-        // Max inflight msgs that the leader sends messages to follower without
-        // receiving ACKs.
-        max_inflight_msgs: 256,
-        // The Raft applied index.
-        // You need to save your applied index when you apply the committed Raft logs.
-        applied: 0,
-        // Just for log
-        tag: format!("[{}]", 1),
-        ..Default::default()
-    };
+    let cfg = raft_config.to_raft(node_id, peers);
 
     // Create the Raft node.
     let mut r = RawNode::new(&cfg, storage, vec![]).unwrap();
 
     // Loop forever to drive the Raft.
     let mut t = Instant::now();
-    let mut timeout = Duration::from_millis(100);
+    let mut timeout = raft_config.tick_interval;
 
-    // Use a HashMap to hold the `propose` callbacks.
+    // Use HashMaps to hold the `propose` callbacks.
     let mut callbacks = HashMap::new();
+    let mut apply_callbacks = HashMap::new();
+
+    let mut last_leader_id = raft::INVALID_ID;
 
     loop {
+        // Take the messages that are already waiting along with the first one, so that the
+        // proposals among them are appended to the log and sent to the followers together.
         match receiver.recv_timeout(timeout) {
-            Ok(Msg::Propose {
-                seq,
-                op,
-                cb: callback,
-            }) => {
-                debug!("receive propose message");
-                let leader_id = r.raft.leader_id;
-                if r.raft.leader_id != r.raft.id {
-                    // not leader, callback to notify client
-                    debug!("not a leader");
-                    callback(leader_id as i32, addresses.clone());
-                    continue;
-                }
-                let serialized_op = serialize(&op).unwrap();
-                callbacks.insert(seq, callback);
-                debug!("propose");
-                r.propose(serialize(&seq).unwrap(), serialized_op).unwrap();
-            }
-            Ok(Msg::ConfigChange {
-                seq,
-                change,
-                cb: callback,
-            }) => {
-                debug!("receive config change message");
-                let leader_id = r.raft.leader_id;
-                if r.raft.leader_id != r.raft.id {
-                    // not leader, callback to notify client
-                    debug!("not a leader");
-                    callback(leader_id as i32, addresses.clone());
-                    continue;
-                } else {
-                    callbacks.insert(seq, callback);
-                    debug!("propose config change");
-                    r.propose_conf_change(serialize(&seq).unwrap(), change)
-                        .unwrap();
-                }
-            }
-            Ok(Msg::Read { cb: callback }) => {
-                let leader_id = r.raft.leader_id;
-                callback(leader_id as i32, addresses.clone());
-                continue;
-            }
-            Ok(Msg::Raft(m)) => {
-                debug!("receive raft message");
-                if let Ok(_a) = r.step(m) {};
-            }
-            Ok(Msg::Address(address_state)) => {
-                debug!("receive address message");
-                let new_addresses = address_state.get_address_map();
-                for (id, address) in new_addresses {
-                    let address_changed = match addresses.get(id) {
-                        Some(a) => {
-                            if a.raft_address == address.raft_address {
-                                false
-                            } else {
-                                true
-                            }
-                        }
-                        None => true,
-                    };
-                    if address_changed {
-                        insert_client(id.clone(), address.raft_address.as_str(), &mut rpc_clients);
-                    }
+            Ok(msg) => {
+                handle_msg(
+                    msg,
+                    &mut r,
+                    &mut callbacks,
+                    &mut apply_callbacks,
+                    &mut addresses,
+                    &mut transport,
+                );
+                for msg in receiver.try_iter().take(MAX_MSGS_PER_ROUND - 1) {
+                    handle_msg(
+                        msg,
+                        &mut r,
+                        &mut callbacks,
+                        &mut apply_callbacks,
+                        &mut addresses,
+                        &mut transport,
+                    );
                 }
-                addresses = new_addresses.clone();
             }
             Err(RecvTimeoutError::Timeout) => {
                 debug!("timeout");
@@ -173,8 +226,7 @@ pub fn init_and_run(
         let d = t.elapsed();
         if d >= timeout {
             t = Instant::now();
-            timeout = Duration::from_millis(100);
-            // We drive Raft every 100ms.
+            timeout = raft_config.tick_interval;
             r.tick();
         } else {
             timeout -= d;
@@ -183,19 +235,128 @@ pub fn init_and_run(
         on_ready(
             &mut r,
             &mut callbacks,
+            &mut apply_callbacks,
             &mut addresses,
-            &mut rpc_clients,
+            &mut transport,
             apply_sender.clone(),
         );
+        drop_stale_proposals(&r, &mut callbacks, &mut apply_callbacks, &addresses);
+
+        if r.raft.leader_id != last_leader_id {
+            last_leader_id = r.raft.leader_id;
+            if last_leader_id != raft::INVALID_ID {
+                metrics::RAFT_LEADER_CHANGES_TOTAL.inc();
+            }
+        }
+        metrics::RAFT_TERM.set(r.raft.term as i64);
+        metrics::RAFT_COMMIT_INDEX.set(r.raft.raft_log.committed as i64);
+        metrics::RAFT_PROPOSALS_PENDING.set((callbacks.len() + apply_callbacks.len()) as i64);
+    }
+}
+
+fn handle_msg(
+    msg: Msg,
+    r: &mut RawNode<MemStorage>,
+    callbacks: &mut HashMap<u64, Pending<ChangeCallback>>,
+    apply_callbacks: &mut HashMap<u64, Pending<ApplyCallback>>,
+    addresses: &mut HashMap<u64, NodeAddress>,
+    transport: &mut Transport,
+) {
+    match msg {
+        Msg::Propose {
+            seq,
+            op,
+            cx,
+            cb: callback,
+        } => {
+            debug!("receive propose message");
+            let span = trace::tracer().start_with_context("raft.propose", cx);
+            let cx = Context::current_with_span(span);
+            let leader_id = r.raft.leader_id;
+            if r.raft.leader_id != r.raft.id {
+                // not leader, callback to notify client
+                debug!("not a leader");
+                callback(leader_id as i32, addresses.clone(), None);
+                return;
+            }
+            let serialized_op = serialize(&op).unwrap();
+            let context = ProposalContext {
+                seq,
+                trace: trace::to_carrier(&cx),
+            };
+            debug!("propose");
+            if let Err(e) = r.propose(serialize(&context).unwrap(), serialized_op) {
+                debug!("proposal dropped: {:?}", e);
+                callback(-1, addresses.clone(), Some(ApplyResult::Dropped));
+                return;
+            }
+            apply_callbacks.insert(
+                seq,
+                Pending {
+                    term: r.raft.term,
+                    index: r.raft.raft_log.last_index(),
+                    cb: callback,
+                },
+            );
+        }
+        Msg::ConfigChange {
+            seq,
+            change,
+            cb: callback,
+        } => {
+            debug!("receive config change message");
+            let leader_id = r.raft.leader_id;
+            if r.raft.leader_id != r.raft.id {
+                // not leader, callback to notify client
+                debug!("not a leader");
+                callback(leader_id as i32, addresses.clone(), false);
+            } else {
+                debug!("propose config change");
+                if let Err(e) = r.propose_conf_change(serialize(&seq).unwrap(), change) {
+                    debug!("proposal dropped: {:?}", e);
+                    callback(-1, addresses.clone(), false);
+                    return;
+                }
+                callbacks.insert(
+                    seq,
+                    Pending {
+                        term: r.raft.term,
+                        index: r.raft.raft_log.last_index(),
+                        cb: callback,
+                    },
+                );
+            }
+        }
+        Msg::Read { cb: callback } => {
+            let leader_id = r.raft.leader_id;
+            callback(leader_id as i32, addresses.clone());
+        }
+        Msg::Raft(m) => {
+            debug!("receive raft message");
+            if let Ok(_a) = r.step(m) {};
+        }
+        Msg::Address(address_state) => {
+            debug!("receive address message");
+            let new_addresses = address_state.get_address_map();
+            for (id, address) in new_addresses {
+                transport.add_peer(id.clone(), address.raft_address.as_str());
+            }
+            *addresses = new_addresses.clone();
+        }
+        Msg::Unreachable(id) => {
+            debug!("peer is unreachable: id={}", id);
+            r.report_unreachable(id);
+        }
     }
 }
 
 fn on_ready(
     r: &mut RawNode<MemStorage>,
-    callbacks: &mut HashMap<u64, ProposeCallback>,
+    callbacks: &mut HashMap<u64, Pending<ChangeCallback>>,
+    apply_callbacks: &mut HashMap<u64, Pending<ApplyCallback>>,
     addresses: &mut HashMap<u64, NodeAddress>,
-    clients: &mut HashMap<u64, Arc<RaftServiceClient>>,
-    apply_sender: Sender<Op>,
+    transport: &mut Transport,
+    apply_sender: SyncSender<Apply>,
 ) {
     if !r.has_ready() {
         return;
@@ -204,25 +365,34 @@ fn on_ready(
     // The Raft is ready, we can do something now.
     let mut ready = r.ready();
 
+    // Only record the rounds that carry proposals, and link them to the traces of the proposals.
+    let links: Vec<_> = ready
+        .entries
+        .iter()
+        .chain(ready.committed_entries.iter().flatten())
+        .filter_map(proposal_context)
+        .filter_map(|context| trace::link(&trace::from_carrier(&context.trace)))
+        .collect();
+    let _span = if links.is_empty() {
+        None
+    } else {
+        let tracer = trace::tracer();
+        Some(
+            tracer
+                .span_builder("raft.on_ready")
+                .with_links(links)
+                .start(&tracer),
+        )
+    };
+
     let is_leader = r.raft.leader_id == r.raft.id;
     if is_leader {
         // If the peer is leader, the leader can send messages to other followers ASAP.
-        let msgs = ready.messages.drain(..);
-        for msg in msgs {
-            let client = match clients.get(&msg.get_to()) {
-                Some(c) => c.clone(),
-                None => {
-                    continue;
-                }
-            };
-            let mut address_state = AddressState::new();
-            address_state.set_address_map(addresses.clone());
-            thread::spawn(move || {
-                let msg = msg;
-                let address_state = address_state;
-                if let Ok(_) = client.send_msg(&msg) {};
-                if let Ok(_) = client.send_address(&address_state) {};
-            });
+        let mut address_state = AddressState::new();
+        address_state.set_address_map(addresses.clone());
+        let address_state = Arc::new(address_state);
+        for msg in ready.messages.drain(..) {
+            transport.send(msg, Some(address_state.clone()));
         }
     }
 
@@ -247,28 +417,17 @@ fn on_ready(
     if !is_leader {
         // If not leader, the follower needs to reply the messages to
         // the leader after appending Raft entries.
-        let msgs = ready.messages.drain(..);
-        for msg in msgs {
+        for msg in ready.messages.drain(..) {
             // Send messages to other peers.
-            let client = match clients.get(&msg.get_to()) {
-                Some(c) => c.clone(),
-                None => {
-                    continue;
-                }
-            };
-            thread::spawn(move || {
-                let msg = msg;
-                if let Ok(_) = client.send_msg(&msg) {};
-            });
+            transport.send(msg, None);
         }
     }
 
     if let Some(committed_entries) = ready.committed_entries.take() {
-        let mut _last_apply_index = 0;
         for entry in committed_entries {
             // Mostly, you need to save the last apply index to resume applying
             // after restart. Here we just ignore this because we use a Memory storage.
-            _last_apply_index = entry.get_index();
+            metrics::RAFT_APPLIED_INDEX.set(entry.get_index() as i64);
 
             if entry.get_data().is_empty() {
                 debug!("empty entry");
@@ -278,12 +437,28 @@ fn on_ready(
 
             if entry.get_entry_type() == EntryType::EntryNormal {
                 let op: Op = deserialize(entry.get_data()).unwrap();
-                let seq: u64 = deserialize(entry.get_context()).unwrap();
-                match apply_sender.send(op) {
-                    _ => {}
-                }
-                if let Some(callback) = callbacks.remove(&seq) {
-                    callback(-1, addresses.clone());
+                let context: ProposalContext = deserialize(entry.get_context()).unwrap();
+                let span = trace::tracer()
+                    .start_with_context("raft.apply", trace::from_carrier(&context.trace));
+                // The proposer is notified with the result after the entry is applied.
+                let cb = if is_proposed(apply_callbacks.get(&context.seq), &entry) {
+                    apply_callbacks.remove(&context.seq).map(|pending| {
+                        let addresses = addresses.clone();
+                        Box::new(move |result| (pending.cb)(-1, addresses, Some(result)))
+                            as Box<dyn FnOnce(ApplyResult) + Send>
+                    })
+                } else {
+                    None
+                };
+                let apply = Apply {
+                    op,
+                    cx: Context::current_with_span(span),
+                    cb,
+                };
+                // Wait for the apply daemon if it is behind.
+                if let Err(TrySendError::Full(apply)) = apply_sender.try_send(apply) {
+                    metrics::RAFT_APPLY_QUEUE_FULL_TOTAL.inc();
+                    let _ = apply_sender.send(apply);
                 }
             }
 
@@ -297,17 +472,18 @@ fn on_ready(
                 let change_type = change.get_change_type();
                 if change_type == ConfChangeType::AddNode {
                     let node_address: NodeAddress = deserialize(change.get_context()).unwrap();
-                    insert_client(node_id, node_address.raft_address.as_str(), clients);
+                    transport.add_peer(node_id, node_address.raft_address.as_str());
                     addresses.insert(node_id, node_address);
                 } else if change_type == ConfChangeType::RemoveNode {
-                    if let Some(_client) = clients.remove(&node_id) {
-                        addresses.remove(&node_id);
-                    }
+                    transport.remove_peer(node_id);
+                    addresses.remove(&node_id);
                 }
 
                 r.apply_conf_change(&change);
-                if let Some(callback) = callbacks.remove(&seq) {
-                    callback(-1, addresses.clone());
+                if is_proposed(callbacks.get(&seq), &entry) {
+                    if let Some(pending) = callbacks.remove(&seq) {
+                        (pending.cb)(-1, addresses.clone(), true);
+                    }
                 }
             }
         }
@@ -317,13 +493,122 @@ fn on_ready(
     r.advance(ready);
 }
 
-fn insert_client(
-    node_id: u64,
-    node_address: &str,
-    clients: &mut HashMap<u64, Arc<RaftServiceClient>>,
+// Whether the entry is the one of the pending proposal.
+fn is_proposed<C>(pending: Option<&Pending<C>>, entry: &Entry) -> bool {
+    pending.map_or(false, |p| {
+        p.term == entry.get_term() && p.index == entry.get_index()
+    })
+}
+
+// Fails the proposals that may never be applied: those of an earlier term, whose entries may
+// have been overwritten by the new leader, and those whose entries have been overwritten and
+// passed over by the applied index.
+fn drop_stale_proposals(
+    r: &RawNode<MemStorage>,
+    callbacks: &mut HashMap<u64, Pending<ChangeCallback>>,
+    apply_callbacks: &mut HashMap<u64, Pending<ApplyCallback>>,
+    addresses: &HashMap<u64, NodeAddress>,
 ) {
-    let env = Arc::new(EnvBuilder::new().build());
-    let ch = ChannelBuilder::new(env).connect(node_address);
-    let client = RaftServiceClient::new(ch);
-    clients.insert(node_id.clone(), Arc::new(client));
+    let term = r.raft.term;
+    let is_leader = r.raft.leader_id == r.raft.id;
+    let applied = r.raft.raft_log.applied;
+    let is_stale = |p_term: u64, p_index: u64| !is_leader || p_term != term || p_index <= applied;
+
+    apply_callbacks.retain(|seq, pending| {
+        if !is_stale(pending.term, pending.index) {
+            return true;
+        }
+        debug!("proposal dropped: seq={}", seq);
+        metrics::RAFT_PROPOSALS_DROPPED_TOTAL.inc();
+        (pending.cb)(-1, addresses.clone(), Some(ApplyResult::Dropped));
+        false
+    });
+    callbacks.retain(|seq, pending| {
+        if !is_stale(pending.term, pending.index) {
+            return true;
+        }
+        debug!("config change dropped: seq={}", seq);
+        metrics::RAFT_PROPOSALS_DROPPED_TOTAL.inc();
+        (pending.cb)(-1, addresses.clone(), false);
+        false
+    });
+}
+
+fn proposal_context(entry: &Entry) -> Option<ProposalContext> {
+    if entry.get_entry_type() != EntryType::EntryNormal || entry.get_data().is_empty() {
+        return None;
+    }
+    deserialize(entry.get_context()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default() {
+        let config = RaftConfig::default();
+        assert_eq!(config.max_size_per_msg, 1024 * 1024 * 1024);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid = vec![
+            RaftConfig {
+                tick_interval: Duration::from_millis(0),
+                ..RaftConfig::default()
+            },
+            RaftConfig {
+                max_size_per_msg: 0,
+                ..RaftConfig::default()
+            },
+            RaftConfig {
+                heartbeat_tick: 0,
+                ..RaftConfig::default()
+            },
+            RaftConfig {
+                election_tick: 3,
+                heartbeat_tick: 3,
+                ..RaftConfig::default()
+            },
+            RaftConfig {
+                max_inflight_msgs: 0,
+                ..RaftConfig::default()
+            },
+        ];
+        for config in invalid {
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{:?}", config);
+        }
+
+        let config = RaftConfig {
+            tick_interval: Duration::from_millis(10),
+            election_tick: 20,
+            heartbeat_tick: 2,
+            max_size_per_msg: 1,
+            max_inflight_msgs: 1,
+            pre_vote: true,
+            check_quorum: true,
+            skip_bcast_commit: true,
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_to_raft() {
+        let config = RaftConfig {
+            max_size_per_msg: 4096,
+            pre_vote: true,
+            ..RaftConfig::default()
+        };
+        let raft_config = config.to_raft(2, vec![1, 2]);
+        assert_eq!(raft_config.id, 2);
+        assert_eq!(raft_config.peers, vec![1, 2]);
+        assert_eq!(raft_config.election_tick, 10);
+        assert_eq!(raft_config.heartbeat_tick, 3);
+        assert_eq!(raft_config.max_size_per_msg, 4096);
+        assert_eq!(raft_config.max_inflight_msgs, 256);
+        assert!(raft_config.pre_vote);
+    }
 }
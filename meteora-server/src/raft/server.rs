@@ -1,41 +1,56 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use std::time::Duration;
 
-use futures::Future;
-use grpcio::{RpcContext, UnarySink};
+use futures::{Future, Sink, Stream};
+use grpcio::{DuplexSink, RequestStream, RpcContext, UnarySink, WriteFlags};
 use log::*;
 use raft::eraftpb::{ConfChange, Message};
 
 use meteora_proto::proto::common::{NodeAddress, Null, State};
-use meteora_proto::proto::raft::{AddressState, ChangeReply, StatusReply};
+use meteora_proto::proto::raft::{
+    AddressState, ChangeReply, MessageBatch, MessageBatchAck, StatusReply, StorageStatus,
+};
 use meteora_proto::proto::raft_grpc::RaftService;
 
+use crate::metrics;
 use crate::raft::config;
 
 #[derive(Clone)]
 pub struct RaftServer {
     pub sender: Sender<config::Msg>,
-    seq: u64,
+    seq: Arc<AtomicU64>,
     node_id: u64,
+    storage: StorageStatus,
 }
 
 impl RaftServer {
-    pub fn new(sender: Sender<config::Msg>, node_id: u64) -> RaftServer {
+    pub fn new(
+        sender: Sender<config::Msg>,
+        seq: Arc<AtomicU64>,
+        node_id: u64,
+        storage: StorageStatus,
+    ) -> RaftServer {
         RaftServer {
             sender,
-            seq: 0,
+            seq,
             node_id,
+            storage,
         }
     }
 }
 
 impl RaftService for RaftServer {
     fn status(&mut self, ctx: RpcContext, _req: Null, sink: UnarySink<StatusReply>) {
+        let _timer = metrics::start_request_timer("raft", "status");
+
         let (s1, r1) = mpsc::channel();
         let sender = self.sender.clone();
         let node_id = self.node_id;
+        let storage = self.storage.clone();
 
         sender
             .send(config::Msg::Read {
@@ -52,6 +67,8 @@ impl RaftService for RaftServer {
                             reply.set_leader_id(node_id);
                         }
                         reply.set_address_map(addresses);
+                        reply.set_node_id(node_id);
+                        reply.set_storage(storage.clone());
                         s1.send(reply).expect("callback channel closed");
                     },
                 ),
@@ -68,6 +85,8 @@ impl RaftService for RaftServer {
             }
         };
 
+        metrics::count_request("raft", "status", reply.get_state());
+
         let f = sink
             .success(reply.clone())
             .map_err(move |err| error!("failed to reply: {:?}", err));
@@ -75,19 +94,19 @@ impl RaftService for RaftServer {
     }
 
     fn change_config(&mut self, ctx: RpcContext, req: ConfChange, sink: UnarySink<ChangeReply>) {
+        let _timer = metrics::start_request_timer("raft", "change_config");
+
         let (s1, r1) = mpsc::channel();
         let sender = self.sender.clone();
-        let seq = self.seq;
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
         let node_id = self.node_id;
 
-        self.seq += 1;
-
         sender
             .send(config::Msg::ConfigChange {
                 seq,
                 change: req,
                 cb: Box::new(
-                    move |leader_id: i32, addresses: HashMap<u64, NodeAddress>| {
+                    move |leader_id: i32, addresses: HashMap<u64, NodeAddress>, applied: bool| {
                         let mut reply = ChangeReply::new();
                         if leader_id >= 0 {
                             // follower
@@ -95,7 +114,11 @@ impl RaftService for RaftServer {
                             reply.set_leader_id(leader_id as u64);
                         } else {
                             // leader
-                            reply.set_state(State::OK);
+                            if applied {
+                                reply.set_state(State::OK);
+                            } else {
+                                reply.set_state(State::PROPOSAL_DROPPED);
+                            }
                             reply.set_leader_id(node_id);
                         }
                         reply.set_address_map(addresses);
@@ -115,6 +138,8 @@ impl RaftService for RaftServer {
             }
         };
 
+        metrics::count_request("raft", "change_config", reply.get_state());
+
         let f = sink
             .success(reply.clone())
             .map_err(move |err| error!("failed to reply: {:?}", err));
@@ -122,12 +147,48 @@ impl RaftService for RaftServer {
     }
 
     fn send_msg(&mut self, _ctx: RpcContext, req: Message, _sink: ::grpcio::UnarySink<Null>) {
+        let _timer = metrics::start_request_timer("raft", "send_msg");
+        metrics::count_request("raft", "send_msg", State::OK);
+
         let sender = self.sender.clone();
         sender.send(config::Msg::Raft(req)).unwrap();
     }
 
     fn send_address(&mut self, _ctx: RpcContext, req: AddressState, _sink: UnarySink<Null>) {
+        let _timer = metrics::start_request_timer("raft", "send_address");
+        metrics::count_request("raft", "send_address", State::OK);
+
         let sender = self.sender.clone();
         sender.send(config::Msg::Address(req)).unwrap();
     }
+
+    // Receives the batches of messages from a peer, and acks each of them once it has been
+    // handed to the Raft loop.
+    fn send_messages(
+        &mut self,
+        ctx: RpcContext,
+        stream: RequestStream<MessageBatch>,
+        sink: DuplexSink<MessageBatchAck>,
+    ) {
+        let sender = self.sender.clone();
+        let acks = stream.map(move |mut batch| {
+            metrics::count_request("raft", "send_messages", State::OK);
+            // The addresses come first, so that the replies can be sent to a new leader.
+            if batch.has_address_state() {
+                sender
+                    .send(config::Msg::Address(batch.take_address_state()))
+                    .unwrap();
+            }
+            for msg in batch.take_msgs().into_iter() {
+                sender.send(config::Msg::Raft(msg)).unwrap();
+            }
+            (MessageBatchAck::new(), WriteFlags::default())
+        });
+
+        let f = sink
+            .send_all(acks)
+            .map(|_| ())
+            .map_err(|err| debug!("raft message stream closed: {:?}", err));
+        ctx.spawn(f);
+    }
 }
@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{
+    self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError, TrySendError,
+};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{future, Future, Sink, Stream};
+use grpcio::{EnvBuilder, RpcStatus, RpcStatusCode, WriteFlags};
+use log::*;
+use raft::eraftpb::Message;
+
+use meteora_proto::proto::raft::{AddressState, MessageBatch};
+use meteora_proto::proto::raft_grpc::RaftServiceClient;
+
+use crate::metrics;
+use crate::raft::config::Msg;
+use crate::tls::{self, TlsConfig};
+
+// The number of messages waiting to be sent to a peer. Messages are dropped when it is full,
+// and Raft sends them again later.
+const QUEUE_SIZE: usize = 4096;
+const MAX_BATCH_SIZE: usize = 256;
+// The number of batches sent to a peer without an ack. The queue fills up when it is reached.
+const MAX_INFLIGHT_BATCHES: usize = 32;
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+// A message to a peer. The leader attaches the addresses of the nodes.
+struct Outgoing {
+    msg: Message,
+    address_state: Option<Arc<AddressState>>,
+}
+
+struct Peer {
+    address: String,
+    queue: SyncSender<Outgoing>,
+}
+
+// Sends Raft messages to the peers. Each peer has a long-lived stream, fed by a thread that
+// sends the queued messages in batches.
+pub struct Transport {
+    node_id: u64,
+    peers: HashMap<u64, Peer>,
+    // Failures are reported to the Raft loop through it.
+    raft_sender: Sender<Msg>,
+    tls: Option<TlsConfig>,
+}
+
+impl Transport {
+    pub fn new(node_id: u64, raft_sender: Sender<Msg>, tls: Option<TlsConfig>) -> Transport {
+        Transport {
+            node_id,
+            peers: HashMap::new(),
+            raft_sender,
+            tls,
+        }
+    }
+
+    // Starts sending to the peer, or starts again if its address has changed.
+    pub fn add_peer(&mut self, id: u64, address: &str) {
+        if id == self.node_id {
+            return;
+        }
+        if let Some(peer) = self.peers.get(&id) {
+            if peer.address == address {
+                return;
+            }
+        }
+        debug!("add peer: id={}, address={}", id, address);
+        let (queue, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        let worker = Worker {
+            id,
+            address: address.to_string(),
+            queue: receiver,
+            raft_sender: self.raft_sender.clone(),
+            tls: self.tls.clone(),
+        };
+        thread::Builder::new()
+            .name(format!("raft-peer-{}", id))
+            .spawn(move || worker.run())
+            .unwrap();
+        self.peers.insert(
+            id,
+            Peer {
+                address: address.to_string(),
+                queue,
+            },
+        );
+    }
+
+    // The thread of the peer stops once its queue is dropped, even if the peer can't be
+    // reached.
+    pub fn remove_peer(&mut self, id: u64) {
+        if self.peers.remove(&id).is_some() {
+            debug!("remove peer: id={}", id);
+        }
+    }
+
+    pub fn send(&self, msg: Message, address_state: Option<Arc<AddressState>>) {
+        let to = msg.get_to();
+        let peer = match self.peers.get(&to) {
+            Some(peer) => peer,
+            None => {
+                debug!("unknown peer: id={}", to);
+                return;
+            }
+        };
+        match peer.queue.try_send(Outgoing { msg, address_state }) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                debug!("queue is full: id={}", to);
+                metrics::RAFT_MESSAGES_DROPPED_TOTAL.inc();
+                let _ = self.raft_sender.send(Msg::Unreachable(to));
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+struct Worker {
+    id: u64,
+    address: String,
+    queue: Receiver<Outgoing>,
+    raft_sender: Sender<Msg>,
+    tls: Option<TlsConfig>,
+}
+
+impl Worker {
+    fn run(self) {
+        let env = Arc::new(EnvBuilder::new().build());
+        // The credentials are read again until they can be, since the files may be replaced.
+        let ch = loop {
+            match tls::connect(env.clone(), &self.address, self.tls.as_ref()) {
+                Ok(ch) => break ch,
+                Err(e) => {
+                    error!(
+                        "failed to connect to peer: id={}, address={}, error={}",
+                        self.id, self.address, e
+                    );
+                    if self.raft_sender.send(Msg::Unreachable(self.id)).is_err() || !self.wait() {
+                        return;
+                    }
+                }
+            }
+        };
+        let client = RaftServiceClient::new(ch);
+
+        loop {
+            match self.stream(&client) {
+                Ok(()) => {
+                    debug!("stop sending to peer: id={}", self.id);
+                    return;
+                }
+                Err(e) => {
+                    warn!(
+                        "failed to send to peer: id={}, address={}, error={:?}",
+                        self.id, self.address, e
+                    );
+                    // Raft probes the peer instead of replicating to it until it answers again.
+                    if self.raft_sender.send(Msg::Unreachable(self.id)).is_err() || !self.wait() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // Waits before the peer is tried again. The messages queued meanwhile are dropped, since
+    // Raft sends them again once the peer answers. Returns false if the peer has been removed.
+    fn wait(&self) -> bool {
+        let deadline = Instant::now() + RECONNECT_INTERVAL;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.queue.recv_timeout(timeout) {
+                Ok(_) => metrics::RAFT_MESSAGES_DROPPED_TOTAL.inc(),
+                Err(RecvTimeoutError::Timeout) => return true,
+                Err(RecvTimeoutError::Disconnected) => return false,
+            }
+        }
+    }
+
+    // Sends the queued messages over a new stream until the queue is dropped or the stream
+    // fails.
+    fn stream(&self, client: &RaftServiceClient) -> grpcio::Result<()> {
+        let (mut sink, receiver) = client.send_messages()?;
+
+        let (ack_sender, acks) = mpsc::channel();
+        thread::spawn(move || {
+            for ack in receiver.wait() {
+                if ack.is_err() || ack_sender.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut inflight = 0;
+        loop {
+            let first = match self.queue.recv() {
+                Ok(outgoing) => outgoing,
+                Err(_) => {
+                    future::poll_fn(|| sink.close()).wait()?;
+                    return Ok(());
+                }
+            };
+
+            // Coalesce the messages that have been queued meanwhile. Only the latest addresses
+            // are sent.
+            let mut batch = MessageBatch::new();
+            let mut address_state = first.address_state;
+            batch.mut_msgs().push(first.msg);
+            while batch.get_msgs().len() < MAX_BATCH_SIZE {
+                match self.queue.try_recv() {
+                    Ok(outgoing) => {
+                        if outgoing.address_state.is_some() {
+                            address_state = outgoing.address_state;
+                        }
+                        batch.mut_msgs().push(outgoing.msg);
+                    }
+                    Err(_) => break,
+                }
+            }
+            if let Some(address_state) = address_state {
+                batch.set_address_state((*address_state).clone());
+            }
+
+            sink = sink.send((batch, WriteFlags::default())).wait()?;
+            inflight += 1;
+
+            // Take the acks received so far, and wait for one if too many batches are in flight.
+            loop {
+                let acked = if inflight < MAX_INFLIGHT_BATCHES {
+                    match acks.try_recv() {
+                        Ok(()) => true,
+                        Err(TryRecvError::Empty) => false,
+                        Err(TryRecvError::Disconnected) => {
+                            return Err(grpcio::Error::RemoteStopped)
+                        }
+                    }
+                } else {
+                    match acks.recv_timeout(ACK_TIMEOUT) {
+                        Ok(()) => true,
+                        Err(RecvTimeoutError::Timeout) => {
+                            return Err(grpcio::Error::RpcFailure(RpcStatus::new(
+                                RpcStatusCode::DeadlineExceeded,
+                                Some("no ack from the peer".to_string()),
+                            )))
+                        }
+                        Err(RecvTimeoutError::Disconnected) => {
+                            return Err(grpcio::Error::RemoteStopped)
+                        }
+                    }
+                };
+                if !acked {
+                    break;
+                }
+                inflight -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+
+    use grpcio::{
+        DuplexSink, Environment, RequestStream, RpcContext, Server, ServerBuilder, UnarySink,
+    };
+    use raft::eraftpb::ConfChange;
+
+    use meteora_proto::proto::common::Null;
+    use meteora_proto::proto::raft::{ChangeReply, MessageBatchAck, StatusReply};
+    use meteora_proto::proto::raft_grpc::{create_raft_service, RaftService};
+
+    // A peer that records the batches of each stream. It acks them only if `ack` is set.
+    #[derive(Clone)]
+    struct FakePeer {
+        ack: bool,
+        streams: Arc<Mutex<Vec<Vec<MessageBatch>>>>,
+        silent_sinks: Arc<Mutex<Vec<DuplexSink<MessageBatchAck>>>>,
+    }
+
+    fn unimplemented<T>(ctx: RpcContext, sink: UnarySink<T>) {
+        ctx.spawn(
+            sink.fail(RpcStatus::new(RpcStatusCode::Unimplemented, None))
+                .map_err(|_| ()),
+        );
+    }
+
+    impl RaftService for FakePeer {
+        fn status(&mut self, ctx: RpcContext, _req: Null, sink: UnarySink<StatusReply>) {
+            unimplemented(ctx, sink);
+        }
+
+        fn change_config(
+            &mut self,
+            ctx: RpcContext,
+            _req: ConfChange,
+            sink: UnarySink<ChangeReply>,
+        ) {
+            unimplemented(ctx, sink);
+        }
+
+        fn send_msg(&mut self, ctx: RpcContext, _req: Message, sink: UnarySink<Null>) {
+            unimplemented(ctx, sink);
+        }
+
+        fn send_address(&mut self, ctx: RpcContext, _req: AddressState, sink: UnarySink<Null>) {
+            unimplemented(ctx, sink);
+        }
+
+        fn send_messages(
+            &mut self,
+            ctx: RpcContext,
+            stream: RequestStream<MessageBatch>,
+            sink: DuplexSink<MessageBatchAck>,
+        ) {
+            let index = {
+                let mut streams = self.streams.lock().unwrap();
+                streams.push(Vec::new());
+                streams.len() - 1
+            };
+            let streams = self.streams.clone();
+            let batches = stream.map(move |batch| {
+                streams.lock().unwrap()[index].push(batch);
+                (MessageBatchAck::new(), WriteFlags::default())
+            });
+            if self.ack {
+                ctx.spawn(sink.send_all(batches).map(|_| ()).map_err(|_| ()));
+            } else {
+                // The sink is kept open, so that the stream is not closed without acks.
+                self.silent_sinks.lock().unwrap().push(sink);
+                ctx.spawn(batches.for_each(|_| Ok(())).map_err(|_| ()));
+            }
+        }
+    }
+
+    fn start_peer(ack: bool) -> (Server, String, Arc<Mutex<Vec<Vec<MessageBatch>>>>) {
+        let peer = FakePeer {
+            ack,
+            streams: Arc::new(Mutex::new(Vec::new())),
+            silent_sinks: Arc::new(Mutex::new(Vec::new())),
+        };
+        let streams = peer.streams.clone();
+        let mut server = ServerBuilder::new(Arc::new(Environment::new(1)))
+            .register_service(create_raft_service(peer))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let (host, port) = server.bind_addrs()[0].clone();
+        (server, format!("{}:{}", host, port), streams)
+    }
+
+    fn outgoing(index: u64, address_state: bool) -> Outgoing {
+        let mut msg = Message::new();
+        msg.set_to(2);
+        msg.set_index(index);
+        Outgoing {
+            msg,
+            address_state: if address_state {
+                Some(Arc::new(AddressState::new()))
+            } else {
+                None
+            },
+        }
+    }
+
+    fn wait_until<F: Fn() -> bool>(f: F) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !f() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_batches() {
+        let (_server, address, streams) = start_peer(true);
+        let (raft_sender, raft_receiver) = mpsc::channel();
+        let (queue, receiver) = mpsc::sync_channel(600);
+        for i in 0..600 {
+            queue.send(outgoing(i, i == 0 || i == 300)).unwrap();
+        }
+        drop(queue);
+
+        let worker = Worker {
+            id: 2,
+            address,
+            queue: receiver,
+            raft_sender,
+            tls: None,
+        };
+        worker.run();
+
+        wait_until(|| streams.lock().unwrap().get(0).map_or(0, |s| s.len()) == 3);
+        let streams = streams.lock().unwrap();
+        assert_eq!(streams.len(), 1);
+        let batches = &streams[0];
+        let sizes: Vec<usize> = batches.iter().map(|b| b.get_msgs().len()).collect();
+        assert_eq!(
+            sizes,
+            vec![MAX_BATCH_SIZE, MAX_BATCH_SIZE, 600 - 2 * MAX_BATCH_SIZE]
+        );
+        let addresses: Vec<bool> = batches.iter().map(|b| b.has_address_state()).collect();
+        assert_eq!(addresses, vec![true, true, false]);
+        let indexes: Vec<u64> = batches
+            .iter()
+            .flat_map(|b| b.get_msgs().iter().map(|m| m.get_index()))
+            .collect();
+        assert_eq!(indexes, (0..600).collect::<Vec<u64>>());
+        assert!(raft_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_inflight_batches() {
+        let (_server, address, streams) = start_peer(false);
+        let (raft_sender, raft_receiver) = mpsc::channel();
+        let count = (MAX_INFLIGHT_BATCHES + 1) * MAX_BATCH_SIZE;
+        let (queue, receiver) = mpsc::sync_channel(count);
+        for i in 0..count {
+            queue.send(outgoing(i as u64, false)).unwrap();
+        }
+
+        let worker = Worker {
+            id: 2,
+            address,
+            queue: receiver,
+            raft_sender,
+            tls: None,
+        };
+        let handle = thread::spawn(move || worker.run());
+
+        // No more batches are sent without acks, and the peer is reported once the ack is
+        // overdue.
+        match raft_receiver.recv_timeout(ACK_TIMEOUT * 2) {
+            Ok(Msg::Unreachable(2)) => {}
+            _ => panic!("the peer is not reported"),
+        }
+        assert_eq!(streams.lock().unwrap()[0].len(), MAX_INFLIGHT_BATCHES);
+
+        drop(queue);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_full_queue() {
+        let (raft_sender, raft_receiver) = mpsc::channel();
+        let mut transport = Transport::new(1, raft_sender, None);
+        let (queue, _receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        transport.peers.insert(
+            2,
+            Peer {
+                address: "127.0.0.1:0".to_string(),
+                queue,
+            },
+        );
+
+        for i in 0..QUEUE_SIZE {
+            transport.send(outgoing(i as u64, false).msg, None);
+        }
+        assert!(raft_receiver.try_recv().is_err());
+        transport.send(outgoing(QUEUE_SIZE as u64, false).msg, None);
+        match raft_receiver.try_recv() {
+            Ok(Msg::Unreachable(2)) => {}
+            _ => panic!("the peer is not reported"),
+        }
+    }
+
+    #[test]
+    fn test_remove_unreachable_peer() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (raft_sender, raft_receiver) = mpsc::channel();
+        let mut transport = Transport::new(1, raft_sender, None);
+        transport.add_peer(2, &format!("127.0.0.1:{}", port));
+
+        // Raft keeps sending, as with heartbeats, until the peer is reported.
+        let mut index = 0;
+        loop {
+            transport.send(outgoing(index, false).msg, None);
+            index += 1;
+            match raft_receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(Msg::Unreachable(2)) => break,
+                Ok(_) => panic!("unexpected message"),
+                Err(_) => assert!(index < 100, "the peer is not reported"),
+            }
+        }
+
+        // The worker holds the last sender once the transport is gone, so the channel is
+        // closed when it stops.
+        transport.remove_peer(2);
+        drop(transport);
+        loop {
+            match raft_receiver.recv_timeout(RECONNECT_INTERVAL * 10) {
+                Ok(Msg::Unreachable(2)) => {}
+                Ok(_) => panic!("unexpected message"),
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => panic!("the worker has not stopped"),
+            }
+        }
+    }
+}
@@ -0,0 +1,390 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[cfg(feature = "engine-rocksdb")]
+use rocksdb::{BlockBasedOptions, Cache, DBCompressionType, Options};
+use serde::{Deserialize, Serialize};
+
+use meteora_proto::proto::raft::StorageStatus;
+
+use crate::engine::memory::MemoryEngine;
+#[cfg(feature = "engine-redb")]
+use crate::engine::redb::RedbEngine;
+#[cfg(feature = "engine-rocksdb")]
+use crate::engine::rocks::RocksEngine;
+use crate::engine::KvEngine;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    Rocksdb,
+    // A pure Rust engine, built with the engine-redb feature.
+    Redb,
+    // Keeps the data in memory, for tests and caches. The data of a node is lost when it stops.
+    Memory,
+}
+
+impl FromStr for Engine {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Engine, Error> {
+        match s {
+            "rocksdb" => Ok(Engine::Rocksdb),
+            "redb" => Ok(Engine::Redb),
+            "memory" => Ok(Engine::Memory),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown engine, expected rocksdb, redb or memory: {}", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Engine::Rocksdb => "rocksdb",
+            Engine::Redb => "redb",
+            Engine::Memory => "memory",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Engine {
+    // Whether the engine was built in. RocksDB and redb are cargo features.
+    pub fn is_available(&self) -> bool {
+        match self {
+            Engine::Rocksdb => cfg!(feature = "engine-rocksdb"),
+            Engine::Redb => cfg!(feature = "engine-redb"),
+            Engine::Memory => true,
+        }
+    }
+}
+
+// RocksDB when it is built in, otherwise redb.
+fn default_engine() -> Engine {
+    if cfg!(feature = "engine-rocksdb") || !cfg!(feature = "engine-redb") {
+        Engine::Rocksdb
+    } else {
+        Engine::Redb
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Compression, Error> {
+        match s {
+            "none" => Ok(Compression::None),
+            "snappy" => Ok(Compression::Snappy),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "unknown compression, expected none, snappy, lz4 or zstd: {}",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Compression::None => "none",
+            Compression::Snappy => "snappy",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// The engine of the key-value store and its RocksDB options. The defaults are the ones of
+// RocksDB.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub engine: Engine,
+    // The size in bytes of the LRU cache of uncompressed blocks.
+    pub block_cache_size: u64,
+    pub compression: Compression,
+    // The bits per key of the bloom filters, which save disk reads for missing keys. 0
+    // disables them.
+    pub bloom_filter_bits_per_key: u32,
+    // The size in bytes of a memtable before it is flushed.
+    pub write_buffer_size: u64,
+    // The number of threads that flush and compact.
+    pub max_background_jobs: i32,
+    // A directory for the write-ahead log, for example on a faster device. None keeps it with
+    // the data.
+    pub wal_dir: Option<String>,
+    // The total size in bytes of the write-ahead logs that forces memtables to be flushed. 0
+    // lets RocksDB choose.
+    pub max_total_wal_size: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> StorageConfig {
+        StorageConfig {
+            engine: default_engine(),
+            block_cache_size: 8 * 1024 * 1024,
+            compression: Compression::Snappy,
+            bloom_filter_bits_per_key: 0,
+            write_buffer_size: 64 * 1024 * 1024,
+            max_background_jobs: 2,
+            wal_dir: None,
+            max_total_wal_size: 0,
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if !self.engine.is_available() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "the {} engine is not built in, build with the engine-{} feature",
+                    self.engine, self.engine
+                ),
+            ));
+        }
+        if self.write_buffer_size == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "write buffer size must be greater than 0",
+            ));
+        }
+        if self.max_background_jobs < 1 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "max background jobs must be greater than 0",
+            ));
+        }
+        if self.wal_dir.as_deref() == Some("") {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "WAL directory must not be empty",
+            ));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "engine-rocksdb")]
+    pub fn options(&self) -> Result<Options, Error> {
+        let cache = Cache::new_lru_cache(self.block_cache_size as usize).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to create block cache: {}", e),
+            )
+        })?;
+        let mut block_opts = BlockBasedOptions::default();
+        block_opts.set_block_cache(&cache);
+        if self.bloom_filter_bits_per_key > 0 {
+            block_opts.set_bloom_filter(self.bloom_filter_bits_per_key as i32, false);
+        }
+
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.set_block_based_table_factory(&block_opts);
+        opts.set_compression_type(match self.compression {
+            Compression::None => DBCompressionType::None,
+            Compression::Snappy => DBCompressionType::Snappy,
+            Compression::Lz4 => DBCompressionType::Lz4,
+            Compression::Zstd => DBCompressionType::Zstd,
+        });
+        opts.set_write_buffer_size(self.write_buffer_size as usize);
+        opts.set_max_background_jobs(self.max_background_jobs);
+        if let Some(wal_dir) = &self.wal_dir {
+            opts.set_wal_dir(wal_dir);
+        }
+        if self.max_total_wal_size > 0 {
+            opts.set_max_total_wal_size(self.max_total_wal_size);
+        }
+        Ok(opts)
+    }
+
+    pub fn open(&self, path: &str) -> Result<Arc<dyn KvEngine>, Error> {
+        match self.engine {
+            #[cfg(feature = "engine-rocksdb")]
+            Engine::Rocksdb => Ok(Arc::new(RocksEngine::open(path, self)?)),
+            #[cfg(feature = "engine-redb")]
+            Engine::Redb => Ok(Arc::new(RedbEngine::open(path)?)),
+            Engine::Memory => Ok(Arc::new(MemoryEngine::new())),
+            #[allow(unreachable_patterns)]
+            engine => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("the {} engine is not built in", engine),
+            )),
+        }
+    }
+
+    pub fn status(&self) -> StorageStatus {
+        let mut status = StorageStatus::new();
+        status.set_engine(self.engine.to_string());
+        status.set_block_cache_size(self.block_cache_size);
+        status.set_compression(self.compression.to_string());
+        status.set_bloom_filter_bits_per_key(self.bloom_filter_bits_per_key);
+        status.set_write_buffer_size(self.write_buffer_size);
+        status.set_max_background_jobs(self.max_background_jobs);
+        status.set_wal_dir(self.wal_dir.clone().unwrap_or_default());
+        status.set_max_total_wal_size(self.max_total_wal_size);
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        for engine in &[Engine::Rocksdb, Engine::Redb, Engine::Memory] {
+            assert_eq!(engine.to_string().parse::<Engine>().unwrap(), *engine);
+        }
+        assert!("leveldb".parse::<Engine>().is_err());
+        for compression in &[
+            Compression::None,
+            Compression::Snappy,
+            Compression::Lz4,
+            Compression::Zstd,
+        ] {
+            assert_eq!(
+                compression.to_string().parse::<Compression>().unwrap(),
+                *compression
+            );
+        }
+        assert!("gzip".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(StorageConfig {
+            engine: Engine::Memory,
+            ..StorageConfig::default()
+        }
+        .validate()
+        .is_ok());
+
+        let invalid = vec![
+            StorageConfig {
+                engine: Engine::Memory,
+                write_buffer_size: 0,
+                ..StorageConfig::default()
+            },
+            StorageConfig {
+                engine: Engine::Memory,
+                max_background_jobs: 0,
+                ..StorageConfig::default()
+            },
+            StorageConfig {
+                engine: Engine::Memory,
+                wal_dir: Some(String::new()),
+                ..StorageConfig::default()
+            },
+        ];
+        for config in invalid {
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{:?}", config);
+        }
+
+        for engine in &[Engine::Rocksdb, Engine::Redb] {
+            let config = StorageConfig {
+                engine: *engine,
+                ..StorageConfig::default()
+            };
+            assert_eq!(config.validate().is_ok(), engine.is_available());
+        }
+    }
+
+    #[test]
+    fn test_status() {
+        let config = StorageConfig {
+            engine: Engine::Memory,
+            block_cache_size: 1024,
+            compression: Compression::Zstd,
+            bloom_filter_bits_per_key: 10,
+            write_buffer_size: 2048,
+            max_background_jobs: 4,
+            wal_dir: Some("/wal".to_string()),
+            max_total_wal_size: 4096,
+        };
+        let status = config.status();
+        assert_eq!(status.get_engine(), "memory");
+        assert_eq!(status.get_block_cache_size(), 1024);
+        assert_eq!(status.get_compression(), "zstd");
+        assert_eq!(status.get_bloom_filter_bits_per_key(), 10);
+        assert_eq!(status.get_write_buffer_size(), 2048);
+        assert_eq!(status.get_max_background_jobs(), 4);
+        assert_eq!(status.get_wal_dir(), "/wal");
+        assert_eq!(status.get_max_total_wal_size(), 4096);
+
+        // No WAL directory is reported as an empty one.
+        let status = StorageConfig::default().status();
+        assert_eq!(status.get_wal_dir(), "");
+        assert_eq!(status.get_engine(), default_engine().to_string());
+    }
+
+    #[cfg(feature = "engine-rocksdb")]
+    #[test]
+    fn test_rocksdb_options() {
+        use crate::engine::tests::TempDir;
+
+        for compression in &[
+            Compression::None,
+            Compression::Snappy,
+            Compression::Lz4,
+            Compression::Zstd,
+        ] {
+            let dir = TempDir::new();
+            let wal_dir = TempDir::new();
+            let config = StorageConfig {
+                engine: Engine::Rocksdb,
+                block_cache_size: 4 * 1024 * 1024,
+                compression: *compression,
+                bloom_filter_bits_per_key: 10,
+                write_buffer_size: 1024 * 1024,
+                max_background_jobs: 1,
+                wal_dir: Some(wal_dir.path().to_string()),
+                max_total_wal_size: 16 * 1024 * 1024,
+            };
+            let db = config.open(dir.path()).unwrap();
+            db.put(b"a", b"1").unwrap();
+            assert_eq!(db.get(b"a").unwrap(), Some(b"1".to_vec()));
+
+            assert_eq!(
+                db.property_int_value("rocksdb.block-cache-capacity")
+                    .unwrap(),
+                Some(4 * 1024 * 1024)
+            );
+            // The write-ahead log is kept in its own directory.
+            let logs = std::fs::read_dir(wal_dir.path())
+                .unwrap()
+                .filter(|entry| {
+                    entry
+                        .as_ref()
+                        .unwrap()
+                        .path()
+                        .extension()
+                        .map_or(false, |e| e == "log")
+                })
+                .count();
+            assert!(logs > 0);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+use grpcio::{
+    Channel, ChannelBuilder, ChannelCredentials, ChannelCredentialsBuilder, Environment,
+    ServerCredentials, ServerCredentialsBuilder,
+};
+
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    pub ca_file: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn new(
+        cert_file: Option<&str>,
+        key_file: Option<&str>,
+        ca_file: Option<&str>,
+    ) -> TlsConfig {
+        TlsConfig {
+            cert_file: cert_file.map(|f| f.to_string()),
+            key_file: key_file.map(|f| f.to_string()),
+            ca_file: ca_file.map(|f| f.to_string()),
+        }
+    }
+
+    // If `verify_client` is true, the peer must present a certificate signed by the CA.
+    pub fn server_credentials(&self, verify_client: bool) -> Result<ServerCredentials, Error> {
+        let (cert, key) = match self.cert_and_key()? {
+            Some(cert_and_key) => cert_and_key,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "TLS requires both a certificate and a private key",
+                ));
+            }
+        };
+
+        let mut builder = ServerCredentialsBuilder::new();
+        match &self.ca_file {
+            Some(ca_file) => {
+                builder = builder.root_cert(read_pem(ca_file)?, verify_client);
+            }
+            None => {
+                if verify_client {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "client certificate verification requires a CA certificate",
+                    ));
+                }
+            }
+        }
+
+        Ok(builder.add_cert(cert, key).build())
+    }
+
+    // A node presents its own certificate and key as the client certificate,
+    // so that cluster members can authenticate each other.
+    pub fn channel_credentials(&self) -> Result<ChannelCredentials, Error> {
+        let mut builder = ChannelCredentialsBuilder::new();
+        if let Some(ca_file) = &self.ca_file {
+            builder = builder.root_cert(read_pem(ca_file)?);
+        }
+        if let Some((cert, key)) = self.cert_and_key()? {
+            builder = builder.cert(cert, key);
+        }
+
+        Ok(builder.build())
+    }
+
+    fn cert_and_key(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>, Error> {
+        match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                Ok(Some((read_pem(cert_file)?, read_pem(key_file)?)))
+            }
+            (None, None) => Ok(None),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "both a certificate and a private key must be specified",
+            )),
+        }
+    }
+}
+
+pub fn connect(
+    env: Arc<Environment>,
+    address: &str,
+    tls: Option<&TlsConfig>,
+) -> Result<Channel, Error> {
+    let builder = ChannelBuilder::new(env);
+    match tls {
+        Some(tls) => Ok(builder.secure_connect(address, tls.channel_credentials()?)),
+        None => Ok(builder.connect(address)),
+    }
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, Error> {
+    fs::read(path).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("failed to read PEM file: path={}, error={}", path, e),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use grpcio::EnvBuilder;
+
+    const MISSING: &str = "/nonexistent/meteora/tls.pem";
+
+    #[test]
+    fn test_server_requires_cert_and_key() {
+        let tls = TlsConfig::new(None, None, None);
+        let e = tls.server_credentials(false).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+        let tls = TlsConfig::new(Some(MISSING), None, None);
+        let e = tls.server_credentials(false).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let tls = TlsConfig::new(Some(MISSING), Some(MISSING), None);
+        let e = tls.server_credentials(false).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+        assert!(e.to_string().contains(MISSING));
+
+        let tls = TlsConfig::new(None, None, Some(MISSING));
+        assert_eq!(
+            tls.channel_credentials().err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_connect_returns_error() {
+        let env = Arc::new(EnvBuilder::new().build());
+        let tls = TlsConfig::new(None, None, Some(MISSING));
+        let e = connect(env.clone(), "127.0.0.1:1", Some(&tls))
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+
+        let tls = TlsConfig::new(Some(MISSING), None, None);
+        let e = connect(env, "127.0.0.1:1", Some(&tls)).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_connect_without_tls() {
+        let env = Arc::new(EnvBuilder::new().build());
+        assert!(connect(env, "127.0.0.1:1", None).is_ok());
+    }
+}
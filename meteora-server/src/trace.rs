@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::str;
+
+use grpcio::{MetadataBuilder, RpcContext};
+use log::*;
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace as sdktrace, Resource};
+use opentelemetry::trace::{Link, TraceContextExt};
+use opentelemetry::{Context, KeyValue};
+use tokio::runtime::{Builder, Runtime};
+
+// Exports spans to an OpenTelemetry collector over OTLP until it is dropped.
+//
+// Spans are created with the global tracer, which does nothing until this is initialized, so
+// tracing costs almost nothing when no collector is configured.
+pub struct Tracing {
+    // The OTLP exporter runs on tokio, so it has a runtime of its own.
+    _runtime: Runtime,
+}
+
+impl Tracing {
+    pub fn init(endpoint: &str, service_name: &str) -> Result<Tracing, Error> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("otlp-exporter")
+            .enable_all()
+            .build()?;
+        let _guard = runtime.enter();
+
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        opentelemetry_otlp::new_pipeline()
+            .with_endpoint(endpoint)
+            .with_trace_config(sdktrace::config().with_resource(Resource::new(vec![
+                KeyValue::new("service.name", service_name.to_string()),
+            ])))
+            .with_tonic()
+            .install_batch(opentelemetry::runtime::Tokio)
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!(
+                        "failed to install OTLP exporter: endpoint={}, error={}",
+                        endpoint, e
+                    ),
+                )
+            })?;
+        info!("export traces to {}", endpoint);
+
+        Ok(Tracing { _runtime: runtime })
+    }
+}
+
+impl Drop for Tracing {
+    fn drop(&mut self) {
+        // Export the spans that are still buffered.
+        global::shutdown_tracer_provider();
+    }
+}
+
+pub fn tracer() -> BoxedTracer {
+    global::tracer("meteora")
+}
+
+// Serializes the trace context so that it can be carried in a Raft entry.
+pub fn to_carrier(cx: &Context) -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(cx, &mut carrier));
+    carrier
+}
+
+pub fn from_carrier(carrier: &HashMap<String, String>) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(carrier))
+}
+
+// Adds the trace context to the metadata of an outgoing request.
+pub fn inject(cx: &Context, builder: &mut MetadataBuilder) {
+    for (key, value) in to_carrier(cx) {
+        if let Err(e) = builder.add_str(&key, &value) {
+            warn!("failed to add trace context: key={}, error={:?}", key, e);
+        }
+    }
+}
+
+// Reads the trace context from the metadata of an incoming request.
+pub fn extract(ctx: &RpcContext) -> Context {
+    let carrier: HashMap<String, String> = ctx
+        .request_headers()
+        .iter()
+        .filter_map(|(key, value)| {
+            str::from_utf8(value)
+                .ok()
+                .map(|value| (key.to_string(), value.to_string()))
+        })
+        .collect();
+    from_carrier(&carrier)
+}
+
+// Returns a link to the span of the context, or None if the context is not traced.
+pub fn link(cx: &Context) -> Option<Link> {
+    let span_context = cx.span().span_context().clone();
+    if span_context.is_valid() {
+        Some(Link::new(span_context, Vec::new()))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    fn traced_context() -> Context {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let mut carrier = HashMap::new();
+        carrier.insert("traceparent".to_string(), TRACEPARENT.to_string());
+        from_carrier(&carrier)
+    }
+
+    #[test]
+    fn test_carrier_round_trip() {
+        let cx = traced_context();
+        let span_context = cx.span().span_context().clone();
+        assert!(span_context.is_valid());
+        assert!(span_context.is_remote());
+
+        // A Raft entry carries the context of the proposal to the apply step.
+        let carrier = to_carrier(&cx);
+        assert_eq!(carrier["traceparent"], TRACEPARENT);
+        let applied = from_carrier(&carrier);
+        assert_eq!(
+            applied.span().span_context().trace_id(),
+            span_context.trace_id()
+        );
+        assert_eq!(
+            applied.span().span_context().span_id(),
+            span_context.span_id()
+        );
+    }
+
+    #[test]
+    fn test_untraced_context() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        assert!(to_carrier(&Context::new()).is_empty());
+        assert!(link(&Context::new()).is_none());
+        assert!(!from_carrier(&HashMap::new())
+            .span()
+            .span_context()
+            .is_valid());
+    }
+
+    #[test]
+    fn test_link() {
+        let cx = traced_context();
+        let link = link(&cx).unwrap();
+        assert_eq!(
+            link.span_context().trace_id(),
+            cx.span().span_context().trace_id()
+        );
+    }
+
+    #[test]
+    fn test_metadata() {
+        let cx = traced_context();
+        let mut builder = MetadataBuilder::with_capacity(1);
+        inject(&cx, &mut builder);
+        let metadata = builder.build();
+        let traceparent = metadata
+            .iter()
+            .find(|(key, _)| *key == "traceparent")
+            .map(|(_, value)| value);
+        assert_eq!(traceparent, Some(TRACEPARENT.as_bytes()));
+    }
+}
@@ -1,6 +1,17 @@
+pub mod authenticate;
+pub mod change_password;
+pub mod config;
 pub mod delete;
 pub mod get;
 pub mod leave;
 pub mod put;
+pub mod role_add;
+pub mod role_delete;
+pub mod role_grant;
+pub mod role_revoke;
 pub mod start;
 pub mod status;
+pub mod user_add;
+pub mod user_delete;
+pub mod user_grant_role;
+pub mod user_revoke_role;
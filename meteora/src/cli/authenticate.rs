@@ -0,0 +1,28 @@
+use clap::ArgMatches;
+
+use meteora_client::auth::client::AuthClient;
+
+use crate::log::set_logger;
+use crate::tls::tls_config;
+
+pub fn run_authenticate_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
+    set_logger();
+
+    let address = matches.value_of("ADDRESS").unwrap();
+    let name = matches.value_of("NAME").unwrap();
+    let password = matches.value_of("PASSWORD").unwrap();
+
+    let seeds: Vec<&str> = address.split(',').collect();
+    let mut auth_client = AuthClient::connect(&seeds, tls_config(matches))?;
+
+    match auth_client.authenticate(name, password) {
+        Ok(token) => {
+            println!("{}", token);
+            Ok(())
+        }
+        Err(e) => {
+            println!("{}", e);
+            Err(e.into())
+        }
+    }
+}
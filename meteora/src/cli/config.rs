@@ -0,0 +1,47 @@
+use std::fs;
+use std::io::Error;
+
+use clap::ArgMatches;
+
+use meteora_server::encryption::KeyRing;
+
+use crate::config::NodeConfig;
+use crate::log::set_logger;
+
+pub fn run_config_cli(matches: &ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("check", Some(matches)) => run_config_check_cli(matches),
+        (subcommand, _) => panic!("Subcommand {} is unknown", subcommand),
+    }
+}
+
+// Validates the configuration that the start command would use, and the files it refers to,
+// without starting the node. The resulting configuration is printed, defaults included.
+fn run_config_check_cli(matches: &ArgMatches) -> Result<(), Error> {
+    set_logger();
+
+    let config = NodeConfig::from_matches(matches)?;
+
+    for file in [
+        &config.tls.cert_file,
+        &config.tls.key_file,
+        &config.tls.ca_file,
+    ]
+    .iter()
+    .copied()
+    .flatten()
+    {
+        fs::read(file).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("failed to read TLS file: path={}, error={}", file, e),
+            )
+        })?;
+    }
+    if let Some(key_file) = &config.encryption.key_file {
+        KeyRing::load(key_file)?;
+    }
+
+    println!("{}", serde_json::to_string_pretty(&config).unwrap());
+    Ok(())
+}
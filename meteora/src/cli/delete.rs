@@ -3,14 +3,22 @@ use clap::ArgMatches;
 use meteora_client::kv::client::KVClient;
 
 use crate::log::set_logger;
+use crate::tls::tls_config;
+use crate::trace::init_tracing;
 
 pub fn run_delete_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
     set_logger();
+    let _tracing = init_tracing(matches, "meteora-client")?;
 
     let address = matches.value_of("ADDRESS").unwrap();
     let key = matches.value_of("KEY").unwrap();
 
-    let mut kv_client = KVClient::new(address);
+    let seeds: Vec<&str> = address.split(',').collect();
+    let mut kv_client = KVClient::connect(&seeds, tls_config(matches))?;
+    if let Some(token) = matches.value_of("TOKEN") {
+        kv_client.set_token(token.to_string());
+    }
 
-    kv_client.delete(key.as_bytes().to_vec())
+    kv_client.delete(key.as_bytes().to_vec())?;
+    Ok(())
 }
@@ -3,6 +3,7 @@ use clap::ArgMatches;
 use meteora_client::raft::client::RaftClient;
 
 use crate::log::set_logger;
+use crate::tls::tls_config;
 
 pub fn run_leave_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
     set_logger();
@@ -10,7 +11,8 @@ pub fn run_leave_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
     let address = matches.value_of("ADDRESS").unwrap();
     let id = matches.value_of("ID").unwrap().parse::<u64>().unwrap();
 
-    let mut raft_client = RaftClient::new(address);
+    let seeds: Vec<&str> = address.split(',').collect();
+    let mut raft_client = RaftClient::connect(&seeds, tls_config(matches))?;
 
     match raft_client.leave(id) {
         Ok(v) => {
@@ -19,7 +21,7 @@ pub fn run_leave_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
         }
         Err(e) => {
             println!("{}", e);
-            Err(e)
+            Err(e.into())
         }
     }
 }
@@ -0,0 +1,37 @@
+use clap::ArgMatches;
+
+use meteora_client::auth::client::{prefix_range_end, AuthClient};
+use meteora_proto::proto::auth::PermissionType;
+
+use crate::log::set_logger;
+use crate::tls::tls_config;
+
+pub fn run_role_grant_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
+    set_logger();
+
+    let address = matches.value_of("ADDRESS").unwrap();
+    let name = matches.value_of("NAME").unwrap();
+    let perm_type = match matches.value_of("PERMISSION_TYPE").unwrap() {
+        "read" => PermissionType::READ,
+        "write" => PermissionType::WRITE,
+        _ => PermissionType::READWRITE,
+    };
+    let key = matches.value_of("KEY").unwrap().as_bytes().to_vec();
+    let range_end = if matches.is_present("PREFIX") {
+        prefix_range_end(&key)
+    } else {
+        matches
+            .value_of("RANGE_END")
+            .map(|r| r.as_bytes().to_vec())
+            .unwrap_or_default()
+    };
+
+    let seeds: Vec<&str> = address.split(',').collect();
+    let mut auth_client = AuthClient::connect(&seeds, tls_config(matches))?;
+    if let Some(token) = matches.value_of("TOKEN") {
+        auth_client.set_token(token.to_string());
+    }
+
+    auth_client.role_grant_permission(name, perm_type, key, range_end)?;
+    Ok(())
+}
@@ -10,30 +10,37 @@ use log::*;
 use raft::storage::MemStorage;
 
 use meteora_client::raft::client::RaftClient;
+use meteora_proto::proto::auth_grpc::create_auth_service;
 use meteora_proto::proto::common::NodeAddress;
+use meteora_proto::proto::etcd_grpc::{create_kv, create_lease, create_watch};
 use meteora_proto::proto::kv_grpc::create_kv_service;
 use meteora_proto::proto::raft_grpc::create_raft_service;
+use meteora_server::encryption::KeyRing;
 use meteora_server::kv::server::KVServer;
+use meteora_server::metrics;
+use meteora_server::trace::Tracing;
 
+use crate::config::NodeConfig;
+use crate::gateway;
 use crate::log::set_logger;
+use crate::memcached;
+use crate::redis;
 use crate::signal::sigterm_channel;
 
 pub fn run_start_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
     set_logger();
+    let config = NodeConfig::from_matches(matches)?;
+    let _tracing = match &config.tracing.otlp_endpoint {
+        Some(endpoint) => Some(Tracing::init(endpoint, "meteora")?),
+        None => None,
+    };
 
-    let id = matches.value_of("ID").unwrap().parse::<u64>().unwrap();
-    let address = matches.value_of("ADDRESS").unwrap();
-    let raft_port = matches
-        .value_of("RAFT_PORT")
-        .unwrap()
-        .parse::<u16>()
-        .unwrap();
-    let kv_port = matches.value_of("KV_PORT").unwrap().parse::<u16>().unwrap();
-    let mut peer_address = "";
-    let data_directory = matches.value_of("DATA_DIRECTORY").unwrap();
-    if let Some(_peer_address) = matches.value_of("PEER_RAFT_ADDRESS") {
-        peer_address = _peer_address;
-    }
+    let id = config.id;
+    let address = config.address.as_str();
+    let raft_port = config.raft_port;
+    let kv_port = config.kv_port;
+    let data_directory = config.data_directory.as_str();
+    let tls = config.tls.tls_config();
 
     let raft_address = format!("{}:{}", address, raft_port);
     let kv_address = format!("{}:{}", address, kv_port);
@@ -48,12 +55,10 @@ pub fn run_start_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
     let mut addresses = HashMap::new();
 
     // change config
-    if peer_address != "" {
-        let mut client = RaftClient::new(peer_address);
-        match client.join(id, node_address.clone()) {
-            Ok(_addresses) => addresses = _addresses,
-            Err(e) => return Err(e),
-        };
+    if !config.peers.is_empty() {
+        let seeds: Vec<&str> = config.peers.iter().map(|p| p.as_str()).collect();
+        let mut client = RaftClient::connect(&seeds, tls.clone())?;
+        addresses = client.join(id, node_address.clone())?;
     }
 
     // new
@@ -67,21 +72,68 @@ pub fn run_start_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
         .to_string();
     let raft_storage = MemStorage::new();
 
-    let (kv, raft) = KVServer::new(kv_path, raft_storage, id, node_address, addresses);
+    let key_ring = match &config.encryption.key_file {
+        Some(key_file) => Some(KeyRing::load(key_file)?),
+        None => None,
+    };
+
+    let (mut kv, raft, auth, etcd) = KVServer::new(
+        kv_path,
+        raft_storage,
+        id,
+        node_address,
+        addresses,
+        tls.clone(),
+        config.auth.enabled,
+        key_ring,
+        config.raft.raft_config(),
+        config.storage.clone(),
+    )?;
+    if config.forward_writes {
+        kv.forward_writes(env_kv.clone());
+    }
+
+    let authenticator = if config.auth.enabled {
+        Some(auth.authenticator())
+    } else {
+        None
+    };
 
     let kv_service = create_kv_service(kv);
     let raft_service = create_raft_service(raft);
+    let auth_service = create_auth_service(auth);
 
-    let mut kv_server = ServerBuilder::new(env_kv)
+    let mut kv_builder = ServerBuilder::new(env_kv)
         .register_service(kv_service)
-        .bind(address, kv_port)
-        .build()
-        .unwrap();
-    let mut raft_server = ServerBuilder::new(env_raft)
-        .register_service(raft_service)
-        .bind(address, raft_port)
-        .build()
-        .unwrap();
+        .register_service(auth_service);
+    if config.enable_etcd {
+        etcd.start_lease_expiry();
+        kv_builder = kv_builder
+            .register_service(create_kv(etcd.clone()))
+            .register_service(create_watch(etcd.clone()))
+            .register_service(create_lease(etcd));
+    }
+    let raft_builder = ServerBuilder::new(env_raft).register_service(raft_service);
+    let (kv_builder, raft_builder) = match &tls {
+        Some(tls) => {
+            let verify_raft_client = config.tls.raft_client_auth;
+            (
+                kv_builder.bind_secure(address, kv_port, tls.server_credentials(false)?),
+                raft_builder.bind_secure(
+                    address,
+                    raft_port,
+                    tls.server_credentials(verify_raft_client)?,
+                ),
+            )
+        }
+        None => (
+            kv_builder.bind(address, kv_port),
+            raft_builder.bind(address, raft_port),
+        ),
+    };
+
+    let mut kv_server = kv_builder.build().unwrap();
+    let mut raft_server = raft_builder.build().unwrap();
 
     kv_server.start();
     raft_server.start();
@@ -94,6 +146,37 @@ pub fn run_start_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
         info!("start Raft service on {}:{}", h, p);
     }
 
+    if let Some(metrics_address) = &config.metrics.address {
+        metrics::serve(metrics_address)?;
+    }
+
+    if let Some(http_port) = config.http_port {
+        gateway::serve(
+            &format!("{}:{}", address, http_port),
+            id,
+            &raft_address,
+            config.raft.max_size_per_msg,
+            tls.clone(),
+            authenticator,
+        )?;
+    }
+
+    if let Some(redis_port) = config.redis_port {
+        redis::serve(
+            &format!("{}:{}", address, redis_port),
+            &raft_address,
+            tls.clone(),
+        )?;
+    }
+
+    if let Some(memcached_port) = config.memcached_port {
+        memcached::serve(
+            &format!("{}:{}", address, memcached_port),
+            &raft_address,
+            tls.clone(),
+        )?;
+    }
+
     // Wait for signals for termination (SIGINT, SIGTERM).
     let sigterm_receiver = sigterm_channel().unwrap();
     loop {
@@ -105,11 +188,11 @@ pub fn run_start_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
         }
     }
 
-    if matches.is_present("ENABLE_AUTO_LEAVING") {
+    if config.enable_auto_leaving {
         info!("leaving from the cluster");
-        let mut raft_client = RaftClient::new(&raft_address);
-
-        match raft_client.leave(id) {
+        let left = RaftClient::connect(&[raft_address.as_str()], tls.clone())
+            .and_then(|mut raft_client| raft_client.leave(id));
+        match left {
             Ok(v) => {
                 info!("{}", serde_json::to_string(&v).unwrap());
             }
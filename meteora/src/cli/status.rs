@@ -1,24 +1,42 @@
 use clap::ArgMatches;
+use serde_json::json;
 
 use meteora_client::raft::client::RaftClient;
 
 use crate::log::set_logger;
+use crate::tls::tls_config;
 
 pub fn run_status_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
     set_logger();
 
     let address = matches.value_of("ADDRESS").unwrap();
 
-    let mut raft_client = RaftClient::new(address);
+    let seeds: Vec<&str> = address.split(',').collect();
+    let mut raft_client = RaftClient::connect(&seeds, tls_config(matches))?;
 
-    match raft_client.status() {
-        Ok(v) => {
+    match raft_client.status_reply() {
+        Ok(reply) => {
+            let storage = reply.get_storage();
+            let v = json!({
+                "nodes": reply.get_address_map(),
+                "node_id": reply.get_node_id(),
+                "storage": {
+                    "engine": storage.get_engine(),
+                    "block_cache_size": storage.get_block_cache_size(),
+                    "compression": storage.get_compression(),
+                    "bloom_filter_bits_per_key": storage.get_bloom_filter_bits_per_key(),
+                    "write_buffer_size": storage.get_write_buffer_size(),
+                    "max_background_jobs": storage.get_max_background_jobs(),
+                    "wal_dir": storage.get_wal_dir(),
+                    "max_total_wal_size": storage.get_max_total_wal_size(),
+                },
+            });
             println!("{}", serde_json::to_string(&v).unwrap());
             Ok(())
         }
         Err(e) => {
             println!("{}", e);
-            Err(e)
+            Err(e.into())
         }
     }
 }
@@ -0,0 +1,23 @@
+use clap::ArgMatches;
+
+use meteora_client::auth::client::AuthClient;
+
+use crate::log::set_logger;
+use crate::tls::tls_config;
+
+pub fn run_user_add_cli(matches: &ArgMatches) -> Result<(), std::io::Error> {
+    set_logger();
+
+    let address = matches.value_of("ADDRESS").unwrap();
+    let name = matches.value_of("NAME").unwrap();
+    let password = matches.value_of("PASSWORD").unwrap();
+
+    let seeds: Vec<&str> = address.split(',').collect();
+    let mut auth_client = AuthClient::connect(&seeds, tls_config(matches))?;
+    if let Some(token) = matches.value_of("TOKEN") {
+        auth_client.set_token(token.to_string());
+    }
+
+    auth_client.user_add(name, password)?;
+    Ok(())
+}
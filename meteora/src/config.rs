@@ -0,0 +1,697 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::{Arg, ArgMatches};
+use serde::{Deserialize, Serialize};
+
+use meteora_server::raft::config::RaftConfig;
+use meteora_server::storage::StorageConfig;
+use meteora_server::tls::TlsConfig;
+
+// The configuration of a node, read from a TOML or YAML file. The options of the start command,
+// and the environment variables they read, take precedence over the file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    // A number that is unique in the cluster, greater than or equal to 1.
+    pub id: u64,
+    // The IP address or hostname that the services listen on.
+    pub address: String,
+    pub raft_port: u16,
+    pub kv_port: u16,
+    pub data_directory: String,
+    // The Raft addresses of nodes of the cluster to join. A new cluster is started if it is
+    // empty.
+    pub peers: Vec<String>,
+    pub enable_auto_leaving: bool,
+    pub forward_writes: bool,
+    pub enable_etcd: bool,
+    pub http_port: Option<u16>,
+    pub redis_port: Option<u16>,
+    pub memcached_port: Option<u16>,
+    pub raft: RaftSection,
+    pub storage: StorageConfig,
+    pub tls: TlsSection,
+    pub auth: AuthSection,
+    pub encryption: EncryptionSection,
+    pub metrics: MetricsSection,
+    pub tracing: TracingSection,
+}
+
+impl Default for NodeConfig {
+    fn default() -> NodeConfig {
+        NodeConfig {
+            id: 1,
+            address: "0.0.0.0".to_string(),
+            raft_port: 7000,
+            kv_port: 5000,
+            data_directory: "./data".to_string(),
+            peers: Vec::new(),
+            enable_auto_leaving: false,
+            forward_writes: false,
+            enable_etcd: false,
+            http_port: None,
+            redis_port: None,
+            memcached_port: None,
+            raft: RaftSection::default(),
+            storage: StorageConfig::default(),
+            tls: TlsSection::default(),
+            auth: AuthSection::default(),
+            encryption: EncryptionSection::default(),
+            metrics: MetricsSection::default(),
+            tracing: TracingSection::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RaftSection {
+    // Milliseconds.
+    pub tick_interval: u64,
+    pub election_tick: usize,
+    pub heartbeat_tick: usize,
+    pub max_size_per_msg: u64,
+    pub max_inflight_msgs: usize,
+    pub pre_vote: bool,
+    pub check_quorum: bool,
+    pub skip_bcast_commit: bool,
+}
+
+impl Default for RaftSection {
+    fn default() -> RaftSection {
+        let config = RaftConfig::default();
+        RaftSection {
+            tick_interval: config.tick_interval.as_millis() as u64,
+            election_tick: config.election_tick,
+            heartbeat_tick: config.heartbeat_tick,
+            max_size_per_msg: config.max_size_per_msg,
+            max_inflight_msgs: config.max_inflight_msgs,
+            pre_vote: config.pre_vote,
+            check_quorum: config.check_quorum,
+            skip_bcast_commit: config.skip_bcast_commit,
+        }
+    }
+}
+
+impl RaftSection {
+    pub fn raft_config(&self) -> RaftConfig {
+        RaftConfig {
+            tick_interval: Duration::from_millis(self.tick_interval),
+            election_tick: self.election_tick,
+            heartbeat_tick: self.heartbeat_tick,
+            max_size_per_msg: self.max_size_per_msg,
+            max_inflight_msgs: self.max_inflight_msgs,
+            pre_vote: self.pre_vote,
+            check_quorum: self.check_quorum,
+            skip_bcast_commit: self.skip_bcast_commit,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSection {
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    pub ca_file: Option<String>,
+    // Require cluster members to present a certificate signed by the CA on the Raft service.
+    pub raft_client_auth: bool,
+}
+
+impl TlsSection {
+    pub fn tls_config(&self) -> Option<TlsConfig> {
+        if self.cert_file.is_none() && self.key_file.is_none() && self.ca_file.is_none() {
+            return None;
+        }
+        Some(TlsConfig::new(
+            self.cert_file.as_deref(),
+            self.key_file.as_deref(),
+            self.ca_file.as_deref(),
+        ))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSection {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncryptionSection {
+    pub key_file: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSection {
+    // The address that serves Prometheus metrics on /metrics.
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingSection {
+    pub otlp_endpoint: Option<String>,
+}
+
+// The options of a node, which the start command takes and `config check` validates.
+pub fn node_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("CONFIG")
+            .help("A TOML or YAML file that configures the node. The options given here, or their environment variables, take precedence over it.")
+            .short("c")
+            .long("config")
+            .value_name("CONFIG")
+            .env("METEORA_CONFIG")
+            .takes_value(true),
+        Arg::with_name("ID")
+            .help("A number that is unique ID in the cluster. It must be greater than or equal to 1. Defaults to 1.")
+            .short("i")
+            .long("id")
+            .value_name("ID")
+            .env("METEORA_ID")
+            .takes_value(true),
+        Arg::with_name("ADDRESS")
+            .help("An IP address or a hostname that runs the server. Defaults to 0.0.0.0.")
+            .short("a")
+            .long("address")
+            .value_name("ADDRESS")
+            .env("METEORA_ADDRESS")
+            .takes_value(true),
+        Arg::with_name("RAFT_PORT")
+            .help("A port number that provides the Raft service. Defaults to 7000.")
+            .short("r")
+            .long("raft-port")
+            .value_name("RAFT_PORT")
+            .env("METEORA_RAFT_PORT")
+            .takes_value(true),
+        Arg::with_name("KV_PORT")
+            .help("A port number that provides the Key-Value service. Defaults to 5000.")
+            .short("k")
+            .long("kv-port")
+            .value_name("KV_PORT")
+            .env("METEORA_KV_PORT")
+            .takes_value(true),
+        Arg::with_name("DATA_DIRECTORY")
+            .help("A directory path that stores data. Defaults to ./data.")
+            .short("d")
+            .long("data-directory")
+            .value_name("DATA_DIRECTORY")
+            .env("METEORA_DATA_DIRECTORY")
+            .takes_value(true),
+        Arg::with_name("PEER_RAFT_ADDRESS")
+            .help("Join a cluster in which a peer node with the specified raft address is joined. Several addresses can be given separated by commas.")
+            .short("p")
+            .long("peer-raft-address")
+            .value_name("ADDRESS:RAFT_PORT")
+            .env("METEORA_PEER_RAFT_ADDRESS")
+            .takes_value(true),
+        Arg::with_name("ENABLE_AUTO_LEAVING")
+            .help("Automatically delete the node from the cluster when stopped.")
+            .short("l")
+            .long("enable-auto-leaving")
+            .value_name("BOOL")
+            .env("METEORA_ENABLE_AUTO_LEAVING")
+            .possible_values(&["true", "false"])
+            .min_values(0)
+            .require_equals(true),
+        Arg::with_name("RAFT_TICK_INTERVAL")
+            .help("The interval of Raft ticks in milliseconds. The election and heartbeat timeouts are counted in ticks. Defaults to 100.")
+            .long("raft-tick-interval")
+            .value_name("RAFT_TICK_INTERVAL")
+            .env("METEORA_RAFT_TICK_INTERVAL")
+            .takes_value(true),
+        Arg::with_name("RAFT_ELECTION_TICK")
+            .help("The number of ticks a follower waits for the leader before it starts an election. It must be greater than the heartbeat ticks. Defaults to 10.")
+            .long("raft-election-tick")
+            .value_name("RAFT_ELECTION_TICK")
+            .env("METEORA_RAFT_ELECTION_TICK")
+            .takes_value(true),
+        Arg::with_name("RAFT_HEARTBEAT_TICK")
+            .help("The number of ticks between heartbeats of the leader. Defaults to 3.")
+            .long("raft-heartbeat-tick")
+            .value_name("RAFT_HEARTBEAT_TICK")
+            .env("METEORA_RAFT_HEARTBEAT_TICK")
+            .takes_value(true),
+        Arg::with_name("RAFT_MAX_SIZE_PER_MSG")
+            .help("The max size in bytes of the entries in a Raft append message. Defaults to 1073741824.")
+            .long("raft-max-size-per-msg")
+            .value_name("RAFT_MAX_SIZE_PER_MSG")
+            .env("METEORA_RAFT_MAX_SIZE_PER_MSG")
+            .takes_value(true),
+        Arg::with_name("RAFT_MAX_INFLIGHT_MSGS")
+            .help("The max number of Raft append messages sent to a follower without an acknowledgement. Defaults to 256.")
+            .long("raft-max-inflight-msgs")
+            .value_name("RAFT_MAX_INFLIGHT_MSGS")
+            .env("METEORA_RAFT_MAX_INFLIGHT_MSGS")
+            .takes_value(true),
+        Arg::with_name("RAFT_PRE_VOTE")
+            .help("Check that a node could win an election before it starts one, so that a node that was partitioned does not disrupt the cluster.")
+            .long("raft-pre-vote")
+            .value_name("BOOL")
+            .env("METEORA_RAFT_PRE_VOTE")
+            .possible_values(&["true", "false"])
+            .min_values(0)
+            .require_equals(true),
+        Arg::with_name("RAFT_CHECK_QUORUM")
+            .help("Make the leader step down when it has not heard from a quorum for an election timeout.")
+            .long("raft-check-quorum")
+            .value_name("BOOL")
+            .env("METEORA_RAFT_CHECK_QUORUM")
+            .possible_values(&["true", "false"])
+            .min_values(0)
+            .require_equals(true),
+        Arg::with_name("RAFT_SKIP_BCAST_COMMIT")
+            .help("Tell the followers about committed entries with the next message instead of at once.")
+            .long("raft-skip-bcast-commit")
+            .value_name("BOOL")
+            .env("METEORA_RAFT_SKIP_BCAST_COMMIT")
+            .possible_values(&["true", "false"])
+            .min_values(0)
+            .require_equals(true),
+        Arg::with_name("STORAGE_ENGINE")
+            .help("The engine that stores the data: rocksdb, redb, or memory to keep the data in memory only. redb must be built with the engine-redb feature. The data of a node is lost when it stops with memory. Defaults to rocksdb.")
+            .long("storage-engine")
+            .value_name("STORAGE_ENGINE")
+            .env("METEORA_STORAGE_ENGINE")
+            .takes_value(true),
+        Arg::with_name("STORAGE_BLOCK_CACHE_SIZE")
+            .help("The size in bytes of the RocksDB block cache. Defaults to 8388608.")
+            .long("storage-block-cache-size")
+            .value_name("STORAGE_BLOCK_CACHE_SIZE")
+            .env("METEORA_STORAGE_BLOCK_CACHE_SIZE")
+            .takes_value(true),
+        Arg::with_name("STORAGE_COMPRESSION")
+            .help("The compression of RocksDB blocks: none, snappy, lz4 or zstd. Defaults to snappy.")
+            .long("storage-compression")
+            .value_name("STORAGE_COMPRESSION")
+            .env("METEORA_STORAGE_COMPRESSION")
+            .takes_value(true),
+        Arg::with_name("STORAGE_BLOOM_FILTER_BITS_PER_KEY")
+            .help("The bits per key of RocksDB bloom filters, which save disk reads for missing keys. 0 disables them. Defaults to 0.")
+            .long("storage-bloom-filter-bits-per-key")
+            .value_name("STORAGE_BLOOM_FILTER_BITS_PER_KEY")
+            .env("METEORA_STORAGE_BLOOM_FILTER_BITS_PER_KEY")
+            .takes_value(true),
+        Arg::with_name("STORAGE_WRITE_BUFFER_SIZE")
+            .help("The size in bytes of a RocksDB memtable before it is flushed. Defaults to 67108864.")
+            .long("storage-write-buffer-size")
+            .value_name("STORAGE_WRITE_BUFFER_SIZE")
+            .env("METEORA_STORAGE_WRITE_BUFFER_SIZE")
+            .takes_value(true),
+        Arg::with_name("STORAGE_MAX_BACKGROUND_JOBS")
+            .help("The number of RocksDB threads that flush and compact. Defaults to 2.")
+            .long("storage-max-background-jobs")
+            .value_name("STORAGE_MAX_BACKGROUND_JOBS")
+            .env("METEORA_STORAGE_MAX_BACKGROUND_JOBS")
+            .takes_value(true),
+        Arg::with_name("STORAGE_WAL_DIR")
+            .help("A directory for the RocksDB write-ahead log. It is kept with the data if omitted.")
+            .long("storage-wal-dir")
+            .value_name("STORAGE_WAL_DIR")
+            .env("METEORA_STORAGE_WAL_DIR")
+            .takes_value(true),
+        Arg::with_name("STORAGE_MAX_TOTAL_WAL_SIZE")
+            .help("The total size in bytes of the RocksDB write-ahead logs that forces memtables to be flushed. 0 lets RocksDB choose. Defaults to 0.")
+            .long("storage-max-total-wal-size")
+            .value_name("STORAGE_MAX_TOTAL_WAL_SIZE")
+            .env("METEORA_STORAGE_MAX_TOTAL_WAL_SIZE")
+            .takes_value(true),
+        Arg::with_name("TLS_CERT")
+            .help("A PEM encoded certificate file. Enables TLS on the Key-Value and Raft services.")
+            .long("tls-cert")
+            .value_name("TLS_CERT")
+            .env("METEORA_TLS_CERT")
+            .takes_value(true),
+        Arg::with_name("TLS_KEY")
+            .help("A PEM encoded private key file for the certificate.")
+            .long("tls-key")
+            .value_name("TLS_KEY")
+            .env("METEORA_TLS_KEY")
+            .takes_value(true),
+        Arg::with_name("TLS_CA")
+            .help("A PEM encoded CA certificate file to verify peer certificates.")
+            .long("tls-ca")
+            .value_name("TLS_CA")
+            .env("METEORA_TLS_CA")
+            .takes_value(true),
+        Arg::with_name("TLS_RAFT_CLIENT_AUTH")
+            .help("Require cluster members to present a certificate signed by the CA on the Raft service.")
+            .long("tls-raft-client-auth")
+            .value_name("BOOL")
+            .env("METEORA_TLS_RAFT_CLIENT_AUTH")
+            .possible_values(&["true", "false"])
+            .min_values(0)
+            .require_equals(true),
+        Arg::with_name("ENABLE_AUTH")
+            .help("Require a token issued by the authenticate command on the Key-Value service.")
+            .long("enable-auth")
+            .value_name("BOOL")
+            .env("METEORA_ENABLE_AUTH")
+            .possible_values(&["true", "false"])
+            .min_values(0)
+            .require_equals(true),
+        Arg::with_name("ENABLE_ETCD")
+            .help("Serve the KV, Watch and Lease services of the etcd v3 API on the Key-Value port. Authentication is not supported on them.")
+            .long("enable-etcd")
+            .value_name("BOOL")
+            .env("METEORA_ENABLE_ETCD")
+            .possible_values(&["true", "false"])
+            .min_values(0)
+            .require_equals(true),
+        Arg::with_name("FORWARD_WRITES")
+            .help("Forward writes received by a follower to the leader instead of asking the client to retry on the leader.")
+            .long("forward-writes")
+            .value_name("BOOL")
+            .env("METEORA_FORWARD_WRITES")
+            .possible_values(&["true", "false"])
+            .min_values(0)
+            .require_equals(true),
+        Arg::with_name("ENCRYPTION_KEY_FILE")
+            .help("A key file that holds AES-256 keys to encrypt data at rest. The key with the largest ID is used for new data.")
+            .long("encryption-key-file")
+            .value_name("ENCRYPTION_KEY_FILE")
+            .env("METEORA_ENCRYPTION_KEY_FILE")
+            .takes_value(true),
+        Arg::with_name("HTTP_PORT")
+            .help("A port number that serves the REST API. The REST API is disabled if omitted.")
+            .long("http-port")
+            .value_name("HTTP_PORT")
+            .env("METEORA_HTTP_PORT")
+            .takes_value(true),
+        Arg::with_name("REDIS_PORT")
+            .help("A port number that serves the Redis protocol. The Redis protocol is disabled if omitted.")
+            .long("redis-port")
+            .value_name("REDIS_PORT")
+            .env("METEORA_REDIS_PORT")
+            .takes_value(true),
+        Arg::with_name("MEMCACHED_PORT")
+            .help("A port number that serves the memcached text protocol. The memcached protocol is disabled if omitted.")
+            .long("memcached-port")
+            .value_name("MEMCACHED_PORT")
+            .env("METEORA_MEMCACHED_PORT")
+            .takes_value(true),
+        Arg::with_name("METRICS_ADDRESS")
+            .help("An address that serves Prometheus metrics on /metrics.")
+            .long("metrics-address")
+            .value_name("ADDRESS:METRICS_PORT")
+            .env("METEORA_METRICS_ADDRESS")
+            .takes_value(true),
+        Arg::with_name("OTLP_ENDPOINT")
+            .help("An OpenTelemetry collector endpoint to export traces to over OTLP/gRPC.")
+            .long("otlp-endpoint")
+            .value_name("OTLP_ENDPOINT")
+            .env("METEORA_OTLP_ENDPOINT")
+            .takes_value(true),
+    ]
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+// A flag given alone is set. It may also be given as --flag=false, or with its environment
+// variable set to true or false.
+fn flag(matches: &ArgMatches, name: &str) -> Option<bool> {
+    if matches.is_present(name) {
+        Some(matches.value_of(name) != Some("false"))
+    } else {
+        None
+    }
+}
+
+// The value of the option, given on the command line or in its environment variable.
+fn value<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<Option<T>, Error>
+where
+    T::Err: std::fmt::Display,
+{
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse::<T>()
+            .map(Some)
+            .map_err(|e| invalid(format!("invalid value for {}: {}: {}", name, value, e))),
+        None => Ok(None),
+    }
+}
+
+impl NodeConfig {
+    // Reads the file as TOML or YAML depending on its extension.
+    pub fn load(path: &str) -> Result<NodeConfig, Error> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("failed to read config file: path={}, error={}", path, e),
+            )
+        })?;
+        let extension = Path::new(path).extension().and_then(|e| e.to_str());
+        let config = match extension {
+            Some("toml") => toml::from_str(&content).map_err(|e| e.to_string()),
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content).map_err(|e| e.to_string()),
+            _ => {
+                return Err(invalid(format!(
+                    "unknown config file format, expected .toml, .yaml or .yml: path={}",
+                    path
+                )))
+            }
+        };
+        config.map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse config file: path={}, error={}", path, e),
+            )
+        })
+    }
+
+    // Reads the file given with --config, if any, and applies the options of the start command
+    // on top of it.
+    pub fn from_matches(matches: &ArgMatches) -> Result<NodeConfig, Error> {
+        let mut config = match matches.value_of("CONFIG") {
+            Some(path) => NodeConfig::load(path)?,
+            None => NodeConfig::default(),
+        };
+        config.merge_matches(matches)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn merge_matches(&mut self, matches: &ArgMatches) -> Result<(), Error> {
+        if let Some(id) = value(matches, "ID")? {
+            self.id = id;
+        }
+        if let Some(address) = value(matches, "ADDRESS")? {
+            self.address = address;
+        }
+        if let Some(raft_port) = value(matches, "RAFT_PORT")? {
+            self.raft_port = raft_port;
+        }
+        if let Some(kv_port) = value(matches, "KV_PORT")? {
+            self.kv_port = kv_port;
+        }
+        if let Some(data_directory) = value(matches, "DATA_DIRECTORY")? {
+            self.data_directory = data_directory;
+        }
+        if let Some(peers) = matches.value_of("PEER_RAFT_ADDRESS") {
+            self.peers = peers
+                .split(',')
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .map(|p| p.to_string())
+                .collect();
+        }
+        self.enable_auto_leaving =
+            flag(matches, "ENABLE_AUTO_LEAVING").unwrap_or(self.enable_auto_leaving);
+        self.forward_writes = flag(matches, "FORWARD_WRITES").unwrap_or(self.forward_writes);
+        self.enable_etcd = flag(matches, "ENABLE_ETCD").unwrap_or(self.enable_etcd);
+        self.http_port = value(matches, "HTTP_PORT")?.or(self.http_port);
+        self.redis_port = value(matches, "REDIS_PORT")?.or(self.redis_port);
+        self.memcached_port = value(matches, "MEMCACHED_PORT")?.or(self.memcached_port);
+
+        if let Some(tick_interval) = value(matches, "RAFT_TICK_INTERVAL")? {
+            self.raft.tick_interval = tick_interval;
+        }
+        if let Some(election_tick) = value(matches, "RAFT_ELECTION_TICK")? {
+            self.raft.election_tick = election_tick;
+        }
+        if let Some(heartbeat_tick) = value(matches, "RAFT_HEARTBEAT_TICK")? {
+            self.raft.heartbeat_tick = heartbeat_tick;
+        }
+        if let Some(max_size_per_msg) = value(matches, "RAFT_MAX_SIZE_PER_MSG")? {
+            self.raft.max_size_per_msg = max_size_per_msg;
+        }
+        if let Some(max_inflight_msgs) = value(matches, "RAFT_MAX_INFLIGHT_MSGS")? {
+            self.raft.max_inflight_msgs = max_inflight_msgs;
+        }
+        self.raft.pre_vote = flag(matches, "RAFT_PRE_VOTE").unwrap_or(self.raft.pre_vote);
+        self.raft.check_quorum =
+            flag(matches, "RAFT_CHECK_QUORUM").unwrap_or(self.raft.check_quorum);
+        self.raft.skip_bcast_commit =
+            flag(matches, "RAFT_SKIP_BCAST_COMMIT").unwrap_or(self.raft.skip_bcast_commit);
+
+        if let Some(engine) = value(matches, "STORAGE_ENGINE")? {
+            self.storage.engine = engine;
+        }
+        if let Some(block_cache_size) = value(matches, "STORAGE_BLOCK_CACHE_SIZE")? {
+            self.storage.block_cache_size = block_cache_size;
+        }
+        if let Some(compression) = value(matches, "STORAGE_COMPRESSION")? {
+            self.storage.compression = compression;
+        }
+        if let Some(bits_per_key) = value(matches, "STORAGE_BLOOM_FILTER_BITS_PER_KEY")? {
+            self.storage.bloom_filter_bits_per_key = bits_per_key;
+        }
+        if let Some(write_buffer_size) = value(matches, "STORAGE_WRITE_BUFFER_SIZE")? {
+            self.storage.write_buffer_size = write_buffer_size;
+        }
+        if let Some(max_background_jobs) = value(matches, "STORAGE_MAX_BACKGROUND_JOBS")? {
+            self.storage.max_background_jobs = max_background_jobs;
+        }
+        self.storage.wal_dir = value(matches, "STORAGE_WAL_DIR")?.or(self.storage.wal_dir.take());
+        if let Some(max_total_wal_size) = value(matches, "STORAGE_MAX_TOTAL_WAL_SIZE")? {
+            self.storage.max_total_wal_size = max_total_wal_size;
+        }
+
+        self.tls.cert_file = value(matches, "TLS_CERT")?.or(self.tls.cert_file.take());
+        self.tls.key_file = value(matches, "TLS_KEY")?.or(self.tls.key_file.take());
+        self.tls.ca_file = value(matches, "TLS_CA")?.or(self.tls.ca_file.take());
+        self.tls.raft_client_auth =
+            flag(matches, "TLS_RAFT_CLIENT_AUTH").unwrap_or(self.tls.raft_client_auth);
+        self.auth.enabled = flag(matches, "ENABLE_AUTH").unwrap_or(self.auth.enabled);
+        self.encryption.key_file =
+            value(matches, "ENCRYPTION_KEY_FILE")?.or(self.encryption.key_file.take());
+        self.metrics.address = value(matches, "METRICS_ADDRESS")?.or(self.metrics.address.take());
+        self.tracing.otlp_endpoint =
+            value(matches, "OTLP_ENDPOINT")?.or(self.tracing.otlp_endpoint.take());
+        Ok(())
+    }
+
+    // Checks the settings without reading the files they refer to.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.id == 0 {
+            return Err(invalid("id must be greater than or equal to 1".to_string()));
+        }
+        if self.address.is_empty() {
+            return Err(invalid("address must not be empty".to_string()));
+        }
+
+        let mut ports = HashSet::new();
+        for port in [
+            Some(self.raft_port),
+            Some(self.kv_port),
+            self.http_port,
+            self.redis_port,
+            self.memcached_port,
+        ]
+        .iter()
+        .flatten()
+        {
+            if !ports.insert(port) {
+                return Err(invalid(format!("port {} is used more than once", port)));
+            }
+        }
+
+        self.raft.raft_config().validate()?;
+        self.storage.validate()?;
+
+        if self.tls.cert_file.is_some() != self.tls.key_file.is_some() {
+            return Err(invalid(
+                "TLS requires both a certificate and a private key".to_string(),
+            ));
+        }
+        if self.tls.ca_file.is_some() && self.tls.cert_file.is_none() {
+            return Err(invalid(
+                "a TLS CA certificate requires a certificate".to_string(),
+            ));
+        }
+        if self.tls.raft_client_auth && self.tls.ca_file.is_none() {
+            return Err(invalid(
+                "client certificate verification requires a CA certificate".to_string(),
+            ));
+        }
+        if self.enable_etcd && self.auth.enabled {
+            return Err(invalid(
+                "the etcd API does not support authentication".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::App;
+
+    fn matches(args: &[&str]) -> ArgMatches<'static> {
+        App::new("start")
+            .args(&node_args())
+            .get_matches_from_safe(args)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_raft_section() {
+        let config: NodeConfig = toml::from_str("[raft]\nelection_tick = 20\n").unwrap();
+        assert_eq!(config.raft.election_tick, 20);
+        assert_eq!(config.raft.max_size_per_msg, 1024 * 1024 * 1024);
+        assert!(config.validate().is_ok());
+
+        let raft_config = config.raft.raft_config();
+        assert_eq!(raft_config.tick_interval, Duration::from_millis(100));
+        assert_eq!(raft_config.election_tick, 20);
+        assert_eq!(raft_config.max_size_per_msg, 1024 * 1024 * 1024);
+
+        let config: NodeConfig = toml::from_str("[raft]\nmax_size_per_msg = 0\n").unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut config = NodeConfig::default();
+        config
+            .merge_matches(&matches(&[
+                "start",
+                "--enable-etcd",
+                "--forward-writes=true",
+            ]))
+            .unwrap();
+        assert!(config.enable_etcd);
+        assert!(config.forward_writes);
+        assert!(!config.auth.enabled);
+
+        config
+            .merge_matches(&matches(&["start", "--enable-etcd=false", "--id", "2"]))
+            .unwrap();
+        assert!(!config.enable_etcd);
+        assert!(config.forward_writes);
+        assert_eq!(config.id, 2);
+
+        assert!(App::new("start")
+            .args(&node_args())
+            .get_matches_from_safe(&["start", "--enable-etcd=yes"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_flag_env() {
+        std::env::set_var("METEORA_RAFT_CHECK_QUORUM", "true");
+        std::env::set_var("METEORA_PEER_RAFT_ADDRESS", "node1:7000, node2:7000");
+        let matches = matches(&["start"]);
+        std::env::remove_var("METEORA_RAFT_CHECK_QUORUM");
+        std::env::remove_var("METEORA_PEER_RAFT_ADDRESS");
+
+        let mut config = NodeConfig::default();
+        config.merge_matches(&matches).unwrap();
+        assert!(config.raft.check_quorum);
+        assert_eq!(config.peers, vec!["node1:7000", "node2:7000"]);
+    }
+}
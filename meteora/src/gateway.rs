@@ -0,0 +1,370 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Read};
+use std::sync::Arc;
+use std::thread;
+
+use log::*;
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server, SslConfig};
+
+use meteora_client::auth::client::prefix_range_end;
+use meteora_client::error::Error as ClientError;
+use meteora_client::kv::client::KVClient;
+use meteora_client::raft::client::RaftClient;
+use meteora_proto::proto::common::State;
+use meteora_server::auth::server::Authenticator;
+use meteora_server::tls::TlsConfig;
+
+const WORKERS: usize = 4;
+const KV_PATH: &str = "/v1/kv";
+
+// The body of a reply. Values are returned as they are stored, since they may be binary.
+enum Reply {
+    Json(Value),
+    Bytes(Vec<u8>),
+}
+
+// A request body that is larger than the gateway accepts. It is answered with 413.
+#[derive(Debug)]
+struct BodyTooLarge {
+    limit: u64,
+}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "request body is too large: limit={}", self.limit)
+    }
+}
+
+impl error::Error for BodyTooLarge {}
+
+// A REST API that translates HTTP requests into gRPC requests to the local node.
+// Writes are forwarded to the leader by the clients, in the same way as the CLI.
+struct Gateway {
+    node_id: u64,
+    // The largest value that can be put, so that a large body isn't read into memory only to
+    // be refused by Raft.
+    max_body_size: u64,
+    kv_client: KVClient,
+    raft_client: RaftClient,
+    // Checks the tokens of the cluster requests, which the Raft service doesn't, if
+    // authentication is enabled.
+    authenticator: Option<Authenticator>,
+    token: Option<String>,
+}
+
+impl Gateway {
+    fn handle(&mut self, mut request: Request) {
+        let url = request.url().to_string();
+        let (path, query) = match url.find('?') {
+            Some(i) => (&url[..i], &url[i + 1..]),
+            None => (url.as_str(), ""),
+        };
+
+        self.token = bearer_token(&request);
+        match &self.token {
+            Some(token) => self.kv_client.set_token(token.clone()),
+            None => self.kv_client.clear_token(),
+        }
+
+        let method = request.method().clone();
+        let result = match (&method, path) {
+            (Method::Get, KV_PATH) => self.list(query),
+            (Method::Get, "/v1/cluster/status") => self.status(),
+            (Method::Post, "/v1/cluster/leave") => self.leave(query),
+            (_, path) if path.starts_with("/v1/kv/") => {
+                let key: Vec<u8> = percent_decode_str(&path[KV_PATH.len() + 1..]).collect();
+                match method {
+                    Method::Get => self.get(key),
+                    Method::Put => match read_body(request.as_reader(), self.max_body_size) {
+                        Ok(value) => self.put(key, value),
+                        Err(e) => Err(e),
+                    },
+                    Method::Delete => self.delete(key),
+                    _ => Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("method not allowed: method={}", method),
+                    )),
+                }
+            }
+            _ => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no such endpoint: method={}, path={}", method, path),
+            )),
+        };
+
+        let (status_code, reply) = match result {
+            Ok(reply) => (200, reply),
+            Err(e) => (
+                status_code(&e),
+                Reply::Json(json!({ "error": e.to_string() })),
+            ),
+        };
+        info!("{} {} {}", method, url, status_code);
+
+        let (content_type, body) = match reply {
+            Reply::Json(value) => ("application/json", value.to_string().into_bytes()),
+            Reply::Bytes(bytes) => ("application/octet-stream", bytes),
+        };
+        let content_type =
+            Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap();
+        let response = Response::from_data(body)
+            .with_status_code(status_code)
+            .with_header(content_type);
+        if let Err(e) = request.respond(response) {
+            error!("failed to reply: {:?}", e);
+        }
+    }
+
+    fn get(&mut self, key: Vec<u8>) -> Result<Reply, Error> {
+        Ok(Reply::Bytes(self.kv_client.get(key)?))
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<Reply, Error> {
+        self.kv_client.put(key, value)?;
+        Ok(Reply::Json(json!({})))
+    }
+
+    fn delete(&mut self, key: Vec<u8>) -> Result<Reply, Error> {
+        self.kv_client.delete(key)?;
+        Ok(Reply::Json(json!({})))
+    }
+
+    // Keys and values are encoded in base64, as in the JSON API of etcd.
+    fn list(&mut self, query: &str) -> Result<Reply, Error> {
+        let mut prefix = Vec::new();
+        let mut limit = 0;
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            match name.as_ref() {
+                "prefix" => prefix = value.as_bytes().to_vec(),
+                "limit" => {
+                    limit = value.parse::<u64>().map_err(|e| {
+                        Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("invalid limit: limit={}, error={}", value, e),
+                        )
+                    })?
+                }
+                _ => {}
+            }
+        }
+
+        let range_end = prefix_range_end(&prefix);
+        let kvs: Vec<Value> = self
+            .kv_client
+            .scan(prefix, range_end, limit)?
+            .iter()
+            .map(|(key, value)| {
+                json!({
+                    "key": base64::encode(key),
+                    "value": base64::encode(value),
+                })
+            })
+            .collect();
+        Ok(Reply::Json(json!({ "kvs": kvs })))
+    }
+
+    fn status(&mut self) -> Result<Reply, Error> {
+        if let Some(authenticator) = &self.authenticator {
+            let token = self.token.as_deref().unwrap_or_default();
+            authenticator.authenticate(token).map_err(auth_error)?;
+        }
+        let addresses = self.raft_client.status()?;
+        Ok(Reply::Json(serde_json::to_value(&addresses).unwrap()))
+    }
+
+    // Removes the node given by the `id` parameter, or this node if it is omitted. Only the
+    // root user can, so the endpoint is refused if authentication is disabled.
+    fn leave(&mut self, query: &str) -> Result<Reply, Error> {
+        match &self.authenticator {
+            Some(authenticator) => {
+                let token = self.token.as_deref().unwrap_or_default();
+                authenticator.require_root(token).map_err(auth_error)?;
+            }
+            None => {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "removing a node over HTTP requires authentication to be enabled",
+                ))
+            }
+        }
+
+        let mut id = self.node_id;
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            if name == "id" {
+                id = value.parse::<u64>().map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("invalid node id: id={}, error={}", value, e),
+                    )
+                })?;
+            }
+        }
+
+        let addresses = self.raft_client.leave(id)?;
+        Ok(Reply::Json(serde_json::to_value(&addresses).unwrap()))
+    }
+}
+
+// Reads at most `limit` bytes of the body, and fails if there are more.
+fn read_body<R: Read>(reader: R, limit: u64) -> Result<Vec<u8>, Error> {
+    let mut body = Vec::new();
+    reader
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > limit {
+        return Err(Error::new(ErrorKind::Other, BodyTooLarge { limit }));
+    }
+    Ok(body)
+}
+
+// The client errors are kept as the inner error, so that a cluster without a leader can be
+// told apart from a failure of the node.
+fn status_code(e: &Error) -> u16 {
+    if let Some(inner) = e.get_ref() {
+        if inner.is::<BodyTooLarge>() {
+            return 413;
+        }
+        if let Some(ClientError::RetriesExhausted { .. }) | Some(ClientError::UnknownNode(_)) =
+            inner.downcast_ref::<ClientError>()
+        {
+            return 503;
+        }
+    }
+    match e.kind() {
+        ErrorKind::NotFound => 404,
+        ErrorKind::PermissionDenied => 403,
+        ErrorKind::InvalidInput => 400,
+        _ => 500,
+    }
+}
+
+fn auth_error(state: State) -> Error {
+    match state {
+        State::UNAUTHENTICATED => Error::new(ErrorKind::PermissionDenied, "authentication failed"),
+        State::PERMISSION_DENIED => Error::new(ErrorKind::PermissionDenied, "permission denied"),
+        state => Error::new(
+            ErrorKind::Other,
+            format!("failed to authenticate: state={:?}", state),
+        ),
+    }
+}
+
+fn bearer_token(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+// Serves the REST API on `address` with a few worker threads, each of which has its own
+// clients for the node at `raft_address`. It is served over HTTPS with the certificate of the
+// node if TLS is enabled. Bodies larger than `max_body_size` are refused.
+pub fn serve(
+    address: &str,
+    node_id: u64,
+    raft_address: &str,
+    max_body_size: u64,
+    tls: Option<TlsConfig>,
+    authenticator: Option<Authenticator>,
+) -> Result<(), Error> {
+    let server = match &tls {
+        Some(tls) => Server::https(address, ssl_config(tls)?),
+        None => Server::http(address),
+    }
+    .map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed to start HTTP gateway: address={}, error={}",
+                address, e
+            ),
+        )
+    })?;
+    let server = Arc::new(server);
+    info!("start HTTP gateway on {}", address);
+
+    for _ in 0..WORKERS {
+        let server = Arc::clone(&server);
+        let mut gateway = Gateway {
+            node_id,
+            max_body_size,
+            kv_client: KVClient::connect(&[raft_address], tls.clone())?,
+            raft_client: RaftClient::connect(&[raft_address], tls.clone())?,
+            authenticator: authenticator.clone(),
+            token: None,
+        };
+        thread::spawn(move || loop {
+            match server.recv() {
+                Ok(request) => gateway.handle(request),
+                Err(e) => {
+                    error!("failed to receive request: {:?}", e);
+                    break;
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn ssl_config(tls: &TlsConfig) -> Result<SslConfig, Error> {
+    match (&tls.cert_file, &tls.key_file) {
+        (Some(cert_file), Some(key_file)) => Ok(SslConfig {
+            certificate: fs::read(cert_file)?,
+            private_key: fs::read(key_file)?,
+        }),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "HTTPS requires both a certificate and a private key",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error, ErrorKind};
+
+    use meteora_client::error::Error as ClientError;
+    use meteora_proto::proto::common::State;
+
+    use crate::gateway::{read_body, status_code};
+
+    #[test]
+    fn test_read_body() {
+        assert_eq!(read_body(&b"val1"[..], 4).unwrap(), b"val1".to_vec());
+        assert_eq!(read_body(&b""[..], 4).unwrap(), Vec::<u8>::new());
+
+        let e = read_body(&b"val10"[..], 4).unwrap_err();
+        assert_eq!(status_code(&e), 413);
+    }
+
+    #[test]
+    fn test_status_code() {
+        let cases = vec![
+            (ClientError::State(State::NOT_FOUND), 404),
+            (ClientError::State(State::PERMISSION_DENIED), 403),
+            (ClientError::State(State::INVALID_ARGUMENT), 400),
+            (ClientError::State(State::IO_ERROR), 500),
+            (ClientError::UnknownNode(1), 503),
+            (
+                ClientError::RetriesExhausted {
+                    attempts: 10,
+                    state: State::WRONG_LEADER,
+                },
+                503,
+            ),
+        ];
+        for (e, expected) in cases {
+            assert_eq!(status_code(&Error::from(e)), expected);
+        }
+
+        assert_eq!(status_code(&Error::new(ErrorKind::InvalidInput, "")), 400);
+        assert_eq!(status_code(&Error::new(ErrorKind::Other, "")), 500);
+    }
+}
@@ -1,3 +1,9 @@
 pub mod cli;
+pub mod config;
+pub mod gateway;
 pub mod log;
+pub mod memcached;
+pub mod redis;
 pub mod signal;
+pub mod tls;
+pub mod trace;
@@ -1,11 +1,23 @@
 use clap::{crate_authors, crate_name, crate_version, App, AppSettings, Arg, SubCommand};
 
+use meteora::cli::authenticate::run_authenticate_cli;
+use meteora::cli::change_password::run_change_password_cli;
+use meteora::cli::config::run_config_cli;
 use meteora::cli::delete::run_delete_cli;
 use meteora::cli::get::run_get_cli;
 use meteora::cli::leave::run_leave_cli;
 use meteora::cli::put::run_put_cli;
+use meteora::cli::role_add::run_role_add_cli;
+use meteora::cli::role_delete::run_role_delete_cli;
+use meteora::cli::role_grant::run_role_grant_cli;
+use meteora::cli::role_revoke::run_role_revoke_cli;
 use meteora::cli::start::run_start_cli;
 use meteora::cli::status::run_status_cli;
+use meteora::cli::user_add::run_user_add_cli;
+use meteora::cli::user_delete::run_user_delete_cli;
+use meteora::cli::user_grant_role::run_user_grant_role_cli;
+use meteora::cli::user_revoke_role::run_user_revoke_role_cli;
+use meteora::config::node_args;
 
 fn main() -> Result<(), std::io::Error> {
     let app = App::new(crate_name!())
@@ -27,69 +39,30 @@ fn main() -> Result<(), std::io::Error> {
                 .help_message("Prints help information.")
                 .version_message("Prints version information.")
                 .version_short("v")
-                .arg(
-                    Arg::with_name("ID")
-                        .help("A number that is unique ID in the cluster. It must be greater than or equal to 1.")
-                        .short("i")
-                        .long("id")
-                        .value_name("ID")
-                        .env("METEORA_ID")
-                        .default_value("1")
-                        .takes_value(true),
-                )
-                .arg(
-                    Arg::with_name("ADDRESS")
-                        .help("An IP address or a hostname that runs the server.")
-                        .short("a")
-                        .long("address")
-                        .value_name("ADDRESS")
-                        .env("METEORA_ADDRESS")
-                        .default_value("0.0.0.0")
-                        .takes_value(true),
-                )
-                .arg(
-                    Arg::with_name("RAFT_PORT")
-                        .help("A port number that provides the Raft service.")
-                        .short("r")
-                        .long("raft-port")
-                        .value_name("RAFT_PORT")
-                        .env("METEORA_RAFT_PORT")
-                        .default_value("7000")
-                        .takes_value(true),
-                )
-                .arg(
-                    Arg::with_name("KV_PORT")
-                        .help("A port number that provides the Key-Value service.")
-                        .short("k")
-                        .long("kv-port")
-                        .value_name("KV_PORT")
-                        .env("METEORA_KV_PORT")
-                        .default_value("5000")
-                        .takes_value(true),
-                )
-                .arg(
-                    Arg::with_name("DATA_DIRECTORY")
-                        .help("A directory path that stores data.")
-                        .short("d")
-                        .long("data-directory")
-                        .value_name("DATA_DIRECTORY")
-                        .env("METEORA_DATA_DIRECTORY")
-                        .default_value("./data")
-                        .takes_value(true),
-                )
-                .arg(
-                    Arg::with_name("PEER_RAFT_ADDRESS")
-                        .help("Join a cluster in which a peer node with the specified raft address is joined.")
-                        .short("p")
-                        .long("peer-raft-address")
-                        .value_name("ADDRESS:RAFT_PORT")
-                        .takes_value(true),
-                )
-                .arg(
-                    Arg::with_name("ENABLE_AUTO_LEAVING")
-                        .help("Automatically delete the node from the cluster when stopped.")
-                        .short("l")
-                        .long("enable-auto-leaving"),
+                .args(&node_args())
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .name("config")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Manage configuration files.")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .name("check")
+                        .setting(AppSettings::DeriveDisplayOrder)
+                        .version(crate_version!())
+                        .author(crate_authors!())
+                        .about("Validate the configuration of a node, from a file and the options of the start command, without starting it.")
+                        .help_message("Prints help information.")
+                        .version_message("Prints version information.")
+                        .version_short("v")
+                        .args(&node_args())
                 )
         )
         .subcommand(
@@ -104,13 +77,54 @@ fn main() -> Result<(), std::io::Error> {
                 .version_short("v")
                 .arg(
                     Arg::with_name("ADDRESS")
-                        .help("An address that provides the raft service.")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
                         .short("a")
                         .long("address")
                         .value_name("ADDRESS:KV_PORT")
                         .default_value("127.0.0.1:7000")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("OTLP_ENDPOINT")
+                        .help("An OpenTelemetry collector endpoint to export traces to over OTLP/gRPC.")
+                        .long("otlp-endpoint")
+                        .value_name("OTLP_ENDPOINT")
+                        .env("METEORA_OTLP_ENDPOINT")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("KEY")
                         .help("A unique key that identifies the value in the key-value store.")
@@ -138,13 +152,54 @@ fn main() -> Result<(), std::io::Error> {
                 .version_short("v")
                 .arg(
                     Arg::with_name("ADDRESS")
-                        .help("An address that provides the raft service.")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
                         .short("a")
                         .long("address")
                         .value_name("ADDRESS:KV_PORT")
                         .default_value("127.0.0.1:7000")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("OTLP_ENDPOINT")
+                        .help("An OpenTelemetry collector endpoint to export traces to over OTLP/gRPC.")
+                        .long("otlp-endpoint")
+                        .value_name("OTLP_ENDPOINT")
+                        .env("METEORA_OTLP_ENDPOINT")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("KEY")
                         .help("A unique key that identifies the value in the key-value store.")
@@ -165,13 +220,54 @@ fn main() -> Result<(), std::io::Error> {
                 .version_short("v")
                 .arg(
                     Arg::with_name("ADDRESS")
-                        .help("An address that provides the raft service.")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
                         .short("a")
                         .long("address")
                         .value_name("ADDRESS:KV_PORT")
                         .default_value("127.0.0.1:7000")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("OTLP_ENDPOINT")
+                        .help("An OpenTelemetry collector endpoint to export traces to over OTLP/gRPC.")
+                        .long("otlp-endpoint")
+                        .value_name("OTLP_ENDPOINT")
+                        .env("METEORA_OTLP_ENDPOINT")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("KEY")
                         .help("A unique key that identifies the value in the key-value store.")
@@ -192,13 +288,37 @@ fn main() -> Result<(), std::io::Error> {
                 .version_short("v")
                 .arg(
                     Arg::with_name("ADDRESS")
-                        .help("An address that provides the raft service.")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
                         .short("a")
                         .long("address")
                         .value_name("ADDRESS:KV_PORT")
                         .default_value("127.0.0.1:7000")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
         )
         .subcommand(
             SubCommand::with_name("leave")
@@ -212,13 +332,37 @@ fn main() -> Result<(), std::io::Error> {
                 .version_short("v")
                 .arg(
                     Arg::with_name("ADDRESS")
-                        .help("An address that provides the raft service.")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
                         .short("a")
                         .long("address")
                         .value_name("ADDRESS:KV_PORT")
                         .default_value("127.0.0.1:7000")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("ID")
                         .help("A number that is unique ID in the cluster. It must be greater than or equal to 1.")
@@ -229,17 +373,709 @@ fn main() -> Result<(), std::io::Error> {
                         .takes_value(true),
                 )
         )
+        .subcommand(
+            SubCommand::with_name("authenticate")
+                .name("authenticate")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Authenticate a user and print a token")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS:KV_PORT")
+                        .default_value("127.0.0.1:7000")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("A user name.")
+                        .value_name("NAME")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("PASSWORD")
+                        .help("A password of the user.")
+                        .short("P")
+                        .long("password")
+                        .value_name("PASSWORD")
+                        .env("METEORA_PASSWORD")
+                        .required(true)
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("user-add")
+                .name("user-add")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Add a user. The first user must be root")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS:KV_PORT")
+                        .default_value("127.0.0.1:7000")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("A user name.")
+                        .value_name("NAME")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("PASSWORD")
+                        .help("A password of the user.")
+                        .short("P")
+                        .long("password")
+                        .value_name("PASSWORD")
+                        .env("METEORA_PASSWORD")
+                        .required(true)
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("user-delete")
+                .name("user-delete")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Delete a user")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS:KV_PORT")
+                        .default_value("127.0.0.1:7000")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("A user name.")
+                        .value_name("NAME")
+                        .required(true)
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("change-password")
+                .name("change-password")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Change the password of a user")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS:KV_PORT")
+                        .default_value("127.0.0.1:7000")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("A user name.")
+                        .value_name("NAME")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("PASSWORD")
+                        .help("A password of the user.")
+                        .short("P")
+                        .long("password")
+                        .value_name("PASSWORD")
+                        .env("METEORA_PASSWORD")
+                        .required(true)
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("role-add")
+                .name("role-add")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Add a role")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS:KV_PORT")
+                        .default_value("127.0.0.1:7000")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("A role name.")
+                        .value_name("NAME")
+                        .required(true)
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("role-delete")
+                .name("role-delete")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Delete a role")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS:KV_PORT")
+                        .default_value("127.0.0.1:7000")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("A role name.")
+                        .value_name("NAME")
+                        .required(true)
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("role-grant")
+                .name("role-grant")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Grant a permission on a key, a key range or a prefix to a role")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS:KV_PORT")
+                        .default_value("127.0.0.1:7000")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("A role name.")
+                        .value_name("NAME")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("PERMISSION_TYPE")
+                        .help("A permission type.")
+                        .value_name("PERMISSION_TYPE")
+                        .possible_values(&["read", "write", "readwrite"])
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("KEY")
+                        .help("A key, or the start of a key range.")
+                        .value_name("KEY")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("RANGE_END")
+                        .help("The end of the key range (exclusive).")
+                        .value_name("RANGE_END")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("PREFIX")
+                        .help("Treat the key as a prefix.")
+                        .long("prefix")
+                        .conflicts_with("RANGE_END"),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("role-revoke")
+                .name("role-revoke")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Revoke a permission on a key, a key range or a prefix from a role")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS:KV_PORT")
+                        .default_value("127.0.0.1:7000")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("A role name.")
+                        .value_name("NAME")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("KEY")
+                        .help("A key, or the start of a key range.")
+                        .value_name("KEY")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("RANGE_END")
+                        .help("The end of the key range (exclusive).")
+                        .value_name("RANGE_END")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("PREFIX")
+                        .help("Treat the key as a prefix.")
+                        .long("prefix")
+                        .conflicts_with("RANGE_END"),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("user-grant-role")
+                .name("user-grant-role")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Grant a role to a user")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS:KV_PORT")
+                        .default_value("127.0.0.1:7000")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("A user name.")
+                        .value_name("NAME")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ROLE")
+                        .help("A role name.")
+                        .value_name("ROLE")
+                        .required(true)
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("user-revoke-role")
+                .name("user-revoke-role")
+                .setting(AppSettings::DeriveDisplayOrder)
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Revoke a role from a user")
+                .help_message("Prints help information.")
+                .version_message("Prints version information.")
+                .version_short("v")
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Addresses that provide the raft service, separated by commas. The first one that answers is used.")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS:KV_PORT")
+                        .default_value("127.0.0.1:7000")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("TLS_CERT")
+                        .help("A PEM encoded certificate file to present to the server.")
+                        .long("tls-cert")
+                        .value_name("TLS_CERT")
+                        .env("METEORA_TLS_CERT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_KEY")
+                        .help("A PEM encoded private key file for the certificate.")
+                        .long("tls-key")
+                        .value_name("TLS_KEY")
+                        .env("METEORA_TLS_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TLS_CA")
+                        .help("A PEM encoded CA certificate file to verify the server certificate.")
+                        .long("tls-ca")
+                        .value_name("TLS_CA")
+                        .env("METEORA_TLS_CA")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("TOKEN")
+                        .help("A token issued by the authenticate command.")
+                        .short("t")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .env("METEORA_TOKEN")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("A user name.")
+                        .value_name("NAME")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ROLE")
+                        .help("A role name.")
+                        .value_name("ROLE")
+                        .required(true)
+                        .takes_value(true),
+                )
+        )
         .get_matches();
 
     let (subcommand, some_options) = app.subcommand();
     let options = some_options.unwrap();
     let run_cli = match subcommand {
         "start" => run_start_cli,
+        "config" => run_config_cli,
         "put" => run_put_cli,
         "get" => run_get_cli,
         "delete" => run_delete_cli,
         "status" => run_status_cli,
         "leave" => run_leave_cli,
+        "authenticate" => run_authenticate_cli,
+        "user-add" => run_user_add_cli,
+        "user-delete" => run_user_delete_cli,
+        "change-password" => run_change_password_cli,
+        "role-add" => run_role_add_cli,
+        "role-delete" => run_role_delete_cli,
+        "role-grant" => run_role_grant_cli,
+        "role-revoke" => run_role_revoke_cli,
+        "user-grant-role" => run_user_grant_role_cli,
+        "user-revoke-role" => run_user_revoke_role_cli,
         _ => panic!("Subcommand {} is unknown", subcommand),
     };
 
@@ -0,0 +1,549 @@
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use log::*;
+
+use meteora_client::kv::client::{Item, KVClient, PutOptions};
+use meteora_proto::proto::kv::PutCondition;
+use meteora_server::kv::record::now_millis;
+use meteora_server::tls::TlsConfig;
+
+// The limits of keys and items, the same as the defaults of memcached.
+const MAX_KEY_LEN: usize = 250;
+const MAX_ITEM_SIZE: usize = 1024 * 1024;
+// An exptime larger than this is a UNIX time rather than a number of seconds.
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+// The longest command line accepted, which is a get of this many keys. The rest of a storage
+// command line takes far less than the slack.
+const MAX_GET_KEYS: usize = 1024;
+const MAX_LINE_LEN: usize = (MAX_KEY_LEN + 1) * MAX_GET_KEYS + 128;
+// The number of attempts of a read-modify-write (incr, decr and touch) under contention.
+const MAX_CAS_ATTEMPTS: usize = 10;
+
+// A client connection. Each connection has its own client.
+struct Session {
+    kv_client: KVClient,
+}
+
+// A parsed storage command: <command> <key> <flags> <exptime> <bytes> [<cas unique>] [noreply]
+struct Storage {
+    key: Vec<u8>,
+    flags: u32,
+    ttl: u64,
+    cas: u64,
+}
+
+impl Session {
+    // Returns the reply of a command, or an empty one if it has none.
+    fn handle<R: BufRead>(&mut self, args: &[&[u8]], reader: &mut R) -> Result<Vec<u8>, Error> {
+        let name = String::from_utf8_lossy(args[0]).to_string();
+        let args = &args[1..];
+        let noreply = args.last() == Some(&&b"noreply"[..]);
+        // The key of a storage command is checked after its data block has been read.
+        let keys = match name.as_str() {
+            "get" | "gets" => args,
+            "delete" | "incr" | "decr" | "touch" => &args[..args.len().min(1)],
+            _ => &[],
+        };
+        if !keys.iter().all(|key| valid_key(key)) {
+            return Ok(client_error("bad command line format"));
+        }
+        let reply = match name.as_str() {
+            "get" | "gets" if !args.is_empty() => return self.get(args, name == "gets"),
+            "set" | "add" | "replace" | "cas" => {
+                let (storage, value) = match read_storage(&name, args, reader)? {
+                    Ok(storage) => storage,
+                    Err(reply) => return Ok(reply),
+                };
+                self.store(&name, storage, value)?
+            }
+            "delete" if args.len() == 1 || (args.len() == 2 && noreply) => self.delete(args[0])?,
+            "incr" | "decr" if args.len() == 2 || (args.len() == 3 && noreply) => {
+                let delta = match parse::<u64>(args[1]) {
+                    Some(delta) => delta,
+                    None => return Ok(client_error("invalid numeric delta argument")),
+                };
+                self.incr(args[0], delta, name == "decr")?
+            }
+            "touch" if args.len() == 2 || (args.len() == 3 && noreply) => {
+                let ttl = match parse::<i64>(args[1]).and_then(ttl) {
+                    Some(ttl) => ttl,
+                    None => return Ok(client_error("invalid exptime argument")),
+                };
+                self.touch(args[0], ttl)?
+            }
+            "version" => format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")).into_bytes(),
+            _ => return Ok(b"ERROR\r\n".to_vec()),
+        };
+        Ok(if noreply { Vec::new() } else { reply })
+    }
+
+    fn get(&mut self, keys: &[&[u8]], with_cas: bool) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        for key in keys {
+            let item = match self.get_item(key)? {
+                Some(item) => item,
+                None => continue,
+            };
+            buf.extend_from_slice(b"VALUE ");
+            buf.extend_from_slice(key);
+            if with_cas {
+                buf.extend_from_slice(
+                    format!(" {} {} {}\r\n", item.flags, item.value.len(), item.version).as_bytes(),
+                );
+            } else {
+                buf.extend_from_slice(
+                    format!(" {} {}\r\n", item.flags, item.value.len()).as_bytes(),
+                );
+            }
+            buf.extend_from_slice(&item.value);
+            buf.extend_from_slice(b"\r\n");
+        }
+        buf.extend_from_slice(b"END\r\n");
+        Ok(buf)
+    }
+
+    fn store(&mut self, name: &str, storage: Storage, value: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let (condition, version) = match name {
+            "set" => (PutCondition::ALWAYS, 0),
+            "add" => (PutCondition::IF_NOT_EXISTS, 0),
+            "replace" => (PutCondition::IF_EXISTS, 0),
+            "cas" => (PutCondition::IF_VERSION, storage.cas),
+            _ => unreachable!(),
+        };
+        let options = PutOptions {
+            flags: storage.flags,
+            ttl: storage.ttl,
+            condition,
+            version,
+            ..Default::default()
+        };
+        if self
+            .kv_client
+            .put_with(storage.key.clone(), value, &options)?
+        {
+            return Ok(b"STORED\r\n".to_vec());
+        }
+        if name != "cas" {
+            return Ok(b"NOT_STORED\r\n".to_vec());
+        }
+        // The cas failed either because the item is missing or because it has been modified.
+        match self.get_item(&storage.key)? {
+            Some(_) => Ok(b"EXISTS\r\n".to_vec()),
+            None => Ok(b"NOT_FOUND\r\n".to_vec()),
+        }
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        if self.kv_client.delete(key.to_vec())? {
+            Ok(b"DELETED\r\n".to_vec())
+        } else {
+            Ok(b"NOT_FOUND\r\n".to_vec())
+        }
+    }
+
+    // The value is a decimal 64-bit unsigned integer. An increment wraps around, and a
+    // decrement stops at 0, as in memcached.
+    fn incr(&mut self, key: &[u8], delta: u64, decr: bool) -> Result<Vec<u8>, Error> {
+        for _ in 0..MAX_CAS_ATTEMPTS {
+            let item = match self.get_item(key)? {
+                Some(item) => item,
+                None => return Ok(b"NOT_FOUND\r\n".to_vec()),
+            };
+            let current = match parse::<u64>(&item.value) {
+                Some(current) => current,
+                None => {
+                    return Ok(client_error(
+                        "cannot increment or decrement non-numeric value",
+                    ))
+                }
+            };
+            let value = if decr {
+                current.saturating_sub(delta)
+            } else {
+                current.wrapping_add(delta)
+            };
+            let options = PutOptions {
+                flags: item.flags,
+                keep_ttl: true,
+                condition: PutCondition::IF_VERSION,
+                version: item.version,
+                ..Default::default()
+            };
+            if self
+                .kv_client
+                .put_with(key.to_vec(), value.to_string().into_bytes(), &options)?
+            {
+                return Ok(format!("{}\r\n", value).into_bytes());
+            }
+        }
+        Ok(server_error("too much contention"))
+    }
+
+    fn touch(&mut self, key: &[u8], ttl: u64) -> Result<Vec<u8>, Error> {
+        for _ in 0..MAX_CAS_ATTEMPTS {
+            let item = match self.get_item(key)? {
+                Some(item) => item,
+                None => return Ok(b"NOT_FOUND\r\n".to_vec()),
+            };
+            let options = PutOptions {
+                flags: item.flags,
+                ttl,
+                condition: PutCondition::IF_VERSION,
+                version: item.version,
+                ..Default::default()
+            };
+            if self
+                .kv_client
+                .put_with(key.to_vec(), item.value, &options)?
+            {
+                return Ok(b"TOUCHED\r\n".to_vec());
+            }
+        }
+        Ok(server_error("too much contention"))
+    }
+
+    fn get_item(&mut self, key: &[u8]) -> Result<Option<Item>, Error> {
+        match self.kv_client.get_item(key.to_vec()) {
+            Ok(item) => Ok(Some(item)),
+            Err(e) if e.is_not_found() => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn client_error(message: &str) -> Vec<u8> {
+    format!("CLIENT_ERROR {}\r\n", message).into_bytes()
+}
+
+fn server_error(message: &str) -> Vec<u8> {
+    // A line break would end the error early.
+    let message = message.replace(|c| c == '\r' || c == '\n', " ");
+    format!("SERVER_ERROR {}\r\n", message).into_bytes()
+}
+
+fn parse<T: std::str::FromStr>(arg: &[u8]) -> Option<T> {
+    std::str::from_utf8(arg).ok()?.trim_end().parse::<T>().ok()
+}
+
+// Converts an exptime into a TTL in milliseconds, where 0 means no expiration. An exptime of
+// up to 30 days is a number of seconds, and a larger one is a UNIX time. An item that has
+// already expired gets the shortest TTL, so that it is never returned. Returns None if the
+// exptime is too large to be written in milliseconds.
+fn ttl(exptime: i64) -> Option<u64> {
+    if exptime == 0 {
+        return Some(0);
+    }
+    let ttl = if exptime < 0 {
+        0
+    } else if exptime <= MAX_RELATIVE_EXPTIME {
+        exptime * 1000
+    } else {
+        exptime.checked_mul(1000)? - now_millis() as i64
+    };
+    Some(ttl.max(1) as u64)
+}
+
+fn valid_key(key: &[u8]) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LEN && !key.iter().any(|c| c.is_ascii_control())
+}
+
+// Parses a storage command and reads its data block. The outer error is an I/O error, and the
+// inner one is the reply to a malformed command.
+fn read_storage<R: BufRead>(
+    name: &str,
+    args: &[&[u8]],
+    reader: &mut R,
+) -> Result<Result<(Storage, Vec<u8>), Vec<u8>>, Error> {
+    let len = if name == "cas" { 5 } else { 4 };
+    if args.len() != len && !(args.len() == len + 1 && args[len] == b"noreply") {
+        return Ok(Err(b"ERROR\r\n".to_vec()));
+    }
+    let (flags, exptime, bytes) = match (
+        parse::<u32>(args[1]),
+        parse::<i64>(args[2]),
+        parse::<usize>(args[3]),
+    ) {
+        (Some(flags), Some(exptime), Some(bytes)) => (flags, exptime, bytes),
+        _ => return Ok(Err(client_error("bad command line format"))),
+    };
+    let cas = if name == "cas" {
+        match parse::<u64>(args[4]) {
+            Some(cas) => cas,
+            None => return Ok(Err(client_error("bad command line format"))),
+        }
+    } else {
+        0
+    };
+
+    // The data block is skipped if the command is rejected, so that it is not read as commands.
+    if bytes > MAX_ITEM_SIZE {
+        io::copy(&mut reader.take(bytes as u64 + 2), &mut io::sink())?;
+        return Ok(Err(server_error("object too large for cache")));
+    }
+    let mut data = vec![0u8; bytes + 2];
+    reader.read_exact(&mut data)?;
+    if !data.ends_with(b"\r\n") {
+        return Ok(Err(client_error("bad data chunk")));
+    }
+    data.truncate(bytes);
+    if !valid_key(args[0]) {
+        return Ok(Err(client_error("bad command line format")));
+    }
+    let ttl = match ttl(exptime) {
+        Some(ttl) => ttl,
+        None => return Ok(Err(client_error("invalid exptime argument"))),
+    };
+
+    Ok(Ok((
+        Storage {
+            key: args[0].to_vec(),
+            flags,
+            ttl,
+            cas,
+        },
+        data,
+    )))
+}
+
+// Reads a command line of at most MAX_LINE_LEN bytes, so that a client can't make the line
+// grow without bounds.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    let mut line = Vec::new();
+    let limit = MAX_LINE_LEN as u64 + 2;
+    if reader.take(limit).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') && line.len() as u64 == limit {
+        return Err(Error::new(ErrorKind::InvalidData, "line too long"));
+    }
+    while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    raft_address: &str,
+    tls: Option<TlsConfig>,
+) -> Result<(), Error> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let kv_client = match KVClient::connect(&[raft_address], tls) {
+        Ok(kv_client) => kv_client,
+        Err(e) => {
+            writer.write_all(&server_error(&e.to_string()))?;
+            return Err(e.into());
+        }
+    };
+    let mut session = Session { kv_client };
+
+    loop {
+        let line = match read_line(&mut reader) {
+            Ok(Some(line)) => line,
+            Ok(None) => return Ok(()),
+            // The rest of the line can't be told apart from the next command, so the connection
+            // is closed, as memcached does.
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                writer.write_all(&client_error(&e.to_string()))?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        let args: Vec<&[u8]> = line
+            .split(|c| *c == b' ')
+            .filter(|arg| !arg.is_empty())
+            .collect();
+        if args.is_empty() {
+            continue;
+        }
+        if args[0] == b"quit" {
+            return Ok(());
+        }
+
+        let reply = match session.handle(&args, &mut reader) {
+            Ok(reply) => reply,
+            // The connection is broken in the middle of a data block.
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Err(e),
+            Err(e) => {
+                error!("failed to handle command: error={}", e);
+                server_error(&e.to_string())
+            }
+        };
+        writer.write_all(&reply)?;
+    }
+}
+
+// Serves the memcached text protocol on `address`. Each connection is handled by a thread with
+// its own client for the node at `raft_address`.
+pub fn serve(address: &str, raft_address: &str, tls: Option<TlsConfig>) -> Result<(), Error> {
+    let listener = TcpListener::bind(address).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!(
+                "failed to start memcached server: address={}, error={}",
+                address, e
+            ),
+        )
+    })?;
+    info!("start memcached server on {}", address);
+
+    let raft_address = raft_address.to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("failed to accept connection: {:?}", e);
+                    continue;
+                }
+            };
+            let raft_address = raft_address.clone();
+            let tls = tls.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = handle_connection(stream, &raft_address, tls) {
+                    debug!("connection closed: peer={:?}, error={}", peer, e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(name: &str, line: &str, data: &[u8]) -> (Result<(Storage, Vec<u8>), Vec<u8>>, Vec<u8>) {
+        let args: Vec<&[u8]> = line.split(' ').map(|arg| arg.as_bytes()).collect();
+        let mut reader = BufReader::new(data);
+        let result = read_storage(name, &args, &mut reader).unwrap();
+        // The rest of the input, which is read as the next command.
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        (result, rest)
+    }
+
+    #[test]
+    fn test_ttl() {
+        assert_eq!(ttl(0), Some(0));
+        assert_eq!(ttl(-1), Some(1));
+        assert_eq!(ttl(10), Some(10_000));
+        assert_eq!(
+            ttl(MAX_RELATIVE_EXPTIME),
+            Some(MAX_RELATIVE_EXPTIME as u64 * 1000)
+        );
+        // A UNIX time in the past.
+        assert_eq!(ttl(MAX_RELATIVE_EXPTIME + 1), Some(1));
+        let in_a_minute = now_millis() as i64 / 1000 + 60;
+        let ttl = ttl(in_a_minute).unwrap();
+        assert!(ttl > 58_000 && ttl <= 60_000);
+    }
+
+    #[test]
+    fn test_ttl_overflow() {
+        assert_eq!(ttl(i64::MAX), None);
+        assert_eq!(ttl(i64::MAX / 1000 + 1), None);
+    }
+
+    #[test]
+    fn test_read_storage() {
+        let (result, rest) = read("set", "key 5 10 5", b"value\r\nget key\r\n");
+        let (storage, value) = result.ok().unwrap();
+        assert_eq!(storage.key, b"key".to_vec());
+        assert_eq!(storage.flags, 5);
+        assert_eq!(storage.ttl, 10_000);
+        assert_eq!(storage.cas, 0);
+        assert_eq!(value, b"value".to_vec());
+        assert_eq!(rest, b"get key\r\n".to_vec());
+
+        let (result, _) = read("cas", "key 0 0 5 42 noreply", b"value\r\n");
+        assert_eq!(result.ok().unwrap().0.cas, 42);
+    }
+
+    #[test]
+    fn test_read_storage_invalid() {
+        let (result, _) = read("set", "key 0 0", b"");
+        assert_eq!(result.err().unwrap(), b"ERROR\r\n".to_vec());
+        let (result, _) = read("set", "key x 0 5", b"value\r\n");
+        assert_eq!(
+            result.err().unwrap(),
+            client_error("bad command line format")
+        );
+        let (result, _) = read("set", "key 0 0 3", b"value\r\n");
+        assert_eq!(result.err().unwrap(), client_error("bad data chunk"));
+    }
+
+    #[test]
+    fn test_read_storage_skips_rejected_data() {
+        let exptime = i64::MAX.to_string();
+        let (result, rest) = read("set", &format!("key 0 {} 5", exptime), b"value\r\nquit\r\n");
+        assert_eq!(
+            result.err().unwrap(),
+            client_error("invalid exptime argument")
+        );
+        assert_eq!(rest, b"quit\r\n".to_vec());
+
+        let (result, rest) = read(
+            "set",
+            &format!("{} 0 0 5", "k".repeat(MAX_KEY_LEN + 1)),
+            b"value\r\nquit\r\n",
+        );
+        assert_eq!(
+            result.err().unwrap(),
+            client_error("bad command line format")
+        );
+        assert_eq!(rest, b"quit\r\n".to_vec());
+
+        let data = [vec![0u8; MAX_ITEM_SIZE + 1], b"\r\nquit\r\n".to_vec()].concat();
+        let (result, rest) = read("set", &format!("key 0 0 {}", MAX_ITEM_SIZE + 1), &data);
+        assert_eq!(
+            result.err().unwrap(),
+            server_error("object too large for cache")
+        );
+        assert_eq!(rest, b"quit\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut reader = BufReader::new(&b"get a b\r\nget c\n\nget d"[..]);
+        assert_eq!(read_line(&mut reader).unwrap(), Some(b"get a b".to_vec()));
+        assert_eq!(read_line(&mut reader).unwrap(), Some(b"get c".to_vec()));
+        assert_eq!(read_line(&mut reader).unwrap(), Some(Vec::new()));
+        assert_eq!(read_line(&mut reader).unwrap(), Some(b"get d".to_vec()));
+        assert_eq!(read_line(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_line_too_long() {
+        let keys = vec!["k".repeat(MAX_KEY_LEN); MAX_GET_KEYS].join(" ");
+        let line = format!("get {}\r\n", keys);
+        assert!(line.len() <= MAX_LINE_LEN + 2);
+        let mut reader = BufReader::new(line.as_bytes());
+        assert_eq!(
+            read_line(&mut reader).unwrap(),
+            Some(format!("get {}", keys).into_bytes())
+        );
+
+        let line = format!("get {}\r\n", "k".repeat(MAX_LINE_LEN * 2));
+        let mut reader = BufReader::new(line.as_bytes());
+        let e = read_line(&mut reader).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            client_error(&e.to_string()),
+            b"CLIENT_ERROR line too long\r\n"
+        );
+    }
+
+    #[test]
+    fn test_valid_key() {
+        assert!(valid_key(b"key"));
+        assert!(!valid_key(b""));
+        assert!(!valid_key(b"a\x01b"));
+        assert!(!valid_key("k".repeat(MAX_KEY_LEN + 1).as_bytes()));
+    }
+}
@@ -0,0 +1,698 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use log::*;
+
+use meteora_client::auth::client::{prefix_range_end, AuthClient};
+use meteora_client::kv::client::KVClient;
+use meteora_proto::proto::kv::{BatchOp, DeleteReq, PutCondition, PutReq};
+use meteora_server::tls::TlsConfig;
+
+const DEFAULT_SCAN_COUNT: u64 = 10;
+// The largest bulk string accepted from a client, the same as the default of Redis.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+// The number of SCAN cursors kept by a connection. The oldest ones are dropped first.
+const MAX_CURSORS: usize = 1024;
+
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(s) => buf.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+            Reply::Error(e) => {
+                // A line break would end the error early.
+                let e = e.replace(|c| c == '\r' || c == '\n', " ");
+                buf.extend_from_slice(format!("-{}\r\n", e).as_bytes());
+            }
+            Reply::Integer(i) => buf.extend_from_slice(format!(":{}\r\n", i).as_bytes()),
+            Reply::Bulk(Some(b)) => {
+                buf.extend_from_slice(format!("${}\r\n", b.len()).as_bytes());
+                buf.extend_from_slice(b);
+                buf.extend_from_slice(b"\r\n");
+            }
+            Reply::Bulk(None) => buf.extend_from_slice(b"$-1\r\n"),
+            Reply::Array(items) => {
+                buf.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.write_to(buf);
+                }
+            }
+        }
+    }
+}
+
+// The SCAN cursors of a connection. Clients parse cursors as integers, so each cursor is a
+// number that stands for the last key returned, which the next call resumes after.
+struct Cursors {
+    keys: BTreeMap<u64, Vec<u8>>,
+    next_id: u64,
+}
+
+impl Cursors {
+    fn new() -> Cursors {
+        Cursors {
+            keys: BTreeMap::new(),
+            // 0 starts an iteration.
+            next_id: 1,
+        }
+    }
+
+    fn insert(&mut self, key: Vec<u8>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.keys.insert(id, key);
+        if self.keys.len() > MAX_CURSORS {
+            let oldest = *self.keys.keys().next().unwrap();
+            self.keys.remove(&oldest);
+        }
+        id
+    }
+
+    // Returns the key that the cursor stands for, or None for 0. A cursor stays valid after it
+    // is used, so that a call can be retried.
+    fn get(&self, cursor: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, "invalid cursor");
+        let id = String::from_utf8_lossy(cursor)
+            .parse::<u64>()
+            .map_err(|_| invalid())?;
+        if id == 0 {
+            return Ok(None);
+        }
+        self.keys.get(&id).cloned().map(Some).ok_or_else(invalid)
+    }
+}
+
+// A client connection. Each connection has its own clients, so that AUTH only applies to it.
+struct Session {
+    raft_address: String,
+    tls: Option<TlsConfig>,
+    kv_client: KVClient,
+    cursors: Cursors,
+}
+
+impl Session {
+    fn handle(&mut self, args: &[Vec<u8>]) -> Reply {
+        let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+        let args = &args[1..];
+        let (min, max) = match name.as_str() {
+            "ping" => (0, Some(1)),
+            "quit" => (0, Some(0)),
+            "auth" => (1, Some(2)),
+            "get" | "incr" | "keys" => (1, Some(1)),
+            "set" => (2, None),
+            "del" | "exists" | "mget" | "scan" => (1, None),
+            "mset" => (2, None),
+            _ => return Reply::Error(format!("ERR unknown command '{}'", name)),
+        };
+        if args.len() < min || max.map_or(false, |max| args.len() > max) {
+            return Reply::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name
+            ));
+        }
+
+        let result = match name.as_str() {
+            "ping" => Ok(match args.first() {
+                Some(message) => Reply::Bulk(Some(message.clone())),
+                None => Reply::Simple("PONG"),
+            }),
+            "quit" => Ok(Reply::Simple("OK")),
+            "auth" => self.auth(args),
+            "get" => self.get(&args[0]),
+            "set" => self.set(args),
+            "del" => self.del(args),
+            "exists" => self.exists(args),
+            "incr" => self.incr(&args[0]),
+            "mget" => self.mget(args),
+            "mset" => self.mset(args),
+            "scan" => self.scan(args),
+            "keys" => self.keys(&args[0]),
+            _ => unreachable!(),
+        };
+        match result {
+            Ok(reply) => reply,
+            Err(e) => match e.kind() {
+                ErrorKind::PermissionDenied => Reply::Error(format!("NOPERM {}", e)),
+                ErrorKind::InvalidInput => Reply::Error(format!("ERR {}", e)),
+                _ => {
+                    error!("failed to handle command: name={}, error={}", name, e);
+                    Reply::Error(format!("ERR {}", e))
+                }
+            },
+        }
+    }
+
+    // AUTH [user] password. Without a user name, the password of root is checked.
+    fn auth(&mut self, args: &[Vec<u8>]) -> Result<Reply, Error> {
+        let (name, password) = match args {
+            [password] => ("root".to_string(), password),
+            [name, password] => (String::from_utf8_lossy(name).to_string(), password),
+            _ => unreachable!(),
+        };
+        let mut auth_client = AuthClient::connect(&[self.raft_address.as_str()], self.tls.clone())?;
+        match auth_client.authenticate(&name, &String::from_utf8_lossy(password)) {
+            Ok(token) => {
+                self.kv_client.set_token(token);
+                Ok(Reply::Simple("OK"))
+            }
+            Err(e) if e.is_permission_denied() => Ok(Reply::Error(
+                "WRONGPASS invalid username-password pair".to_string(),
+            )),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Reply, Error> {
+        match self.kv_client.get(key.to_vec()) {
+            Ok(value) => Ok(Reply::Bulk(Some(value))),
+            Err(e) if e.is_not_found() => Ok(Reply::Bulk(None)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // SET key value [EX seconds|PX milliseconds] [NX|XX]
+    fn set(&mut self, args: &[Vec<u8>]) -> Result<Reply, Error> {
+        let mut ttl = 0;
+        let mut condition = PutCondition::ALWAYS;
+        let mut options = args[2..].iter();
+        while let Some(option) = options.next() {
+            match String::from_utf8_lossy(option).to_uppercase().as_str() {
+                unit @ "EX" | unit @ "PX" => {
+                    let value = options.next().ok_or_else(syntax_error)?;
+                    let value = parse_integer(value)?;
+                    if value <= 0 {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "invalid expire time in 'set' command",
+                        ));
+                    }
+                    ttl = if unit == "EX" {
+                        (value as u64).saturating_mul(1000)
+                    } else {
+                        value as u64
+                    };
+                }
+                "NX" if condition == PutCondition::ALWAYS => {
+                    condition = PutCondition::IF_NOT_EXISTS
+                }
+                "XX" if condition == PutCondition::ALWAYS => condition = PutCondition::IF_EXISTS,
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        let applied = self
+            .kv_client
+            .put_if(args[0].clone(), args[1].clone(), ttl, condition)?;
+        if applied {
+            Ok(Reply::Simple("OK"))
+        } else {
+            Ok(Reply::Bulk(None))
+        }
+    }
+
+    fn del(&mut self, keys: &[Vec<u8>]) -> Result<Reply, Error> {
+        let ops = keys
+            .iter()
+            .map(|key| {
+                let mut req = DeleteReq::new();
+                req.set_key(key.clone());
+                let mut op = BatchOp::new();
+                op.set_delete(req);
+                op
+            })
+            .collect();
+        let applied = self.kv_client.batch(ops)?;
+        Ok(Reply::Integer(applied.iter().filter(|a| **a).count() as i64))
+    }
+
+    fn exists(&mut self, keys: &[Vec<u8>]) -> Result<Reply, Error> {
+        let mut count = 0;
+        for key in keys {
+            match self.kv_client.get(key.clone()) {
+                Ok(_) => count += 1,
+                Err(e) if e.is_not_found() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(Reply::Integer(count))
+    }
+
+    fn incr(&mut self, key: &[u8]) -> Result<Reply, Error> {
+        match self.kv_client.incr(key.to_vec(), 1) {
+            Ok(value) => Ok(Reply::Integer(value)),
+            Err(e) if e.is_invalid_argument() => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "value is not an integer or out of range",
+            )),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn mget(&mut self, keys: &[Vec<u8>]) -> Result<Reply, Error> {
+        let mut values = Vec::new();
+        for key in keys {
+            values.push(self.get(key)?);
+        }
+        Ok(Reply::Array(values))
+    }
+
+    fn mset(&mut self, args: &[Vec<u8>]) -> Result<Reply, Error> {
+        if args.len() % 2 != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "wrong number of arguments for 'mset' command",
+            ));
+        }
+        let ops = args
+            .chunks(2)
+            .map(|kv| {
+                let mut req = PutReq::new();
+                req.set_key(kv[0].clone());
+                req.set_value(kv[1].clone());
+                let mut op = BatchOp::new();
+                op.set_put(req);
+                op
+            })
+            .collect();
+        self.kv_client.batch(ops)?;
+        Ok(Reply::Simple("OK"))
+    }
+
+    // SCAN cursor [MATCH pattern] [COUNT count]. The cursor stands for the last key returned, so
+    // each call resumes after it, and every key that exists for the whole iteration is returned
+    // once. Cursors belong to the connection that they were returned on.
+    fn scan(&mut self, args: &[Vec<u8>]) -> Result<Reply, Error> {
+        let last_key = self.cursors.get(&args[0])?;
+        let mut pattern = None;
+        let mut count = DEFAULT_SCAN_COUNT;
+        let mut options = args[1..].iter();
+        while let Some(option) = options.next() {
+            match String::from_utf8_lossy(option).to_uppercase().as_str() {
+                "MATCH" => pattern = Some(options.next().ok_or_else(syntax_error)?),
+                "COUNT" => {
+                    let value = parse_integer(options.next().ok_or_else(syntax_error)?)?;
+                    if value < 1 {
+                        return Err(syntax_error());
+                    }
+                    count = value as u64;
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        // The first key after the last one returned.
+        let start = match last_key {
+            Some(mut key) => {
+                key.push(0);
+                key
+            }
+            None => Vec::new(),
+        };
+        let kvs = self.kv_client.scan(start, vec![0], count)?;
+        let next_cursor = match kvs.last() {
+            Some((key, _)) if kvs.len() as u64 == count => self.cursors.insert(key.clone()),
+            _ => 0,
+        };
+        let keys = kvs
+            .into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| pattern.map_or(true, |p| glob_match(p, key)))
+            .map(|key| Reply::Bulk(Some(key)))
+            .collect();
+        Ok(Reply::Array(vec![
+            Reply::Bulk(Some(next_cursor.to_string().into_bytes())),
+            Reply::Array(keys),
+        ]))
+    }
+
+    fn keys(&mut self, pattern: &[u8]) -> Result<Reply, Error> {
+        // Only the keys that start with the literal prefix of the pattern can match.
+        let prefix: Vec<u8> = pattern
+            .iter()
+            .take_while(|c| !b"*?[\\".contains(c))
+            .cloned()
+            .collect();
+        let range_end = prefix_range_end(&prefix);
+        let keys = self
+            .kv_client
+            .scan(prefix, range_end, 0)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| glob_match(pattern, key))
+            .map(|key| Reply::Bulk(Some(key)))
+            .collect();
+        Ok(Reply::Array(keys))
+    }
+}
+
+fn syntax_error() -> Error {
+    Error::new(ErrorKind::InvalidInput, "syntax error")
+}
+
+fn parse_integer(value: &[u8]) -> Result<i64, Error> {
+    String::from_utf8_lossy(value).parse::<i64>().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "value is not an integer or out of range",
+        )
+    })
+}
+
+// Matches a key against a glob-style pattern in the same way as Redis: `*`, `?`, `[abc]`,
+// `[^abc]`, `[a-z]` and `\` to escape a special character.
+fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    match pattern.first() {
+        None => key.is_empty(),
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            (0..=key.len()).any(|i| glob_match(rest, &key[i..]))
+        }
+        Some(b'?') => !key.is_empty() && glob_match(&pattern[1..], &key[1..]),
+        Some(b'[') => {
+            let c = match key.first() {
+                Some(c) => *c,
+                None => return false,
+            };
+            let mut i = 1;
+            let negate = pattern.get(i) == Some(&b'^');
+            if negate {
+                i += 1;
+            }
+            let mut matched = false;
+            while i < pattern.len() && pattern[i] != b']' {
+                if pattern[i] == b'\\' && i + 1 < pattern.len() {
+                    matched |= pattern[i + 1] == c;
+                    i += 2;
+                } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']'
+                {
+                    let (lo, hi) = if pattern[i] <= pattern[i + 2] {
+                        (pattern[i], pattern[i + 2])
+                    } else {
+                        (pattern[i + 2], pattern[i])
+                    };
+                    matched |= lo <= c && c <= hi;
+                    i += 3;
+                } else {
+                    matched |= pattern[i] == c;
+                    i += 1;
+                }
+            }
+            // An unterminated class runs to the end of the pattern.
+            let rest = if i < pattern.len() {
+                &pattern[i + 1..]
+            } else {
+                &[][..]
+            };
+            matched != negate && glob_match(rest, &key[1..])
+        }
+        Some(b'\\') if pattern.len() > 1 => {
+            key.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &key[1..])
+        }
+        Some(c) => key.first() == Some(c) && glob_match(&pattern[1..], &key[1..]),
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_length(line: &[u8]) -> Result<i64, Error> {
+    String::from_utf8_lossy(line).parse::<i64>().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid length: {}", String::from_utf8_lossy(line)),
+        )
+    })
+}
+
+// Reads a command, either as an array of bulk strings or as an inline command.
+// Returns None when the connection is closed.
+fn read_command<R: BufRead>(reader: &mut R) -> Result<Option<Vec<Vec<u8>>>, Error> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    if line.first() != Some(&b'*') {
+        return Ok(Some(
+            line.split(|c| c.is_ascii_whitespace())
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| arg.to_vec())
+                .collect(),
+        ));
+    }
+
+    let len = parse_length(&line[1..])?;
+    let mut args = Vec::new();
+    for _ in 0..len.max(0) {
+        let line = match read_line(reader)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        if line.first() != Some(&b'$') {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "expected a bulk string".to_string(),
+            ));
+        }
+        let len = parse_length(&line[1..])?;
+        if len < 0 || len as usize > MAX_BULK_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid bulk length: {}", len),
+            ));
+        }
+        let mut arg = vec![0u8; len as usize + 2];
+        reader.read_exact(&mut arg)?;
+        arg.truncate(len as usize);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    raft_address: &str,
+    tls: Option<TlsConfig>,
+) -> Result<(), Error> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let kv_client = match KVClient::connect(&[raft_address], tls.clone()) {
+        Ok(kv_client) => kv_client,
+        Err(e) => {
+            let mut buf = Vec::new();
+            Reply::Error(format!("ERR {}", e)).write_to(&mut buf);
+            writer.write_all(&buf)?;
+            return Err(e.into());
+        }
+    };
+    let mut session = Session {
+        raft_address: raft_address.to_string(),
+        tls,
+        kv_client,
+        cursors: Cursors::new(),
+    };
+
+    loop {
+        let args = match read_command(&mut reader) {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                let mut buf = Vec::new();
+                Reply::Error(format!("ERR Protocol error: {}", e)).write_to(&mut buf);
+                writer.write_all(&buf)?;
+                return Err(e);
+            }
+        };
+        if args.is_empty() {
+            continue;
+        }
+
+        let reply = session.handle(&args);
+        let mut buf = Vec::new();
+        reply.write_to(&mut buf);
+        writer.write_all(&buf)?;
+
+        if args[0].eq_ignore_ascii_case(b"quit") {
+            return Ok(());
+        }
+    }
+}
+
+// Serves the Redis protocol (RESP2) on `address`. Each connection is handled by a thread with
+// its own clients for the node at `raft_address`.
+pub fn serve(address: &str, raft_address: &str, tls: Option<TlsConfig>) -> Result<(), Error> {
+    let listener = TcpListener::bind(address).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!(
+                "failed to start Redis server: address={}, error={}",
+                address, e
+            ),
+        )
+    })?;
+    info!("start Redis server on {}", address);
+
+    let raft_address = raft_address.to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("failed to accept connection: {:?}", e);
+                    continue;
+                }
+            };
+            let raft_address = raft_address.clone();
+            let tls = tls.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = handle_connection(stream, &raft_address, tls) {
+                    debug!("connection closed: peer={:?}, error={}", peer, e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &[u8]) -> Result<Option<Vec<Vec<u8>>>, Error> {
+        read_command(&mut BufReader::new(input))
+    }
+
+    fn args(args: &[&[u8]]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.to_vec()).collect()
+    }
+
+    #[test]
+    fn test_read_array() {
+        let command = read(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$4\r\na\r\nb\r\n").unwrap();
+        assert_eq!(command, Some(args(&[b"SET", b"key", b"a\r\nb"])));
+        assert_eq!(read(b"*1\r\n$0\r\n\r\n").unwrap(), Some(args(&[b""])));
+    }
+
+    #[test]
+    fn test_read_inline() {
+        assert_eq!(
+            read(b"GET  key\r\n").unwrap(),
+            Some(args(&[b"GET", b"key"]))
+        );
+        assert_eq!(read(b"PING\n").unwrap(), Some(args(&[b"PING"])));
+        assert_eq!(read(b"\r\n").unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_read_closed() {
+        assert_eq!(read(b"").unwrap(), None);
+        assert_eq!(read(b"*2\r\n$3\r\nGET\r\n").unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        assert!(read(b"*x\r\n").is_err());
+        assert!(read(b"*1\r\n+GET\r\n").is_err());
+        assert!(read(b"*1\r\n$-1\r\n").is_err());
+        assert!(read(format!("*1\r\n${}\r\n", MAX_BULK_LEN + 1).as_bytes()).is_err());
+        // The bulk string is shorter than its length.
+        assert!(read(b"*1\r\n$10\r\nGET\r\n").is_err());
+    }
+
+    #[test]
+    fn test_write_reply() {
+        let mut buf = Vec::new();
+        Reply::Array(vec![
+            Reply::Simple("OK"),
+            Reply::Error("ERR bad\r\nline".to_string()),
+            Reply::Integer(-1),
+            Reply::Bulk(Some(b"value".to_vec())),
+            Reply::Bulk(None),
+        ])
+        .write_to(&mut buf);
+        assert_eq!(
+            buf,
+            b"*5\r\n+OK\r\n-ERR bad  line\r\n:-1\r\n$5\r\nvalue\r\n$-1\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_cursors() {
+        let mut cursors = Cursors::new();
+        assert_eq!(cursors.get(b"0").unwrap(), None);
+
+        let keys: Vec<Vec<u8>> = vec![
+            b"user:12345".to_vec(),
+            b"\x00\x00key".to_vec(),
+            vec![0xff; 1024],
+        ];
+        let ids: Vec<u64> = keys.iter().map(|key| cursors.insert(key.clone())).collect();
+        for (id, key) in ids.iter().zip(&keys) {
+            assert_ne!(*id, 0);
+            // A cursor can be used more than once.
+            for _ in 0..2 {
+                assert_eq!(
+                    cursors.get(id.to_string().as_bytes()).unwrap(),
+                    Some(key.clone())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_cursor() {
+        let mut cursors = Cursors::new();
+        let id = cursors.insert(b"user:12345".to_vec());
+        assert!(cursors.get(b"-1").is_err());
+        assert!(cursors.get(b"abc").is_err());
+        assert!(cursors.get(b"18446744073709551616").is_err());
+        assert!(cursors.get((id + 1).to_string().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_cursor_eviction() {
+        let mut cursors = Cursors::new();
+        let first = cursors.insert(b"user:0".to_vec());
+        let mut last = first;
+        for i in 1..=MAX_CURSORS {
+            last = cursors.insert(format!("user:{}", i).into_bytes());
+        }
+        assert!(cursors.get(first.to_string().as_bytes()).is_err());
+        assert_eq!(
+            cursors.get(last.to_string().as_bytes()).unwrap(),
+            Some(format!("user:{}", MAX_CURSORS).into_bytes())
+        );
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"user:*", b"user:1"));
+        assert!(!glob_match(b"user:*", b"users"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-c]llo", b"hbllo"));
+        assert!(glob_match(b"h\\*llo", b"h*llo"));
+        assert!(!glob_match(b"h\\*llo", b"hello"));
+    }
+}
@@ -0,0 +1,52 @@
+use clap::ArgMatches;
+
+use meteora_server::tls::TlsConfig;
+
+pub fn tls_config(matches: &ArgMatches) -> Option<TlsConfig> {
+    let cert_file = matches.value_of("TLS_CERT");
+    let key_file = matches.value_of("TLS_KEY");
+    let ca_file = matches.value_of("TLS_CA");
+
+    if cert_file.is_none() && key_file.is_none() && ca_file.is_none() {
+        return None;
+    }
+
+    Some(TlsConfig::new(cert_file, key_file, ca_file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::{App, Arg};
+
+    fn matches(args: &[&str]) -> ArgMatches<'static> {
+        App::new("test")
+            .arg(
+                Arg::with_name("TLS_CERT")
+                    .long("tls-cert")
+                    .takes_value(true),
+            )
+            .arg(Arg::with_name("TLS_KEY").long("tls-key").takes_value(true))
+            .arg(Arg::with_name("TLS_CA").long("tls-ca").takes_value(true))
+            .get_matches_from(args.to_vec())
+    }
+
+    #[test]
+    fn test_no_tls() {
+        assert!(tls_config(&matches(&["test"])).is_none());
+    }
+
+    #[test]
+    fn test_tls() {
+        let tls = tls_config(&matches(&["test", "--tls-ca", "ca.pem"])).unwrap();
+        assert_eq!(tls.ca_file, Some("ca.pem".to_string()));
+        assert_eq!(tls.cert_file, None);
+
+        let args = ["test", "--tls-cert", "node.pem", "--tls-key", "node.key"];
+        let tls = tls_config(&matches(&args)).unwrap();
+        assert_eq!(tls.cert_file, Some("node.pem".to_string()));
+        assert_eq!(tls.key_file, Some("node.key".to_string()));
+        assert_eq!(tls.ca_file, None);
+    }
+}
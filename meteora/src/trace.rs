@@ -0,0 +1,13 @@
+use clap::ArgMatches;
+
+use meteora_server::trace::Tracing;
+
+pub fn init_tracing(
+    matches: &ArgMatches,
+    service_name: &str,
+) -> Result<Option<Tracing>, std::io::Error> {
+    match matches.value_of("OTLP_ENDPOINT") {
+        Some(endpoint) => Tracing::init(endpoint, service_name).map(Some),
+        None => Ok(None),
+    }
+}